@@ -0,0 +1,318 @@
+//! Accounts and authentication.
+//!
+//! Browsers authenticate with an HttpOnly session cookie set by `login`;
+//! scripts and the CLI send `Authorization: Bearer <api token>`. Only hashes
+//! of session ids and tokens are stored.
+
+use crate::{
+    clips::now_millis,
+    error::ApiError,
+    store::{ApiToken, User},
+    AppState,
+};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{AppendHeaders, IntoResponse},
+    Json,
+};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+pub const SESSION_COOKIE: &str = "synapse_session";
+const SESSION_TTL_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+const TOKEN_PREFIX: &str = "syn_";
+/// How often expired sessions are deleted.
+const SESSION_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Checked instead when the user does not exist or has no password, so a
+/// failed login takes as long whether or not the username exists. Nobody
+/// knows its password.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$0UBhzGFLZn+baU5D0mGnAw$qgFr0HdXR+CN3++vcqCtRM0FEznwim+voi9orQi4+iQ";
+
+#[async_trait]
+impl FromRequestParts<AppState> for User {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, ApiError> {
+        let now = now_millis();
+        if let Some(token) = bearer_token(&parts.headers) {
+            return state
+                .store
+                .api_token_user(&hash_secret(token), now)?
+                .ok_or(ApiError::Unauthorized);
+        }
+        if let Some(session) = cookie(&parts.headers, SESSION_COOKIE) {
+            return state
+                .store
+                .session_user(&hash_secret(session), now)?
+                .ok_or(ApiError::Unauthorized);
+        }
+        Err(ApiError::Unauthorized)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Credentials {
+    username: String,
+    password: String,
+    /// Registered device to bind the new session to.
+    #[serde(default)]
+    device_id: Option<String>,
+}
+
+/// Creates an account. Open when no account exists yet, afterwards only if
+/// registration is enabled in the config.
+pub async fn register(
+    State(state): State<AppState>,
+    Json(credentials): Json<Credentials>,
+) -> Result<impl IntoResponse, ApiError> {
+    if state.store.user_count()? > 0 && !state.config.allow_registration {
+        return Err(ApiError::Forbidden("registration is disabled".to_string()));
+    }
+    let username = credentials.username.trim();
+    if username.is_empty() {
+        return Err(ApiError::BadRequest(
+            "username must not be empty".to_string(),
+        ));
+    }
+    if credentials.password.len() < 8 {
+        return Err(ApiError::BadRequest(
+            "password must be at least 8 characters".to_string(),
+        ));
+    }
+    let taken = || ApiError::BadRequest("username is already taken".to_string());
+    if state.store.user_by_name(username)?.is_some() {
+        return Err(taken());
+    }
+
+    let password = credentials.password.clone();
+    let password_hash = tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))??;
+    // Another registration may have claimed the name while the hash was computed
+    let user = state
+        .store
+        .create_user(username, Some(&password_hash), now_millis())?
+        .ok_or_else(taken)?;
+    tracing::info!("registered user {}", user.username);
+    let cookie = start_session(&state, user.id, None)?;
+    Ok((StatusCode::CREATED, AppendHeaders([cookie]), Json(user)))
+}
+
+pub async fn login(
+    State(state): State<AppState>,
+    Json(credentials): Json<Credentials>,
+) -> Result<impl IntoResponse, ApiError> {
+    let account = state.store.user_by_name(credentials.username.trim())?;
+    let password_hash = account
+        .as_ref()
+        .and_then(|(_, hash)| hash.clone())
+        .unwrap_or_else(|| DUMMY_HASH.to_string());
+    // Argon2 takes tens of milliseconds of CPU; keep it off the async workers
+    let password = credentials.password.clone();
+    let verified = tokio::task::spawn_blocking(move || verify_password(&password, &password_hash))
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))??;
+    let user = match account {
+        Some((user, Some(_))) if verified => user,
+        _ => return Err(ApiError::Unauthorized),
+    };
+
+    // An unknown or revoked device is ignored; the client then registers anew
+    let device_id = match &credentials.device_id {
+        Some(id) => state
+            .store
+            .active_device(user.id, id)?
+            .map(|device| device.id),
+        None => None,
+    };
+    let cookie = start_session(&state, user.id, device_id.as_deref())?;
+    Ok((AppendHeaders([cookie]), Json(User { device_id, ..user })))
+}
+
+pub async fn logout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(session) = cookie(&headers, SESSION_COOKIE) {
+        state.store.delete_session(&hash_secret(session))?;
+    }
+    let expired = cookie_header(&state, SESSION_COOKIE, "", 0)?;
+    Ok((StatusCode::NO_CONTENT, AppendHeaders([expired])))
+}
+
+pub async fn me(user: User) -> Json<User> {
+    Json(user)
+}
+
+#[derive(Serialize)]
+pub struct Methods {
+    password: bool,
+    oidc: bool,
+}
+
+/// Tells the login page which sign-in options to offer.
+pub async fn methods(State(state): State<AppState>) -> Json<Methods> {
+    Json(Methods {
+        password: true,
+        oidc: state.oidc.is_some(),
+    })
+}
+
+pub async fn list_tokens(
+    State(state): State<AppState>,
+    user: User,
+) -> Result<Json<Vec<ApiToken>>, ApiError> {
+    Ok(Json(state.store.list_api_tokens(user.id)?))
+}
+
+#[derive(Deserialize)]
+pub struct NewToken {
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct CreatedToken {
+    #[serde(flatten)]
+    info: ApiToken,
+    token: String,
+}
+
+pub async fn create_token(
+    State(state): State<AppState>,
+    user: User,
+    Json(body): Json<NewToken>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::BadRequest(
+            "token name must not be empty".to_string(),
+        ));
+    }
+    let token = new_api_token();
+    let info =
+        state
+            .store
+            .create_api_token(user.id, name, &hash_secret(&token), now_millis(), None)?;
+    Ok((StatusCode::CREATED, Json(CreatedToken { info, token })))
+}
+
+pub async fn delete_token(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    if state.store.delete_api_token(user.id, id)? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+/// Opens a session for `user_id`, optionally bound to one of its devices, and
+/// returns the `Set-Cookie` header carrying it.
+pub fn start_session(
+    state: &AppState,
+    user_id: i64,
+    device_id: Option<&str>,
+) -> Result<(header::HeaderName, HeaderValue), ApiError> {
+    let session = random_secret();
+    state.store.create_session(
+        user_id,
+        &hash_secret(&session),
+        now_millis() + SESSION_TTL_MS,
+        device_id,
+    )?;
+    cookie_header(state, SESSION_COOKIE, &session, SESSION_TTL_MS / 1000)
+}
+
+/// A `Set-Cookie` header for an HttpOnly, SameSite=Lax cookie; a zero
+/// `max_age_secs` deletes it.
+pub fn cookie_header(
+    state: &AppState,
+    name: &str,
+    value: &str,
+    max_age_secs: i64,
+) -> Result<(header::HeaderName, HeaderValue), ApiError> {
+    let secure = if state.config.tls.is_some() {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie =
+        format!("{name}={value}; Path=/; HttpOnly; SameSite=Lax; Max-Age={max_age_secs}{secure}");
+    let value = HeaderValue::from_str(&cookie).map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok((header::SET_COOKIE, value))
+}
+
+pub fn hash_password(password: &str) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| ApiError::Internal(e.to_string()))
+}
+
+fn verify_password(password: &str, password_hash: &str) -> Result<bool, ApiError> {
+    let parsed = PasswordHash::new(password_hash).map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .is_ok())
+}
+
+/// Periodically deletes expired sessions.
+pub async fn expire_sessions(state: AppState) {
+    let mut interval = tokio::time::interval(SESSION_CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match state.store.delete_expired_sessions(now_millis()) {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("deleted {} expired sessions", count),
+            Err(e) => tracing::error!("failed to delete expired sessions: {}", e),
+        }
+    }
+}
+
+pub fn new_api_token() -> String {
+    format!("{TOKEN_PREFIX}{}", random_secret())
+}
+
+/// 256 bits of randomness, hex encoded.
+pub fn random_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
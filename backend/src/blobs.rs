@@ -0,0 +1,302 @@
+//! Content-addressed blob store.
+//!
+//! Binary payloads live in the data directory under their SHA-256
+//! (`blobs/<hash>`) instead of in the database: the images of image clips,
+//! which keep only the hash in `blob`, and the files listed by file clips.
+//! Identical payloads are stored once. `clip_blobs` records which clips
+//! reference which blob and `blobs.refs` counts them; a periodic sweep deletes
+//! the blobs no clip references, sparing those added within the last day so
+//! a finished upload has time to be listed in a clip. A user may read the
+//! blobs they added. Encrypted images stay inline, as the server cannot
+//! decode them.
+
+use crate::{
+    auth::random_secret,
+    capture,
+    clips::{now_millis, ClipboardItem},
+    error::ApiError,
+    store::User,
+    AppState,
+};
+use axum::{
+    body::Body,
+    extract::{Path as UrlPath, Query, Request, State},
+    http::{header, HeaderValue},
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tower_http::services::ServeFile;
+
+/// Unreferenced blobs are kept this long after they were last added, and
+/// files written out for pasting this long after the last paste.
+const GRACE_MS: i64 = 24 * 60 * 60 * 1000;
+const GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Blobs are served with this policy, so HTML or SVG opened directly from
+/// `/api/blobs` cannot run scripts or load anything.
+const BLOB_CSP: &str = "default-src 'none'; img-src 'self'; style-src 'unsafe-inline'; sandbox";
+
+#[derive(Deserialize)]
+pub struct BlobParams {
+    /// File name for `Content-Disposition`, and for `Content-Type` when the
+    /// blob has none.
+    name: Option<String>,
+}
+
+/// Serves a blob of the user with its `Content-Type`, and with range requests
+/// so interrupted downloads can resume.
+pub async fn get_blob(
+    State(state): State<AppState>,
+    user: User,
+    UrlPath(hash): UrlPath<String>,
+    Query(params): Query<BlobParams>,
+    request: Request,
+) -> Result<Response, ApiError> {
+    let hash = hash.to_ascii_lowercase();
+    if !is_hash(&hash) {
+        return Err(ApiError::NotFound);
+    }
+    let blob = state
+        .store
+        .blob(user.id, &hash)?
+        .ok_or(ApiError::NotFound)?;
+    let name = params.name.as_deref().and_then(file_name);
+    let mime = match (&blob.mime_type, name) {
+        (Some(mime), _) => mime
+            .parse()
+            .unwrap_or(mime_guess::mime::APPLICATION_OCTET_STREAM),
+        (None, Some(name)) => mime_guess::from_path(name).first_or_octet_stream(),
+        (None, None) => mime_guess::mime::APPLICATION_OCTET_STREAM,
+    };
+    let mut response = ServeFile::new_with_mime(blob_path(&state.config.data_dir, &hash), &mime)
+        .try_call(request)
+        .await
+        .map_err(internal)?;
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(BLOB_CSP),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    // Contents never change under a hash
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, max-age=31536000, immutable"),
+    );
+    if let Some(name) = name {
+        // RFC 6266 filename*, percent-encoding everything outside a safe set
+        let encoded: String = name
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{b:02X}"),
+            })
+            .collect();
+        if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename*=UTF-8''{encoded}"))
+        {
+            headers.insert(header::CONTENT_DISPOSITION, value);
+        }
+    }
+    Ok(response.map(Body::new))
+}
+
+/// Moves the payload of an unencrypted image clip into the blob store and
+/// leaves a reference in `blob`. Clients either send the image as base64 in
+/// `content`, or upload it first and name it in `blob`.
+pub fn attach_image(
+    state: &AppState,
+    user_id: i64,
+    item: &mut ClipboardItem,
+) -> Result<(), ApiError> {
+    let mime = item
+        .mime_type
+        .get_or_insert_with(|| "image/png".to_string())
+        .clone();
+    if !mime.starts_with("image/") {
+        return Err(ApiError::BadRequest(format!(
+            "image clips cannot have MIME type {mime}"
+        )));
+    }
+    let (hash, size) = match item.blob.take() {
+        Some(hash) => {
+            let hash = hash.to_ascii_lowercase();
+            let blob = state.store.blob(user_id, &hash)?.ok_or_else(|| {
+                ApiError::BadRequest(format!("blob {hash} has not been uploaded"))
+            })?;
+            (hash, blob.size)
+        }
+        None => {
+            // Accept data URLs as well as bare base64
+            let data = match item.content.split_once(";base64,") {
+                Some((_, data)) => data,
+                None => &item.content,
+            };
+            let bytes = STANDARD.decode(data.trim()).map_err(|_| {
+                ApiError::BadRequest("image content must be base64 encoded".to_string())
+            })?;
+            let hash = put(state, &bytes).map_err(internal)?;
+            (hash, bytes.len() as u64)
+        }
+    };
+    state
+        .store
+        .add_blob(user_id, &hash, size, Some(&mime), now_millis())?;
+    item.content.clear();
+    item.size = Some(size as usize);
+    item.blob = Some(hash);
+    Ok(())
+}
+
+/// Writes `bytes` to the store and returns their hash. The caller records the
+/// blob with `Store::add_blob`.
+pub fn put(state: &AppState, bytes: &[u8]) -> io::Result<String> {
+    let hash = hex::encode(Sha256::digest(bytes));
+    if is_stored(state, &hash)? {
+        return Ok(hash);
+    }
+    let data_dir = &state.config.data_dir;
+    let temp = temp_path(data_dir);
+    fs::create_dir_all(temp.parent().unwrap())?;
+    fs::write(&temp, bytes)?;
+    let target = blob_path(data_dir, &hash);
+    fs::create_dir_all(target.parent().unwrap())?;
+    fs::rename(temp, target)?;
+    Ok(hash)
+}
+
+/// Periodically deletes blobs no clip references any more, and the files
+/// host capture wrote out for pasting.
+pub async fn collect_garbage(state: AppState) {
+    let mut interval = tokio::time::interval(GC_INTERVAL);
+    loop {
+        interval.tick().await;
+        let deleted = state
+            .store
+            .delete_unreferenced_blobs(now_millis() - GRACE_MS, |hash| {
+                let _ = fs::remove_file(blob_path(&state.config.data_dir, hash));
+            });
+        match deleted {
+            Ok(0) => {}
+            Ok(deleted) => tracing::info!("deleted {} unreferenced blobs", deleted),
+            Err(e) => tracing::error!("failed to delete unreferenced blobs: {}", e),
+        }
+        let cutoff = SystemTime::now() - Duration::from_millis(GRACE_MS as u64);
+        match capture::sweep_received(&state.config.data_dir, cutoff) {
+            Ok(0) => {}
+            Ok(deleted) => tracing::info!("deleted the pasted files of {} clips", deleted),
+            Err(e) => tracing::error!("failed to delete pasted files: {}", e),
+        }
+    }
+}
+
+/// Brings data written before the blob store up to date: moves uploaded
+/// files from `files/` and inline images out of the database.
+pub fn migrate(state: &AppState) {
+    let data_dir = &state.config.data_dir;
+    let old = data_dir.join("files");
+    if old.is_dir() && !data_dir.join("blobs").exists() {
+        if let Err(e) = fs::rename(&old, data_dir.join("blobs")) {
+            tracing::error!("failed to move {} to the blob store: {}", old.display(), e);
+        }
+    }
+
+    let mut after = 0;
+    let mut moved = 0;
+    loop {
+        let image = match state.store.next_inline_image(after) {
+            Ok(Some(image)) => image,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::error!("failed to read inline images: {}", e);
+                break;
+            }
+        };
+        after = image.id;
+        let data = match image.content.split_once(";base64,") {
+            Some((_, data)) => data,
+            None => &image.content,
+        };
+        let Ok(bytes) = STANDARD.decode(data.trim()) else {
+            tracing::warn!("image clip {} is not valid base64, left inline", image.id);
+            continue;
+        };
+        let result = put(state, &bytes)
+            .map_err(|e| e.to_string())
+            .and_then(|hash| {
+                state
+                    .store
+                    .move_to_blob(&image, &hash, bytes.len() as u64, now_millis())
+                    .map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(()) => moved += 1,
+            Err(e) => tracing::error!("failed to move image clip {} to a blob: {}", image.id, e),
+        }
+    }
+    if moved > 0 {
+        tracing::info!("moved {} inline images to the blob store", moved);
+    }
+}
+
+/// Where the blob with `hash` is stored.
+pub fn blob_path(data_dir: &Path, hash: &str) -> PathBuf {
+    data_dir.join("blobs").join(hash)
+}
+
+/// A fresh path next to the store for writing a blob before it is verified.
+pub fn temp_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("uploads").join(&random_secret()[..32])
+}
+
+/// SHA-256 and length of a file.
+pub fn hash_file(path: &Path) -> io::Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+/// Moves a verified file into place, unless the same contents are already there.
+pub fn store_file(state: &AppState, source: &Path, hash: &str) -> io::Result<()> {
+    if is_stored(state, hash)? {
+        return fs::remove_file(source);
+    }
+    let target = blob_path(&state.config.data_dir, hash);
+    fs::create_dir_all(target.parent().unwrap())?;
+    fs::rename(source, target)
+}
+
+/// Whether the bytes of `hash` are in place and stay there for now. Bytes
+/// are only trusted while their blob is recorded: without a row the sweep may
+/// be about to remove them, so they are written again. A recorded blob is
+/// marked as just added, which keeps the sweep off it until it is referenced.
+fn is_stored(state: &AppState, hash: &str) -> io::Result<bool> {
+    let recorded = state
+        .store
+        .keep_blob(hash, now_millis())
+        .map_err(io::Error::other)?;
+    Ok(recorded && blob_path(&state.config.data_dir, hash).exists())
+}
+
+pub fn is_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn file_name(name: &str) -> Option<&str> {
+    let name = name.rsplit(['/', '\\']).next()?;
+    (!name.is_empty() && name != "." && name != "..").then_some(name)
+}
+
+pub fn internal(e: io::Error) -> ApiError {
+    ApiError::Internal(e.to_string())
+}
@@ -0,0 +1,543 @@
+//! Opt-in capture of the host clipboard.
+//!
+//! A dedicated thread owns the `arboard` clipboard: it polls for changes,
+//! records new text, images and copied files into the history store, and
+//! serves requests to write a history item back to the host clipboard.
+
+use crate::{
+    blobs,
+    clips::{now_millis, ClipboardItem},
+    files::{self, FileEntry},
+    thumbnails, AppState,
+};
+use arboard::{Clipboard, ImageData};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use image::{ImageFormat, ImageReader, RgbaImage};
+use std::{
+    borrow::Cow,
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, SystemTime},
+};
+use synapse_sensitive::{hints, Verdict};
+use tokio::sync::oneshot;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+struct WriteRequest {
+    item: ClipboardItem,
+    reply: oneshot::Sender<Result<(), String>>,
+}
+
+pub enum CaptureError {
+    /// The capture thread is not running, e.g. no display was available.
+    Stopped,
+    Write(String),
+}
+
+/// Handle to the capture thread, stored in `AppState` when capture is enabled.
+#[derive(Clone)]
+pub struct CaptureHandle {
+    requests: mpsc::Sender<WriteRequest>,
+}
+
+impl CaptureHandle {
+    /// Puts `item` on the host clipboard without recording it as a new clip.
+    pub async fn write(&self, item: ClipboardItem) -> Result<(), CaptureError> {
+        let (reply, response) = oneshot::channel();
+        self.requests
+            .send(WriteRequest { item, reply })
+            .map_err(|_| CaptureError::Stopped)?;
+        response
+            .await
+            .map_err(|_| CaptureError::Stopped)?
+            .map_err(CaptureError::Write)
+    }
+}
+
+/// Spawns the capture thread. History changes are written through `state`.
+pub fn spawn(state: AppState) -> CaptureHandle {
+    let (requests, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name("clipboard-capture".to_string())
+        .spawn(move || run(state, receiver))
+        .expect("failed to spawn clipboard capture thread");
+    CaptureHandle { requests }
+}
+
+fn run(state: AppState, requests: mpsc::Receiver<WriteRequest>) {
+    let mut clipboard = match Clipboard::new() {
+        Ok(clipboard) => clipboard,
+        Err(e) => {
+            tracing::error!("host clipboard capture disabled: {}", e);
+            return;
+        }
+    };
+    let device = whoami::fallible::hostname().ok();
+    let username = Some(whoami::username());
+    tracing::info!("host clipboard capture started");
+
+    // Whatever is on the clipboard at startup was already there, don't record it
+    let mut last = read(&mut clipboard).map(|content| content.fingerprint());
+
+    loop {
+        match requests.recv_timeout(POLL_INTERVAL) {
+            Ok(request) => {
+                let result = write(&mut clipboard, &request.item, &state.config.data_dir);
+                if result.is_ok() {
+                    // Skip the change we just made ourselves
+                    last = read(&mut clipboard).map(|content| content.fingerprint());
+                }
+                let _ = request.reply.send(result);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let Some(content) = read(&mut clipboard) else {
+                    continue;
+                };
+                let fingerprint = content.fingerprint();
+                if last == Some(fingerprint) {
+                    continue;
+                }
+                last = Some(fingerprint);
+
+                let source = hints::source_app();
+                if let Some(source) = &source {
+                    if state.config.sensitive.excludes(source) {
+                        tracing::info!("skipped host clip from excluded {}", source.name);
+                        continue;
+                    }
+                }
+                let concealed = hints::concealed();
+                let Some(user_id) = capture_user_id(&state) else {
+                    continue;
+                };
+                let text = match &content {
+                    HostContent::Text(text) => Some(text.as_str()),
+                    _ => None,
+                };
+                // Screen before storing anything, so skipped images and files
+                // leave no blobs behind
+                let Some(verdict) = screen(&state, text, concealed.as_deref()) else {
+                    continue;
+                };
+                let item = match content {
+                    HostContent::Text(text) => text_item(text),
+                    HostContent::Image(image) => store_image(&state, user_id, image),
+                    HostContent::Files(paths) => import_files(&state, user_id, &paths),
+                };
+                let Some(mut item) = item else {
+                    continue;
+                };
+                item.source_app = source.map(|source| source.name);
+                redact(&mut item, verdict);
+                item.device = device.clone();
+                item.username = username.clone();
+                match state.store.insert(user_id, item) {
+                    Ok((clip, event)) => {
+                        tracing::debug!("captured host clip {}", clip.id);
+                        state.publish(event);
+                    }
+                    Err(e) => tracing::error!("failed to record host clip: {}", e),
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+/// Applies the `[sensitive]` rules to captured text (`None` for images and
+/// files), and the `concealed` action to clips a password manager marked with
+/// `concealed`. Returns `None` when the clip must not be recorded.
+fn screen(state: &AppState, text: Option<&str>, concealed: Option<&str>) -> Option<Verdict> {
+    let scanner = &state.config.sensitive;
+    let verdict = match (concealed, text) {
+        (Some(hint), text) => {
+            tracing::debug!("host clipboard carries concealment hint {}", hint);
+            scanner.conceal(text)
+        }
+        (None, Some(text)) => scanner.scan(text),
+        (None, None) => Verdict::default(),
+    };
+    if verdict.is_clean() {
+        return Some(verdict);
+    }
+    if verdict.skip {
+        tracing::info!(
+            "skipped host clip matching {}",
+            verdict.detectors.join(", ")
+        );
+        return None;
+    }
+    tracing::info!(
+        "redacted host clip matching {}",
+        verdict.detectors.join(", ")
+    );
+    Some(verdict)
+}
+
+/// Masks or sets the expiry of a clip as `screen` decided.
+fn redact(item: &mut ClipboardItem, verdict: Verdict) {
+    if let Some(masked) = verdict.masked {
+        item.size = Some(masked.len());
+        item.content = masked;
+    }
+    if let Some(after) = verdict.expire_after {
+        item.expires_at = Some(now_millis() + after.as_millis() as i64);
+    }
+}
+
+/// Captured clips belong to the configured capture user.
+fn capture_user_id(state: &AppState) -> Option<i64> {
+    let Some(username) = state.config.capture_user.as_deref() else {
+        tracing::warn!("no capture user configured, dropping host clip");
+        return None;
+    };
+    match state.store.user_by_name(username) {
+        Ok(Some((user, _))) => Some(user.id),
+        Ok(None) => {
+            tracing::warn!(
+                "capture user {} does not exist, dropping host clip",
+                username
+            );
+            None
+        }
+        Err(e) => {
+            tracing::error!("failed to look up capture user: {}", e);
+            None
+        }
+    }
+}
+
+enum HostContent {
+    Text(String),
+    Image(ImageData<'static>),
+    /// Files and folders copied in a file manager.
+    Files(Vec<PathBuf>),
+}
+
+impl HostContent {
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        match self {
+            HostContent::Text(text) => text.hash(&mut hasher),
+            HostContent::Image(image) => image.bytes.hash(&mut hasher),
+            HostContent::Files(paths) => paths.hash(&mut hasher),
+        }
+        hasher.finish()
+    }
+}
+
+// Copied files take precedence over the icons and names that file managers
+// put next to them, and images over text, matching the Tauri capture glue
+fn read(clipboard: &mut Clipboard) -> Option<HostContent> {
+    if let Ok(paths) = clipboard.get().file_list() {
+        if !paths.is_empty() {
+            return Some(HostContent::Files(paths));
+        }
+    }
+    if let Ok(image) = clipboard.get_image() {
+        return Some(HostContent::Image(image.to_owned_img()));
+    }
+    clipboard.get_text().ok().map(HostContent::Text)
+}
+
+fn write(clipboard: &mut Clipboard, item: &ClipboardItem, data_dir: &Path) -> Result<(), String> {
+    match item.item_type.as_str() {
+        "image" => {
+            let bytes = match &item.blob {
+                Some(hash) => fs::read(blobs::blob_path(data_dir, hash))
+                    .map_err(|e| format!("failed to read the image: {e}"))?,
+                None => {
+                    // Stored images may carry a `data:image/png;base64,` prefix
+                    let content = &item.content;
+                    let data = content
+                        .split_once(',')
+                        .map_or(content.as_str(), |(_, data)| data);
+                    STANDARD.decode(data.trim()).map_err(|e| e.to_string())?
+                }
+            };
+            let image = decode_image(&bytes)?;
+            clipboard.set_image(image).map_err(|e| e.to_string())
+        }
+        "file" => {
+            // Pasting needs real files; write them out once per distinct clip,
+            // afresh so the sweep counts the day from the last paste
+            let hash = item.content_hash().unwrap_or_default();
+            let dir = data_dir.join("received").join(&hash[..hash.len().min(16)]);
+            let _ = fs::remove_dir_all(&dir);
+            let paths = files::materialize(data_dir, &item.content, &dir)
+                .map_err(|e| format!("failed to write the files of the clip: {e}"))?;
+            clipboard.set().file_list(&paths).map_err(|e| e.to_string())
+        }
+        _ => clipboard
+            .set_text(item.content.clone())
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Deletes the files written out for pasting (`received/` in the data
+/// directory) that were last written before `cutoff`, returning how many
+/// clips' files were deleted.
+pub fn sweep_received(data_dir: &Path, cutoff: SystemTime) -> io::Result<usize> {
+    let entries = match fs::read_dir(data_dir.join("received")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut deleted = 0;
+    for entry in entries {
+        let entry = entry?;
+        if entry.metadata()?.modified()? < cutoff {
+            fs::remove_dir_all(entry.path())?;
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+fn encode_png(image: ImageData) -> Result<Vec<u8>, String> {
+    let rgba = RgbaImage::from_raw(
+        image.width as u32,
+        image.height as u32,
+        image.bytes.into_owned(),
+    )
+    .ok_or_else(|| "image buffer does not match its dimensions".to_string())?;
+    let mut png = Vec::new();
+    rgba.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+fn decode_image(bytes: &[u8]) -> Result<ImageData<'static>, String> {
+    let rgba = thumbnails::decode(ImageReader::new(Cursor::new(bytes)))?.into_rgba8();
+    Ok(ImageData {
+        width: rgba.width() as usize,
+        height: rgba.height() as usize,
+        bytes: Cow::Owned(rgba.into_raw()),
+    })
+}
+
+fn text_item(text: String) -> Option<ClipboardItem> {
+    if text.trim().is_empty() {
+        return None;
+    }
+    Some(host_item("text", text.len(), text, Some("text/plain")))
+}
+
+/// Encodes a host clipboard image as PNG into the blob store.
+fn store_image(state: &AppState, user_id: i64, image: ImageData) -> Option<ClipboardItem> {
+    let png = encode_png(image)
+        .map_err(|e| tracing::warn!("failed to encode host clipboard image: {}", e))
+        .ok()?;
+    let hash = blobs::put(state, &png)
+        .map_err(|e| tracing::error!("failed to store host clipboard image: {}", e))
+        .ok()?;
+    let size = png.len();
+    if let Err(e) =
+        state
+            .store
+            .add_blob(user_id, &hash, size as u64, Some("image/png"), now_millis())
+    {
+        tracing::error!("failed to record host clipboard image: {}", e);
+        return None;
+    }
+    let mut item = host_item("image", size, String::new(), Some("image/png"));
+    item.blob = Some(hash);
+    thumbnails::attach(state, user_id, &mut item);
+    Some(item)
+}
+
+/// Copies the files on the host clipboard, and everything inside copied
+/// folders, into the file store and lists them in a file clip. Copies over
+/// the `[files]` limits are not recorded.
+fn import_files(state: &AppState, user_id: i64, paths: &[PathBuf]) -> Option<ClipboardItem> {
+    let limits = &state.config.files;
+    let mut found = Vec::new();
+    for path in paths {
+        let name = path.file_name()?.to_string_lossy().into_owned();
+        // The copied items themselves may be links; only those inside copied
+        // folders are skipped
+        let collected = fs::canonicalize(path)
+            .and_then(|path| collect_files(&path, name, limits.max_files, &mut found));
+        if let Err(e) = collected {
+            tracing::warn!("skipped copied files: {}", e);
+            return None;
+        }
+    }
+    let total: u64 = found.iter().map(|(_, _, size)| size).sum();
+    if found.is_empty()
+        || found
+            .iter()
+            .any(|(_, _, size)| *size > limits.max_file_size)
+        || total > limits.max_clip_size
+    {
+        tracing::info!(
+            "skipped {} copied files ({} bytes) outside the [files] limits",
+            found.len(),
+            total
+        );
+        return None;
+    }
+
+    let mut entries = Vec::new();
+    for (relative, path, _) in found {
+        let (hash, size) = match files::import_file(state, &path) {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::warn!("failed to store copied file {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        if let Err(e) = state
+            .store
+            .add_blob(user_id, &hash, size, None, now_millis())
+        {
+            tracing::error!("failed to record copied file: {}", e);
+            return None;
+        }
+        entries.push(FileEntry {
+            path: relative,
+            size,
+            hash,
+        });
+    }
+    let content = serde_json::to_string(&entries).ok()?;
+    Some(host_item("file", total as usize, content, None))
+}
+
+fn host_item(
+    item_type: &str,
+    size: usize,
+    content: String,
+    mime_type: Option<&str>,
+) -> ClipboardItem {
+    ClipboardItem {
+        item_type: item_type.to_string(),
+        size: Some(size),
+        content,
+        mime_type: mime_type.map(str::to_string),
+        timestamp: Some(now_millis()),
+        device: None,
+        device_id: None,
+        username: None,
+        key_version: None,
+        expires_at: None,
+        source_app: None,
+        pinned: false,
+        favorite: false,
+        collection: None,
+        copy_count: 1,
+        blob: None,
+        preview: None,
+    }
+}
+
+/// Lists the regular files at `path` with their `/`-separated paths relative
+/// to the copied item, giving up past `max_files`. Symbolic links are not
+/// followed, so a folder cannot pull in files from outside itself or loop.
+fn collect_files(
+    path: &Path,
+    relative: String,
+    max_files: usize,
+    found: &mut Vec<(String, PathBuf, u64)>,
+) -> io::Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_symlink() {
+        tracing::debug!("skipped symbolic link {}", path.display());
+    } else if meta.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            collect_files(
+                &entry.path(),
+                format!("{relative}/{name}"),
+                max_files,
+                found,
+            )?;
+        }
+    } else if meta.is_file() {
+        if found.len() == max_files {
+            return Err(io::Error::other(format!("more than {max_files} files")));
+        }
+        found.push((relative, path.to_path_buf(), meta.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("synapse-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_inside_copied_folders_are_skipped() {
+        let dir = temp_dir("links");
+        let outside = dir.join("outside.txt");
+        fs::write(&outside, "secret").unwrap();
+        let copied = dir.join("copied");
+        fs::create_dir_all(copied.join("sub")).unwrap();
+        fs::write(copied.join("sub/a.txt"), "abc").unwrap();
+        std::os::unix::fs::symlink(&outside, copied.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(&copied, copied.join("sub/loop")).unwrap();
+
+        let mut found = Vec::new();
+        collect_files(&copied, "copied".to_string(), 10, &mut found).unwrap();
+        let listed: Vec<_> = found
+            .iter()
+            .map(|(path, _, size)| (path.as_str(), *size))
+            .collect();
+        assert_eq!(listed, [("copied/sub/a.txt", 3)]);
+
+        // A copied link is resolved by `import_files`, not by `collect_files`
+        let mut found = Vec::new();
+        collect_files(
+            &copied.join("link.txt"),
+            "link.txt".to_string(),
+            10,
+            &mut found,
+        )
+        .unwrap();
+        assert!(found.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_limit_is_enforced() {
+        let dir = temp_dir("limit");
+        for name in ["a", "b", "c"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let mut found = Vec::new();
+        assert!(collect_files(&dir, "dir".to_string(), 2, &mut found).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn old_pasted_files_are_swept() {
+        let dir = temp_dir("received");
+        assert_eq!(sweep_received(&dir, SystemTime::now()).unwrap(), 0);
+        let received = dir.join("received");
+        fs::create_dir_all(received.join("0123456789abcdef/folder")).unwrap();
+        fs::write(received.join("0123456789abcdef/folder/a.txt"), "a").unwrap();
+
+        let past = SystemTime::now() - Duration::from_secs(60);
+        assert_eq!(sweep_received(&dir, past).unwrap(), 0);
+        assert!(received.join("0123456789abcdef").exists());
+        let future = SystemTime::now() + Duration::from_secs(60);
+        assert_eq!(sweep_received(&dir, future).unwrap(), 1);
+        assert!(!received.join("0123456789abcdef").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
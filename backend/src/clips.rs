@@ -0,0 +1,469 @@
+use crate::{
+    blobs,
+    capture::CaptureError,
+    error::ApiError,
+    events::EventKind,
+    files::{self, FileEntry},
+    store::{Collection, Search, User},
+    thumbnails::{self, ImagePreview},
+    AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use synapse_sensitive::html;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+const ITEM_TYPES: &[&str] = &["text", "image", "html", "file"];
+const MAX_COLLECTION_LEN: usize = 64;
+/// How often clips past their `expires_at` are deleted.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// Same JSON shape as the frontend's `ClipboardItem`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClipboardItem {
+    #[serde(rename = "type")]
+    pub item_type: String,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    // 元数据
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>, // Unix 时间戳（毫秒）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    // 注册设备的 id，由服务端根据认证信息填写
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>, // 内容大小（字节）
+    // 端到端加密：content 为密文时对应的用户密钥版本
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_version: Option<i64>,
+    // 自动过期时间（毫秒），到期后从历史中删除
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    // 复制来源的应用名（平台支持时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_app: Option<String>,
+    // 置顶、收藏和归入集合的条目不受保留策略影响
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub favorite: bool,
+    // 所属的片段集合名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    // 被复制的次数，重复复制相同内容只会累加次数
+    #[serde(default = "first_copy")]
+    pub copy_count: u32,
+    // 图片保存在 blob 存储中时的 SHA-256，内容从 /api/blobs/{blob} 读取，content 为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+    // 服务端为 blob 图片生成的缩略图、blurhash 占位图和原图尺寸，客户端不能设置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<ImagePreview>,
+}
+
+fn first_copy() -> u32 {
+    1
+}
+
+impl ClipboardItem {
+    /// SHA-256 of the content that identifies repeated copies: the text
+    /// itself, or the bytes of an image, which is also its blob's hash.
+    /// Encrypted clips and images that are not valid base64 have none.
+    pub fn content_hash(&self) -> Option<String> {
+        if self.key_version.is_some() {
+            return None;
+        }
+        if let Some(blob) = &self.blob {
+            return Some(blob.clone());
+        }
+        let digest = match self.item_type.as_str() {
+            "image" => {
+                // Accept data URLs as well as bare base64
+                let data = match self.content.split_once(";base64,") {
+                    Some((_, data)) => data,
+                    None => &self.content,
+                };
+                Sha256::digest(STANDARD.decode(data.trim()).ok()?)
+            }
+            _ => Sha256::digest(self.content.as_bytes()),
+        };
+        Some(hex::encode(digest))
+    }
+
+    /// The text an unencrypted HTML clip is searched by, without its markup.
+    /// Text clips are searched by their content and encrypted ones not at all.
+    pub fn search_text(&self) -> Option<String> {
+        (self.item_type == "html" && self.key_version.is_none())
+            .then(|| html::to_text(&self.content))
+    }
+
+    /// Hashes of the blobs the clip references: its image and thumbnail, or
+    /// the files it lists.
+    pub fn blob_refs(&self) -> Vec<String> {
+        if self.item_type == "file" {
+            let entries: Vec<FileEntry> = serde_json::from_str(&self.content).unwrap_or_default();
+            return entries
+                .into_iter()
+                .map(|entry| entry.hash.to_ascii_lowercase())
+                .collect();
+        }
+        self.blob
+            .iter()
+            .chain(self.preview.as_ref().and_then(|p| p.thumbnail.as_ref()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// A stored history entry: the item plus its server-assigned id.
+#[derive(Clone, Debug, Serialize)]
+pub struct Clip {
+    pub id: i64,
+    #[serde(flatten)]
+    pub item: ClipboardItem,
+}
+
+#[derive(Deserialize)]
+pub struct ListParams {
+    offset: Option<usize>,
+    limit: Option<usize>,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    favorite: bool,
+    collection: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    #[serde(default)]
+    q: String,
+    #[serde(rename = "type")]
+    item_type: Option<String>,
+    device: Option<String>,
+    username: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+/// Narrows a listing down to pinned or favorite clips, or one collection.
+pub struct ClipFilter {
+    pub pinned: bool,
+    pub favorite: bool,
+    pub collection: Option<String>,
+}
+
+/// Fields a client may change on an existing clip; omitted ones are kept.
+#[derive(Deserialize)]
+pub struct ClipUpdate {
+    pub pinned: Option<bool>,
+    pub favorite: Option<bool>,
+    /// Moves the clip into this collection; an empty name takes it out.
+    pub collection: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ClipPage {
+    items: Vec<Clip>,
+    total: usize,
+    offset: usize,
+    limit: usize,
+}
+
+pub async fn list_clips(
+    State(state): State<AppState>,
+    user: User,
+    Query(params): Query<ListParams>,
+) -> Result<Json<ClipPage>, ApiError> {
+    let offset = params.offset.unwrap_or(0);
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let filter = ClipFilter {
+        pinned: params.pinned,
+        favorite: params.favorite,
+        collection: params.collection,
+    };
+    let (items, total) = state.store.list(user.id, &filter, offset, limit)?;
+    Ok(Json(ClipPage {
+        items,
+        total,
+        offset,
+        limit,
+    }))
+}
+
+pub async fn get_clip(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<i64>,
+) -> Result<Json<Clip>, ApiError> {
+    state
+        .store
+        .get(user.id, id)?
+        .map(Json)
+        .ok_or(ApiError::NotFound)
+}
+
+pub async fn create_clip(
+    State(state): State<AppState>,
+    user: User,
+    Json(mut item): Json<ClipboardItem>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !ITEM_TYPES.contains(&item.item_type.as_str()) {
+        return Err(ApiError::BadRequest(format!(
+            "unsupported item type: {}",
+            item.item_type
+        )));
+    }
+    if item.content.trim().is_empty() && item.blob.is_none() {
+        return Err(ApiError::BadRequest(
+            "content must not be empty".to_string(),
+        ));
+    }
+    if item.blob.is_some() && (item.item_type != "image" || item.key_version.is_some()) {
+        return Err(ApiError::BadRequest(
+            "only unencrypted image clips can reference a blob".to_string(),
+        ));
+    }
+
+    // Encrypted clips must use the current key so revoked devices cannot read them
+    if let Some(version) = item.key_version {
+        let current = state.store.current_key_version(user.id)?;
+        if current != Some(version) {
+            return Err(ApiError::Conflict(format!(
+                "clip is encrypted with key version {version}, but the current version is {}",
+                current.map_or("none".to_string(), |v| v.to_string())
+            )));
+        }
+    }
+
+    // File clips list files uploaded beforehand; the server must be able to read that list
+    if item.item_type == "file" {
+        if item.key_version.is_some() {
+            return Err(ApiError::BadRequest(
+                "file clips cannot be encrypted".to_string(),
+            ));
+        }
+        item.size = Some(files::check_clip(&state, user.id, &item.content)? as usize);
+    }
+    // Unencrypted images move to the blob store; the clip keeps their hash
+    item.preview = None;
+    if item.item_type == "image" && item.key_version.is_none() {
+        blobs::attach_image(&state, user.id, &mut item)?;
+        // Decoding and scaling a large image takes a while
+        let (state, user_id) = (state.clone(), user.id);
+        item = tokio::task::spawn_blocking(move || {
+            thumbnails::attach(&state, user_id, &mut item);
+            item
+        })
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+    }
+
+    item.timestamp.get_or_insert_with(now_millis);
+    // Retention limits add up sizes, so never take the client's word for a
+    // size the server can measure; images and files got theirs above
+    match item.item_type.as_str() {
+        "text" | "html" if item.key_version.is_none() => item.size = Some(item.content.len()),
+        _ => {
+            item.size.get_or_insert(item.content.len());
+        }
+    }
+    item.copy_count = 1;
+    // Attribution comes from the authenticated device, never from the client
+    item.device_id = None;
+    if let Some(device_id) = &user.device_id {
+        if let Some(device) = state.store.active_device(user.id, device_id)? {
+            item.device = Some(device.name);
+            item.device_id = Some(device.id);
+        }
+    }
+
+    let (clip, event) = state.store.insert(user.id, item)?;
+    // A repeated copy updates the clip that already holds the content
+    let status = match event.kind {
+        EventKind::Updated { .. } => StatusCode::OK,
+        _ => StatusCode::CREATED,
+    };
+    state.publish(event);
+    Ok((status, Json(clip)))
+}
+
+pub async fn update_clip(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<i64>,
+    Json(mut update): Json<ClipUpdate>,
+) -> Result<Json<Clip>, ApiError> {
+    if let Some(collection) = &mut update.collection {
+        *collection = collection.trim().to_string();
+        if collection.chars().count() > MAX_COLLECTION_LEN {
+            return Err(ApiError::BadRequest(format!(
+                "collection names are limited to {MAX_COLLECTION_LEN} characters"
+            )));
+        }
+    }
+    let (clip, event) = state
+        .store
+        .update(user.id, id, &update)?
+        .ok_or(ApiError::NotFound)?;
+    state.publish(event);
+    Ok(Json(clip))
+}
+
+/// Full-text search with optional type, device, username and time filters.
+pub async fn search_clips(
+    State(state): State<AppState>,
+    user: User,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<ClipPage>, ApiError> {
+    let offset = params.offset.unwrap_or(0);
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let search = Search {
+        query: params.q,
+        item_type: params.item_type,
+        device: params.device,
+        username: params.username,
+        from: params.from,
+        to: params.to,
+    };
+    let (items, total) = state.store.search(user.id, &search, offset, limit)?;
+    Ok(Json(ClipPage {
+        items,
+        total,
+        offset,
+        limit,
+    }))
+}
+
+/// Indexes the HTML clips stored while search still read their markup.
+pub fn backfill_search_text(state: &AppState) {
+    let mut after = 0;
+    let mut indexed = 0;
+    loop {
+        let (id, content) = match state.store.next_html_without_text(after) {
+            Ok(Some(clip)) => clip,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::error!("failed to read HTML clips: {}", e);
+                break;
+            }
+        };
+        after = id;
+        match state.store.set_search_text(id, &html::to_text(&content)) {
+            Ok(()) => indexed += 1,
+            Err(e) => tracing::error!("failed to index HTML clip {}: {}", id, e),
+        }
+    }
+    if indexed > 0 {
+        tracing::info!("indexed the text of {} HTML clips", indexed);
+    }
+}
+
+/// Every collection of the user with the number of clips in it.
+pub async fn list_collections(
+    State(state): State<AppState>,
+    user: User,
+) -> Result<Json<Vec<Collection>>, ApiError> {
+    Ok(Json(state.store.collections(user.id)?))
+}
+
+pub async fn delete_clip(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let event = state.store.delete(user.id, id)?.ok_or(ApiError::NotFound)?;
+    state.publish(event);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn clear_clips(
+    State(state): State<AppState>,
+    user: User,
+) -> Result<StatusCode, ApiError> {
+    let (count, event) = state.store.clear(user.id)?;
+    state.publish(event);
+    tracing::info!("cleared {} clips", count);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Writes a history item back to the host clipboard (requires capture mode).
+///
+/// Only the configured capture user may write to the host clipboard.
+pub async fn paste_clip(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let capture = state
+        .capture
+        .as_ref()
+        .ok_or_else(|| ApiError::Unavailable("host clipboard capture is disabled".to_string()))?;
+    if state.config.capture_user.as_deref() != Some(user.username.as_str()) {
+        return Err(ApiError::Forbidden(
+            "only the capture user may write to the host clipboard".to_string(),
+        ));
+    }
+    let clip = state.store.get(user.id, id)?.ok_or(ApiError::NotFound)?;
+    if clip.item.key_version.is_some() {
+        return Err(ApiError::BadRequest(
+            "encrypted clips can only be pasted by your devices".to_string(),
+        ));
+    }
+    match capture.write(clip.item).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(CaptureError::Stopped) => Err(ApiError::Unavailable(
+            "host clipboard is not available".to_string(),
+        )),
+        Err(CaptureError::Write(e)) => Err(ApiError::Internal(e)),
+    }
+}
+
+/// Deletes clips whose `expires_at` has passed, such as captures flagged by
+/// an `expire` rule, and tells subscribers about it.
+pub async fn expire_clips(state: AppState) {
+    let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        match state.store.delete_expired(now_millis()) {
+            Ok(events) => {
+                if !events.is_empty() {
+                    tracing::info!("deleted {} expired clips", events.len());
+                }
+                for event in events {
+                    state.publish(event);
+                }
+            }
+            Err(e) => tracing::error!("failed to delete expired clips: {}", e),
+        }
+    }
+}
+
+pub fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,252 @@
+//! Layered server configuration.
+//!
+//! Every setting is resolved in this order: command-line flag, environment
+//! variable, TOML config file, built-in default.
+
+use crate::{files::FileLimits, store::Retention};
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+use synapse_sensitive::{Rules, Scanner};
+
+const DEFAULT_CONFIG_FILE: &str = "synapse.toml";
+const DEFAULT_PORT: u16 = 3000;
+
+#[derive(Parser)]
+#[command(name = "synapse-server")]
+#[command(about = "Synapse clipboard sync server")]
+struct Args {
+    /// TOML config file (defaults to ./synapse.toml when present)
+    #[arg(long, env = "SYNAPSE_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on
+    #[arg(long, env = "SYNAPSE_HOST")]
+    host: Option<IpAddr>,
+
+    /// Port to listen on
+    #[arg(long, env = "SYNAPSE_PORT")]
+    port: Option<u16>,
+
+    /// Directory holding the history database
+    #[arg(long, env = "SYNAPSE_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Log filter, e.g. `info` or `backend=debug`
+    #[arg(long, env = "SYNAPSE_LOG")]
+    log_level: Option<String>,
+
+    /// Origins allowed to call the API from a browser (comma separated)
+    #[arg(
+        long = "cors-origin",
+        env = "SYNAPSE_CORS_ORIGINS",
+        value_delimiter = ','
+    )]
+    cors_origins: Option<Vec<String>>,
+
+    /// PEM certificate chain; enables HTTPS together with --tls-key
+    #[arg(long, env = "SYNAPSE_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key; enables HTTPS together with --tls-cert
+    #[arg(long, env = "SYNAPSE_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+
+    /// Watch the host clipboard and record its changes
+    #[arg(long, env = "SYNAPSE_CAPTURE")]
+    capture: bool,
+
+    /// Account that owns clips captured from the host clipboard
+    #[arg(long, env = "SYNAPSE_CAPTURE_USER")]
+    capture_user: Option<String>,
+
+    /// Let anyone create an account (the first account can always be created)
+    #[arg(long, env = "SYNAPSE_ALLOW_REGISTRATION")]
+    allow_registration: bool,
+
+    /// OpenID Connect issuer URL; enables single sign-on
+    #[arg(long, env = "SYNAPSE_OIDC_ISSUER")]
+    oidc_issuer: Option<String>,
+
+    /// OpenID Connect client id
+    #[arg(long, env = "SYNAPSE_OIDC_CLIENT_ID")]
+    oidc_client_id: Option<String>,
+
+    /// OpenID Connect client secret (omit for public clients)
+    #[arg(long, env = "SYNAPSE_OIDC_CLIENT_SECRET", hide_env_values = true)]
+    oidc_client_secret: Option<String>,
+
+    /// Callback URL registered with the identity provider,
+    /// e.g. `https://synapse.example.com/api/auth/oidc/callback`
+    #[arg(long, env = "SYNAPSE_OIDC_REDIRECT_URL")]
+    oidc_redirect_url: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    host: Option<IpAddr>,
+    port: Option<u16>,
+    data_dir: Option<PathBuf>,
+    log_level: Option<String>,
+    cors_origins: Option<Vec<String>>,
+    tls: Option<FileTls>,
+    capture: Option<bool>,
+    capture_user: Option<String>,
+    allow_registration: Option<bool>,
+    oidc: Option<FileOidc>,
+    sensitive: Option<Rules>,
+    retention: Option<Retention>,
+    files: Option<FileLimits>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileTls {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileOidc {
+    issuer: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    redirect_url: Option<String>,
+    scopes: Option<Vec<String>>,
+    username_claim: Option<UsernameClaim>,
+    allow_signup: Option<bool>,
+}
+
+pub struct Config {
+    pub host: IpAddr,
+    pub port: u16,
+    pub data_dir: PathBuf,
+    pub log_level: String,
+    /// Browser origins allowed to make credentialed cross-origin requests.
+    pub cors_origins: Vec<String>,
+    pub tls: Option<TlsConfig>,
+    pub capture: bool,
+    pub capture_user: Option<String>,
+    pub allow_registration: bool,
+    pub oidc: Option<OidcConfig>,
+    /// Detectors applied to text captured from the host clipboard.
+    pub sensitive: Scanner,
+    /// Retention policy of users who have not set their own.
+    pub retention: Retention,
+    /// Size limits of file clips, uploaded or captured.
+    pub files: FileLimits,
+}
+
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    /// Requested in addition to `openid`.
+    pub scopes: Vec<String>,
+    /// Claim that names the Synapse account of a first-time SSO user.
+    pub username_claim: UsernameClaim,
+    /// Create accounts for unknown SSO users instead of rejecting them.
+    pub allow_signup: bool,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsernameClaim {
+    Email,
+    PreferredUsername,
+    Sub,
+}
+
+impl Config {
+    /// Builds the configuration from the process arguments, environment and config file.
+    pub fn load() -> Result<Self> {
+        let args = Args::parse();
+        let file = match &args.config {
+            Some(path) => read_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                read_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => FileConfig::default(),
+        };
+
+        let tls = match (args.tls_cert, args.tls_key) {
+            (Some(cert), Some(key)) => Some(TlsConfig { cert, key }),
+            (None, None) => file.tls.map(|tls| TlsConfig {
+                cert: tls.cert,
+                key: tls.key,
+            }),
+            _ => bail!("--tls-cert and --tls-key must be given together"),
+        };
+
+        let file_oidc = file.oidc.unwrap_or_default();
+        let oidc = match (
+            args.oidc_issuer.or(file_oidc.issuer),
+            args.oidc_client_id.or(file_oidc.client_id),
+            args.oidc_redirect_url.or(file_oidc.redirect_url),
+        ) {
+            (Some(issuer), Some(client_id), Some(redirect_url)) => Some(OidcConfig {
+                issuer,
+                client_id,
+                client_secret: args.oidc_client_secret.or(file_oidc.client_secret),
+                redirect_url,
+                scopes: file_oidc
+                    .scopes
+                    .unwrap_or_else(|| vec!["email".to_string(), "profile".to_string()]),
+                username_claim: file_oidc.username_claim.unwrap_or(UsernameClaim::Email),
+                allow_signup: file_oidc.allow_signup.unwrap_or(true),
+            }),
+            (None, None, None) => None,
+            _ => bail!("OIDC needs an issuer, a client id and a redirect URL"),
+        };
+
+        let sensitive = Scanner::new(&file.sensitive.unwrap_or_default())
+            .context("invalid [sensitive] rules")?;
+
+        Ok(Self {
+            host: args
+                .host
+                .or(file.host)
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            port: args.port.or(file.port).unwrap_or(DEFAULT_PORT),
+            data_dir: args
+                .data_dir
+                .or(file.data_dir)
+                .unwrap_or_else(|| PathBuf::from("data")),
+            log_level: args
+                .log_level
+                .or(file.log_level)
+                .unwrap_or_else(|| "info".to_string()),
+            cors_origins: args.cors_origins.or(file.cors_origins).unwrap_or_default(),
+            tls,
+            capture: args.capture || file.capture.unwrap_or(false),
+            capture_user: args.capture_user.or(file.capture_user),
+            allow_registration: args.allow_registration || file.allow_registration.unwrap_or(false),
+            oidc,
+            sensitive,
+            retention: file.retention.unwrap_or_default(),
+            files: file.files.unwrap_or_default(),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))
+}
@@ -0,0 +1,181 @@
+//! Device registry.
+//!
+//! Every client registers itself once with a friendly name, its platform and
+//! the public half of a keypair it generated on first run. Requests made
+//! through a session or API token bound to a device are attributed to it, so
+//! clips carry a device id the client cannot forge.
+
+use crate::{
+    auth::{self, SESSION_COOKIE},
+    clips::now_millis,
+    error::ApiError,
+    store::{Device, User},
+    AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+const MAX_NAME_LEN: usize = 64;
+const PUBLIC_KEY_LEN: usize = 32;
+
+#[derive(Deserialize)]
+pub struct NewDevice {
+    name: String,
+    platform: Option<String>,
+    /// Base64 X25519 public key.
+    public_key: Option<String>,
+    /// Return a device-bound API token instead of binding the current session.
+    #[serde(default)]
+    issue_token: bool,
+}
+
+#[derive(Serialize)]
+pub struct RegisteredDevice {
+    #[serde(flatten)]
+    device: Device,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
+pub async fn list_devices(
+    State(state): State<AppState>,
+    user: User,
+) -> Result<Json<Vec<Device>>, ApiError> {
+    let mut devices = state.store.devices(user.id)?;
+    for device in &mut devices {
+        device.current = user.device_id.as_ref() == Some(&device.id);
+    }
+    Ok(Json(devices))
+}
+
+/// Registers the calling client as a new device.
+///
+/// Browsers get their session cookie bound to the device; clients asking for
+/// `issue_token` (the CLI) get an API token bound to it instead.
+pub async fn register_device(
+    State(state): State<AppState>,
+    user: User,
+    headers: HeaderMap,
+    Json(body): Json<NewDevice>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = device_name(&body.name)?;
+    if let Some(key) = &body.public_key {
+        let valid = STANDARD
+            .decode(key)
+            .is_ok_and(|bytes| bytes.len() == PUBLIC_KEY_LEN);
+        if !valid {
+            return Err(ApiError::BadRequest(
+                "public_key must be a base64 X25519 public key".to_string(),
+            ));
+        }
+    }
+
+    let now = now_millis();
+    let mut device = Device {
+        id: device_id(),
+        name: name.to_string(),
+        platform: body
+            .platform
+            .map(|platform| platform.trim().to_string())
+            .filter(|platform| !platform.is_empty()),
+        public_key: body.public_key,
+        created_at: now,
+        last_seen: Some(now),
+        revoked_at: None,
+        current: false,
+    };
+    state.store.create_device(user.id, &device)?;
+    tracing::info!(
+        "registered device {} ({}) for {}",
+        device.name,
+        device.id,
+        user.username
+    );
+
+    let mut token = None;
+    if body.issue_token {
+        let secret = auth::new_api_token();
+        state.store.create_api_token(
+            user.id,
+            &device.name,
+            &auth::hash_secret(&secret),
+            now,
+            Some(&device.id),
+        )?;
+        token = Some(secret);
+        device.current = true;
+    } else if let (None, Some(session)) = (
+        auth::bearer_token(&headers),
+        auth::cookie(&headers, SESSION_COOKIE),
+    ) {
+        state
+            .store
+            .bind_session(&auth::hash_secret(session), &device.id)?;
+        device.current = true;
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(RegisteredDevice { device, token }),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct DeviceUpdate {
+    name: String,
+}
+
+pub async fn rename_device(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<String>,
+    Json(body): Json<DeviceUpdate>,
+) -> Result<Json<Device>, ApiError> {
+    let name = device_name(&body.name)?;
+    if !state.store.rename_device(user.id, &id, name)? {
+        return Err(ApiError::NotFound);
+    }
+    let mut device = state
+        .store
+        .active_device(user.id, &id)?
+        .ok_or(ApiError::NotFound)?;
+    device.current = user.device_id == Some(id);
+    Ok(Json(device))
+}
+
+/// Revokes a device; its sessions and tokens stop working immediately.
+pub async fn revoke_device(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    if state.store.revoke_device(user.id, &id, now_millis())? {
+        tracing::info!("revoked device {} of {}", id, user.username);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+fn device_name(name: &str) -> Result<&str, ApiError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(ApiError::BadRequest(format!(
+            "device name must be 1 to {MAX_NAME_LEN} characters"
+        )));
+    }
+    Ok(name)
+}
+
+/// 128 bits of randomness, hex encoded.
+fn device_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
@@ -0,0 +1,98 @@
+use crate::{clips::Clip, error::ApiError, store::User, AppState};
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+/// Capacity of the live broadcast channel; slower subscribers are disconnected
+/// and resume from their last seen event id.
+pub const CHANNEL_CAPACITY: usize = 256;
+
+/// A change to the clipboard history, numbered by a monotonically increasing `seq`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClipEvent {
+    pub seq: i64,
+    /// Owner of the changed history; events are only delivered to this user.
+    #[serde(skip)]
+    pub user_id: i64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    Created { clip: Box<Clip> },
+    Updated { clip: Box<Clip> },
+    Deleted { id: i64 },
+    Cleared,
+}
+
+impl ClipEvent {
+    fn to_sse(&self) -> Event {
+        let name = match self.kind {
+            EventKind::Created { .. } => "created",
+            EventKind::Updated { .. } => "updated",
+            EventKind::Deleted { .. } => "deleted",
+            EventKind::Cleared => "cleared",
+        };
+        Event::default()
+            .id(self.seq.to_string())
+            .event(name)
+            .json_data(self)
+            .unwrap_or_else(|_| Event::default().comment("unserializable event"))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StreamParams {
+    cursor: Option<i64>,
+}
+
+/// Server-Sent Events stream of history changes.
+///
+/// Clients resume after a reconnect with the `Last-Event-ID` header (sent
+/// automatically by `EventSource`) or the `cursor` query parameter; every event
+/// after that id is replayed before live events. `cursor=0` replays the whole
+/// current history.
+pub async fn stream_events(
+    State(state): State<AppState>,
+    user: User,
+    headers: HeaderMap,
+    Query(params): Query<StreamParams>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let cursor = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .or(params.cursor);
+
+    // Subscribe before reading the backlog so nothing falls between the two
+    let live = BroadcastStream::new(state.events.subscribe());
+    let backlog = match cursor {
+        Some(cursor) => state.store.events_since(user.id, cursor)?,
+        None => Vec::new(),
+    };
+    let last_seq = backlog
+        .last()
+        .map(|event| event.seq)
+        .or(cursor)
+        .unwrap_or(0);
+
+    let live = live
+        .take_while(|received| received.is_ok())
+        .filter_map(move |received| {
+            received
+                .ok()
+                .filter(|event| event.user_id == user.id && event.seq > last_seq)
+        });
+    let stream = tokio_stream::iter(backlog)
+        .chain(live)
+        .map(|event| Ok(event.to_sse()));
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,438 @@
+//! File transfer.
+//!
+//! A `file` clip lists the files and folders that were on the clipboard; its
+//! content is a JSON array of `FileEntry`. The file contents are uploaded on
+//! their own, in chunks, and kept in the blob store (see `blobs`). An upload
+//! is keyed by the user and the hash, so a client that lost its connection
+//! starts the same upload again and carries on from the offset it gets back.
+//! A finished upload is checked against its hash before the file can be
+//! listed in a clip or downloaded from `/api/blobs/{hash}`.
+
+use crate::{
+    auth::random_secret,
+    blobs::{self, blob_path, hash_file, internal, is_hash, store_file},
+    clips::now_millis,
+    error::ApiError,
+    store::{Upload, User},
+    AppState,
+};
+use axum::{
+    body::Bytes,
+    extract::{Path as UrlPath, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use tokio::io::AsyncWriteExt;
+
+/// Largest chunk accepted by `PUT /api/uploads/{id}`, well under the
+/// default request body limit.
+pub const CHUNK_SIZE: u64 = 1024 * 1024;
+/// Uploads that receive nothing for this long are dropped.
+const UPLOAD_TTL_MS: i64 = 24 * 60 * 60 * 1000;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Size limits of file clips, from the `[files]` config section.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileLimits {
+    /// Largest single file, in bytes.
+    pub max_file_size: u64,
+    /// Largest total size of the files in one clip, in bytes.
+    pub max_clip_size: u64,
+    /// Most files in one clip, counting the files inside copied folders.
+    pub max_files: usize,
+}
+
+impl Default for FileLimits {
+    fn default() -> Self {
+        Self {
+            max_file_size: 512 * 1024 * 1024,
+            max_clip_size: 2 * 1024 * 1024 * 1024,
+            max_files: 1000,
+        }
+    }
+}
+
+/// One file of a file clip.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// `/`-separated relative path; files from a copied folder start with
+    /// the folder's name.
+    pub path: String,
+    pub size: u64,
+    /// SHA-256 of the contents, hex encoded.
+    pub hash: String,
+}
+
+#[derive(Deserialize)]
+pub struct NewUpload {
+    hash: String,
+    size: u64,
+}
+
+#[derive(Serialize)]
+pub struct UploadStatus {
+    /// Absent when the file had already been uploaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    hash: String,
+    size: u64,
+    received: u64,
+    complete: bool,
+    chunk_size: u64,
+}
+
+impl UploadStatus {
+    fn of(upload: Upload) -> Self {
+        Self {
+            complete: upload.received == upload.size,
+            id: Some(upload.id),
+            hash: upload.hash,
+            size: upload.size,
+            received: upload.received,
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+
+    fn complete(hash: String, size: u64) -> Self {
+        Self {
+            id: None,
+            hash,
+            size,
+            received: size,
+            complete: true,
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChunkParams {
+    offset: u64,
+}
+
+/// Starts or resumes the upload of a file. Files the user already uploaded
+/// are reported complete straight away.
+pub async fn create_upload(
+    State(state): State<AppState>,
+    user: User,
+    Json(new): Json<NewUpload>,
+) -> Result<Json<UploadStatus>, ApiError> {
+    let hash = new.hash.to_ascii_lowercase();
+    if !is_hash(&hash) {
+        return Err(ApiError::BadRequest(
+            "hash must be a hex encoded SHA-256".to_string(),
+        ));
+    }
+    let limit = state.config.files.max_file_size;
+    if new.size > limit {
+        return Err(ApiError::BadRequest(format!(
+            "files are limited to {limit} bytes"
+        )));
+    }
+    if state.store.blob(user.id, &hash)?.is_some() {
+        return Ok(Json(UploadStatus::complete(hash, new.size)));
+    }
+    // An empty file has no chunks; it is complete as soon as it is announced
+    if new.size == 0 {
+        if hash != hex::encode(Sha256::digest([])) {
+            return Err(ApiError::BadRequest(
+                "hash does not match an empty file".to_string(),
+            ));
+        }
+        blobs::put(&state, &[]).map_err(internal)?;
+        state
+            .store
+            .add_blob(user.id, &hash, 0, None, now_millis())?;
+        return Ok(Json(UploadStatus::complete(hash, 0)));
+    }
+    let upload = state.store.start_upload(
+        user.id,
+        &random_secret()[..32],
+        &hash,
+        new.size,
+        now_millis(),
+    )?;
+    if upload.size != new.size {
+        return Err(ApiError::Conflict(format!(
+            "an upload of this file with {} bytes is in progress",
+            upload.size
+        )));
+    }
+    // Keep the partial file and the recorded offset in step after a crash
+    let partial = partial_path(&state.config.data_dir, &upload.id);
+    let written = fs::metadata(&partial).map_or(0, |meta| meta.len());
+    if written != upload.received {
+        let received = written.min(upload.received);
+        truncate(&partial, received).map_err(internal)?;
+        state.store.rewind_upload(&upload.id, received)?;
+        return Ok(Json(UploadStatus::of(Upload { received, ..upload })));
+    }
+    Ok(Json(UploadStatus::of(upload)))
+}
+
+pub async fn upload_status(
+    State(state): State<AppState>,
+    user: User,
+    UrlPath(id): UrlPath<String>,
+) -> Result<Json<UploadStatus>, ApiError> {
+    let upload = state
+        .store
+        .upload(user.id, &id)?
+        .ok_or(ApiError::NotFound)?;
+    Ok(Json(UploadStatus::of(upload)))
+}
+
+/// Appends a chunk at `offset`, which must be where the upload is. The last
+/// chunk completes the upload once the contents match the hash.
+pub async fn upload_chunk(
+    State(state): State<AppState>,
+    user: User,
+    UrlPath(id): UrlPath<String>,
+    Query(params): Query<ChunkParams>,
+    body: Bytes,
+) -> Result<Json<UploadStatus>, ApiError> {
+    let upload = state
+        .store
+        .upload(user.id, &id)?
+        .ok_or(ApiError::NotFound)?;
+    let len = body.len() as u64;
+    if len == 0 || len > CHUNK_SIZE {
+        return Err(ApiError::BadRequest(format!(
+            "chunks must hold 1 to {CHUNK_SIZE} bytes"
+        )));
+    }
+    if params.offset.saturating_add(len) > upload.size {
+        return Err(ApiError::BadRequest(
+            "chunk runs past the end of the file".to_string(),
+        ));
+    }
+    if !state
+        .store
+        .claim_chunk(&id, params.offset, len, now_millis())?
+    {
+        let received = state.store.upload(user.id, &id)?.map_or(0, |u| u.received);
+        return Err(ApiError::Conflict(format!(
+            "upload is at byte {received}, not {}",
+            params.offset
+        )));
+    }
+
+    let data_dir = &state.config.data_dir;
+    let partial = partial_path(data_dir, &id);
+    if let Err(e) = append(&partial, &body).await {
+        // Forget the chunk so the client can send it again
+        let _ = truncate(&partial, params.offset);
+        state.store.rewind_upload(&id, params.offset)?;
+        return Err(internal(e));
+    }
+    let upload = Upload {
+        received: params.offset + len,
+        ..upload
+    };
+    if upload.received < upload.size {
+        return Ok(Json(UploadStatus::of(upload)));
+    }
+
+    let (hash, _) = {
+        let partial = partial.clone();
+        tokio::task::spawn_blocking(move || hash_file(&partial))
+            .await
+            .map_err(|e| ApiError::Internal(e.to_string()))?
+            .map_err(internal)?
+    };
+    if hash != upload.hash {
+        let _ = fs::remove_file(&partial);
+        state.store.cancel_upload(user.id, &id)?;
+        return Err(ApiError::BadRequest(
+            "uploaded contents do not match the hash; upload the file again".to_string(),
+        ));
+    }
+    store_file(&state, &partial, &hash).map_err(internal)?;
+    state.store.finish_upload(user.id, &upload, now_millis())?;
+    tracing::debug!("received file {} ({} bytes)", hash, upload.size);
+    Ok(Json(UploadStatus::complete(hash, upload.size)))
+}
+
+pub async fn cancel_upload(
+    State(state): State<AppState>,
+    user: User,
+    UrlPath(id): UrlPath<String>,
+) -> Result<StatusCode, ApiError> {
+    if !state.store.cancel_upload(user.id, &id)? {
+        return Err(ApiError::NotFound);
+    }
+    let _ = fs::remove_file(partial_path(&state.config.data_dir, &id));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Checks the content of a file clip against the limits and makes sure the
+/// user has uploaded every file it lists. Returns the total size.
+pub fn check_clip(state: &AppState, user_id: i64, content: &str) -> Result<u64, ApiError> {
+    let entries: Vec<FileEntry> = serde_json::from_str(content).map_err(|e| {
+        ApiError::BadRequest(format!("file clips must list their files as JSON: {e}"))
+    })?;
+    let limits = &state.config.files;
+    if entries.is_empty() {
+        return Err(ApiError::BadRequest(
+            "file clips must list at least one file".to_string(),
+        ));
+    }
+    if entries.len() > limits.max_files {
+        return Err(ApiError::BadRequest(format!(
+            "file clips are limited to {} files",
+            limits.max_files
+        )));
+    }
+    let mut paths = HashSet::new();
+    let mut total = 0;
+    for entry in &entries {
+        if relative_path(&entry.path).is_none() || !paths.insert(entry.path.as_str()) {
+            return Err(ApiError::BadRequest(format!(
+                "invalid or repeated file path {:?}",
+                entry.path
+            )));
+        }
+        let hash = entry.hash.to_ascii_lowercase();
+        let blob = if is_hash(&hash) {
+            state.store.blob(user_id, &hash)?
+        } else {
+            None
+        };
+        let Some(blob) = blob else {
+            return Err(ApiError::BadRequest(format!(
+                "{} has not been uploaded",
+                entry.path
+            )));
+        };
+        // The limits and the clip's size go by what was stored, not by the listing
+        if entry.size != blob.size {
+            return Err(ApiError::BadRequest(format!(
+                "{} is {} bytes, not {}",
+                entry.path, blob.size, entry.size
+            )));
+        }
+        if blob.size > limits.max_file_size {
+            return Err(ApiError::BadRequest(format!(
+                "{} is larger than the limit of {} bytes",
+                entry.path, limits.max_file_size
+            )));
+        }
+        total += blob.size;
+    }
+    if total > limits.max_clip_size {
+        return Err(ApiError::BadRequest(format!(
+            "file clips are limited to {} bytes in total",
+            limits.max_clip_size
+        )));
+    }
+    Ok(total)
+}
+
+/// Periodically drops uploads that were abandoned half way.
+pub async fn expire_uploads(state: AppState) {
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match state
+            .store
+            .delete_stale_uploads(now_millis() - UPLOAD_TTL_MS)
+        {
+            Ok(ids) => {
+                for id in &ids {
+                    let _ = fs::remove_file(partial_path(&state.config.data_dir, id));
+                }
+                if !ids.is_empty() {
+                    tracing::info!("dropped {} abandoned uploads", ids.len());
+                }
+            }
+            Err(e) => tracing::error!("failed to drop abandoned uploads: {}", e),
+        }
+    }
+}
+
+fn partial_path(data_dir: &Path, id: &str) -> PathBuf {
+    data_dir.join("uploads").join(id)
+}
+
+/// Copies a local file into the blob store, returning its hash and size.
+pub fn import_file(state: &AppState, source: &Path) -> io::Result<(String, u64)> {
+    let temp = blobs::temp_path(&state.config.data_dir);
+    fs::create_dir_all(temp.parent().unwrap())?;
+    fs::copy(source, &temp)?;
+    let result = hash_file(&temp).and_then(|(hash, size)| {
+        store_file(state, &temp, &hash)?;
+        Ok((hash, size))
+    });
+    let _ = fs::remove_file(&temp);
+    result
+}
+
+/// Writes the files of a file clip below `dir` and returns the paths of its
+/// top-level entries: the files and folders that were on the clipboard.
+pub fn materialize(data_dir: &Path, content: &str, dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries: Vec<FileEntry> =
+        serde_json::from_str(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut top = Vec::new();
+    for entry in entries {
+        let relative = relative_path(&entry.path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "invalid file path in clip")
+        })?;
+        let target = dir.join(&relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(
+            blob_path(data_dir, &entry.hash.to_ascii_lowercase()),
+            &target,
+        )?;
+        if let Some(Component::Normal(first)) = relative.components().next() {
+            let first = dir.join(first);
+            if !top.contains(&first) {
+                top.push(first);
+            }
+        }
+    }
+    Ok(top)
+}
+
+async fn append(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(bytes).await?;
+    file.sync_data().await
+}
+
+fn truncate(path: &Path, len: u64) -> io::Result<()> {
+    match fs::OpenOptions::new().write(true).open(path) {
+        Ok(file) => file.set_len(len),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && len == 0 => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A clip path as a relative path that cannot leave the directory it is
+/// written to.
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for part in path.split('/') {
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if !part.contains('\\') => relative.push(name),
+            _ => return None,
+        }
+    }
+    Some(relative)
+}
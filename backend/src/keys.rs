@@ -0,0 +1,109 @@
+//! Key distribution for end-to-end encrypted clips.
+//!
+//! Clients encrypt clip contents with a per-user key and the server stores
+//! only ciphertext. Each key version is uploaded wrapped to the X25519 public
+//! key of every device allowed to read it, so the server relays keys it
+//! cannot open. Devices that joined later get older versions wrapped for them
+//! by any device that already holds them (see `missing` in the status). When
+//! a device holding the current key is revoked, the status asks clients to
+//! rotate to a new version, and new clips are only accepted under it.
+
+use crate::{
+    clips::now_millis,
+    error::ApiError,
+    store::{Grant, MissingGrants, User, WrappedKey},
+    AppState,
+};
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+pub struct KeyStatus {
+    current_version: Option<i64>,
+    /// A revoked device held the current key; a new version should be created.
+    rotation_needed: bool,
+    /// Key versions wrapped for the calling device.
+    keys: Vec<WrappedKey>,
+    /// Devices waiting for key versions the caller may be able to grant.
+    missing: Vec<MissingGrants>,
+}
+
+pub async fn key_status(
+    State(state): State<AppState>,
+    user: User,
+) -> Result<Json<KeyStatus>, ApiError> {
+    let device_id = calling_device(&user)?;
+    Ok(Json(KeyStatus {
+        current_version: state.store.current_key_version(user.id)?,
+        rotation_needed: state.store.rotation_needed(user.id)?,
+        keys: state.store.device_keys(user.id, device_id)?,
+        missing: state.store.missing_grants(user.id)?,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct NewKeyVersion {
+    version: i64,
+    grants: Vec<Grant>,
+}
+
+/// Creates the first key version or rotates to the next one. The caller must
+/// include a grant for itself.
+pub async fn create_key_version(
+    State(state): State<AppState>,
+    user: User,
+    Json(body): Json<NewKeyVersion>,
+) -> Result<StatusCode, ApiError> {
+    let device_id = calling_device(&user)?;
+    if body
+        .grants
+        .iter()
+        .any(|grant| grant.version != body.version)
+    {
+        return Err(ApiError::BadRequest(
+            "grants must be for the new version".to_string(),
+        ));
+    }
+    if !body.grants.iter().any(|grant| grant.device_id == device_id) {
+        return Err(ApiError::BadRequest(
+            "grants must include the calling device".to_string(),
+        ));
+    }
+    if !state
+        .store
+        .add_key_version(user.id, body.version, &body.grants, now_millis())?
+    {
+        return Err(ApiError::Conflict(format!(
+            "cannot create key version {}; fetch the key status and retry",
+            body.version
+        )));
+    }
+    tracing::info!("{} created key version {}", user.username, body.version);
+    Ok(StatusCode::CREATED)
+}
+
+#[derive(Deserialize)]
+pub struct NewGrants {
+    grants: Vec<Grant>,
+}
+
+/// Shares existing key versions with devices that do not hold them yet.
+pub async fn add_grants(
+    State(state): State<AppState>,
+    user: User,
+    Json(body): Json<NewGrants>,
+) -> Result<StatusCode, ApiError> {
+    calling_device(&user)?;
+    if !state.store.add_grants(user.id, &body.grants)? {
+        return Err(ApiError::BadRequest(
+            "grants must name existing key versions and active devices".to_string(),
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn calling_device(user: &User) -> Result<&str, ApiError> {
+    user.device_id.as_deref().ok_or_else(|| {
+        ApiError::Forbidden("keys are only available to registered devices".to_string())
+    })
+}
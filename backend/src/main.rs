@@ -1,31 +1,254 @@
+use anyhow::{anyhow, Context};
 use axum::{
     body::Body,
-    http::{header, StatusCode, Uri},
+    http::{header, HeaderName, HeaderValue, Method, StatusCode, Uri},
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{delete, get, patch, post},
     Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use rust_embed::RustEmbed;
-use std::net::SocketAddr;
-use tower_http::cors::CorsLayer;
+use std::{io::ErrorKind, sync::Arc};
+use tokio::sync::broadcast;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing_subscriber::EnvFilter;
+
+mod auth;
+mod blobs;
+mod capture;
+mod clips;
+mod config;
+mod devices;
+mod error;
+mod events;
+mod files;
+mod keys;
+mod oidc;
+mod retention;
+mod store;
+mod thumbnails;
+
+use config::Config;
+use events::ClipEvent;
+use store::Store;
 
 #[derive(RustEmbed)]
 #[folder = "../target/dx/frontend/release/web/public"]
 struct Assets;
 
+#[derive(Clone)]
+pub struct AppState {
+    pub store: Arc<Store>,
+    pub events: broadcast::Sender<ClipEvent>,
+    pub capture: Option<capture::CaptureHandle>,
+    pub config: Arc<Config>,
+    pub oidc: Option<Arc<oidc::Oidc>>,
+}
+
+impl AppState {
+    /// Forwards a logged event to every live subscriber.
+    pub fn publish(&self, event: ClipEvent) {
+        // No receivers just means nobody is listening right now
+        let _ = self.events.send(event);
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let filter = EnvFilter::try_new(&config.log_level).unwrap_or_else(|e| {
+        eprintln!("invalid log level {:?}: {}", config.log_level, e);
+        EnvFilter::new("info")
+    });
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    if let Err(e) = serve(config).await {
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn serve(config: Config) -> anyhow::Result<()> {
+    std::fs::create_dir_all(&config.data_dir)
+        .with_context(|| format!("failed to create data dir {}", config.data_dir.display()))?;
+    let store = Store::open(&config.data_dir).with_context(|| {
+        format!(
+            "failed to open history store in {}",
+            config.data_dir.display()
+        )
+    })?;
+    tracing::info!(
+        "history store: {}",
+        config.data_dir.join("history.db").display()
+    );
+
+    let mut state = AppState {
+        store: Arc::new(store),
+        events: broadcast::channel(events::CHANNEL_CAPACITY).0,
+        capture: None,
+        oidc: None,
+        config: Arc::new(config),
+    };
+    blobs::migrate(&state);
+    if let Some(oidc) = &state.config.oidc {
+        tracing::info!("single sign-on enabled with issuer {}", oidc.issuer);
+        state.oidc = Some(Arc::new(oidc::Oidc::new()?));
+    }
+    // Opt-in: watch the host clipboard and record its changes into history
+    if state.config.capture {
+        if state.config.capture_user.is_none() {
+            tracing::warn!(
+                "capture is enabled but no capture_user is set; host clips will be dropped"
+            );
+        }
+        state.capture = Some(capture::spawn(state.clone()));
+    }
+
+    tokio::spawn(auth::expire_sessions(state.clone()));
+    tokio::spawn(clips::expire_clips(state.clone()));
+    tokio::spawn(retention::compact_history(state.clone()));
+    tokio::spawn(files::expire_uploads(state.clone()));
+    tokio::spawn(blobs::collect_garbage(state.clone()));
+    // Image clips stored before previews existed get theirs in the background
+    let backfill_state = state.clone();
+    tokio::task::spawn_blocking(move || thumbnails::backfill(&backfill_state));
+    // So are HTML clips stored before search read their text
+    let backfill_state = state.clone();
+    tokio::task::spawn_blocking(move || clips::backfill_search_text(&backfill_state));
 
     let app = Router::new()
         .route("/api/health", get(health_check))
+        .route("/api/auth/register", post(auth::register))
+        .route("/api/auth/login", post(auth::login))
+        .route("/api/auth/logout", post(auth::logout))
+        .route("/api/auth/me", get(auth::me))
+        .route("/api/auth/methods", get(auth::methods))
+        .route("/api/auth/oidc/login", get(oidc::login))
+        .route("/api/auth/oidc/callback", get(oidc::callback))
+        .route(
+            "/api/tokens",
+            get(auth::list_tokens).post(auth::create_token),
+        )
+        .route("/api/tokens/:id", delete(auth::delete_token))
+        .route(
+            "/api/devices",
+            get(devices::list_devices).post(devices::register_device),
+        )
+        .route(
+            "/api/devices/:id",
+            patch(devices::rename_device).delete(devices::revoke_device),
+        )
+        .route(
+            "/api/keys",
+            get(keys::key_status).post(keys::create_key_version),
+        )
+        .route("/api/keys/grants", post(keys::add_grants))
+        .route(
+            "/api/retention",
+            get(retention::get_retention)
+                .put(retention::set_retention)
+                .delete(retention::reset_retention),
+        )
+        .route(
+            "/api/clips",
+            get(clips::list_clips)
+                .post(clips::create_clip)
+                .delete(clips::clear_clips),
+        )
+        .route(
+            "/api/clips/:id",
+            get(clips::get_clip)
+                .patch(clips::update_clip)
+                .delete(clips::delete_clip),
+        )
+        .route("/api/clips/:id/paste", post(clips::paste_clip))
+        .route("/api/search", get(clips::search_clips))
+        .route("/api/collections", get(clips::list_collections))
+        .route("/api/uploads", post(files::create_upload))
+        .route(
+            "/api/uploads/:id",
+            get(files::upload_status)
+                .put(files::upload_chunk)
+                .delete(files::cancel_upload),
+        )
+        .route("/api/blobs/:hash", get(blobs::get_blob))
+        .route("/api/events", get(events::stream_events))
         .fallback(static_handler)
-        .layer(CorsLayer::permissive());
+        .layer(cors_layer(&state.config.cors_origins)?)
+        .with_state(state.clone());
+
+    // Bind up front so an occupied port is reported clearly
+    let config = &state.config;
+    let addr = config.addr();
+    let listener = std::net::TcpListener::bind(addr).map_err(|e| match e.kind() {
+        ErrorKind::AddrInUse => anyhow!(
+            "port {} is already in use on {} (is another Synapse instance running?)",
+            addr.port(),
+            addr.ip()
+        ),
+        _ => anyhow!("failed to listen on {}: {}", addr, e),
+    })?;
+    listener.set_nonblocking(true)?;
+
+    match &config.tls {
+        Some(tls) => {
+            let rustls = RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+                .await
+                .with_context(|| {
+                    format!(
+                        "failed to load TLS certificate {} / key {}",
+                        tls.cert.display(),
+                        tls.key.display()
+                    )
+                })?;
+            tracing::info!("listening on https://{}", addr);
+            axum_server::from_tcp_rustls(listener, rustls)
+                .serve(app.into_make_service())
+                .await?;
+        }
+        None => {
+            tracing::info!("listening on http://{}", addr);
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            axum::serve(listener, app).await?;
+        }
+    }
+    Ok(())
+}
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    println!("listening on {}", addr);
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+/// Cross-origin requests are only allowed from the configured origins;
+/// with none configured the API is same-origin only.
+fn cors_layer(origins: &[String]) -> anyhow::Result<CorsLayer> {
+    let origins = origins
+        .iter()
+        .map(|origin| {
+            HeaderValue::from_str(origin).with_context(|| format!("invalid CORS origin {origin:?}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_credentials(true)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::RANGE,
+            HeaderName::from_static("last-event-id"),
+        ])
+        // Cross-origin clients resume blob downloads with range requests
+        .expose_headers([header::CONTENT_RANGE, header::ACCEPT_RANGES]))
 }
 
 async fn health_check() -> &'static str {
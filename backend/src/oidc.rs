@@ -0,0 +1,368 @@
+//! OpenID Connect single sign-on.
+//!
+//! Synapse acts as a relying party using the authorization code flow with
+//! PKCE. The provider is discovered lazily on the first login, so the server
+//! starts even while the identity provider is unreachable, and discovered
+//! again once the metadata is an hour old or an ID token fails verification,
+//! which picks up rotated signing keys. The `state` of a login is also set as
+//! a cookie in the browser that started it, and the callback must present
+//! both. A successful callback ends in the same session cookie as a password
+//! login.
+
+use crate::{
+    auth,
+    clips::now_millis,
+    config::{OidcConfig, UsernameClaim},
+    error::ApiError,
+    store::User,
+    AppState,
+};
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+};
+use openidconnect::{
+    core::{CoreAuthenticationFlow, CoreClient, CoreIdTokenClaims, CoreProviderMetadata},
+    reqwest,
+    url::form_urlencoded,
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointMaybeSet, EndpointNotSet,
+    EndpointSet, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope,
+    TokenResponse,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How long a user may take at the identity provider before the login expires.
+const LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+/// Logins waiting for their callback; past this the oldest is dropped.
+const MAX_PENDING_LOGINS: usize = 1000;
+/// Discovered provider metadata, including the signing keys, is fetched again
+/// after this long.
+const METADATA_TTL: Duration = Duration::from_secs(60 * 60);
+/// An ID token that fails verification triggers a new discovery, but at most
+/// this often.
+const METADATA_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// Holds the `state` of a login in the browser that started it.
+const STATE_COOKIE: &str = "synapse_oidc_state";
+
+type OidcClient = CoreClient<
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointMaybeSet,
+    EndpointMaybeSet,
+>;
+
+/// Provider client plus the logins that are waiting for their callback.
+pub struct Oidc {
+    http: reqwest::Client,
+    client: tokio::sync::Mutex<Option<(OidcClient, Instant)>>,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+struct PendingLogin {
+    verifier: PkceCodeVerifier,
+    nonce: Nonce,
+    device_id: Option<String>,
+    started: Instant,
+}
+
+impl Oidc {
+    pub fn new() -> anyhow::Result<Self> {
+        // Following redirects would let the provider point us at arbitrary URLs
+        let http = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        Ok(Self {
+            http,
+            client: tokio::sync::Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The provider client, discovered again when the cached one is older
+    /// than `max_age`. A failed discovery falls back to the cached client.
+    async fn client(&self, config: &OidcConfig, max_age: Duration) -> Result<OidcClient, ApiError> {
+        let mut cached = self.client.lock().await;
+        if let Some((client, fetched)) = &*cached {
+            if fetched.elapsed() < max_age {
+                return Ok(client.clone());
+            }
+        }
+        match self.discover(config).await {
+            Ok(client) => {
+                *cached = Some((client.clone(), Instant::now()));
+                Ok(client)
+            }
+            Err(e) => match &*cached {
+                Some((client, _)) => Ok(client.clone()),
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn discover(&self, config: &OidcConfig) -> Result<OidcClient, ApiError> {
+        let issuer = IssuerUrl::new(config.issuer.clone())
+            .map_err(|e| ApiError::Internal(format!("invalid OIDC issuer: {e}")))?;
+        let redirect_url = RedirectUrl::new(config.redirect_url.clone())
+            .map_err(|e| ApiError::Internal(format!("invalid OIDC redirect URL: {e}")))?;
+        let metadata = CoreProviderMetadata::discover_async(issuer, &self.http)
+            .await
+            .map_err(|e| {
+                tracing::warn!("OIDC discovery for {} failed: {}", config.issuer, e);
+                ApiError::Unavailable("identity provider is unreachable".to_string())
+            })?;
+        tracing::info!("discovered OIDC provider {}", config.issuer);
+        Ok(CoreClient::from_provider_metadata(
+            metadata,
+            ClientId::new(config.client_id.clone()),
+            config.client_secret.clone().map(ClientSecret::new),
+        )
+        .set_redirect_uri(redirect_url))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginParams {
+    /// Registered device to bind the session to once the login completes.
+    device_id: Option<String>,
+}
+
+/// Redirects the browser to the identity provider.
+pub async fn login(
+    State(state): State<AppState>,
+    Query(params): Query<LoginParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (oidc, config) = enabled(&state)?;
+    let client = oidc.client(config, METADATA_TTL).await?;
+
+    let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+    let mut request = client
+        .authorize_url(
+            CoreAuthenticationFlow::AuthorizationCode,
+            CsrfToken::new_random,
+            Nonce::new_random,
+        )
+        .set_pkce_challenge(challenge);
+    for scope in &config.scopes {
+        request = request.add_scope(Scope::new(scope.clone()));
+    }
+    let (url, csrf, nonce) = request.url();
+
+    let mut pending = oidc.pending.lock().unwrap();
+    pending.retain(|_, login| login.started.elapsed() < LOGIN_TTL);
+    if pending.len() >= MAX_PENDING_LOGINS {
+        let oldest = pending
+            .iter()
+            .min_by_key(|(_, login)| login.started)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            pending.remove(&oldest);
+        }
+    }
+    pending.insert(
+        csrf.secret().clone(),
+        PendingLogin {
+            verifier,
+            nonce,
+            device_id: params.device_id,
+            started: Instant::now(),
+        },
+    );
+    let cookie = auth::cookie_header(
+        &state,
+        STATE_COOKIE,
+        csrf.secret(),
+        LOGIN_TTL.as_secs() as i64,
+    )?;
+    Ok((AppendHeaders([cookie]), Redirect::to(url.as_str())))
+}
+
+#[derive(Deserialize)]
+pub struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Completes the login started by `login`. Failures are sent back to the
+/// frontend as `/?sso_error=<message>` so the login page can show them.
+pub async fn callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<CallbackParams>,
+) -> Response {
+    let browser_state = auth::cookie(&headers, STATE_COOKIE).map(str::to_string);
+    let response = match sign_in(&state, browser_state, params).await {
+        Ok(user) => {
+            tracing::info!("user {} signed in via OIDC", user.username);
+            match auth::start_session(&state, user.id, user.device_id.as_deref()) {
+                Ok(cookie) => (AppendHeaders([cookie]), Redirect::to("/")).into_response(),
+                Err(e) => error_redirect(e),
+            }
+        }
+        Err(e) => error_redirect(e),
+    };
+    // The state is single use either way
+    match auth::cookie_header(&state, STATE_COOKIE, "", 0) {
+        Ok(expired) => (AppendHeaders([expired]), response).into_response(),
+        Err(_) => response,
+    }
+}
+
+/// Resolves the account behind the callback, with `device_id` set when the
+/// login was started for one of its active devices.
+async fn sign_in(
+    state: &AppState,
+    browser_state: Option<String>,
+    params: CallbackParams,
+) -> Result<User, ApiError> {
+    let (oidc, config) = enabled(state)?;
+    if let Some(error) = params.error {
+        return Err(ApiError::BadRequest(
+            params.error_description.unwrap_or(error),
+        ));
+    }
+    let (Some(code), Some(csrf)) = (params.code, params.state) else {
+        return Err(ApiError::BadRequest(
+            "missing code or state in callback".to_string(),
+        ));
+    };
+    // Otherwise a victim could be sent the callback of a login the attacker
+    // started and end up signed in to the attacker's account
+    if browser_state.as_deref() != Some(csrf.as_str()) {
+        return Err(ApiError::BadRequest(
+            "login was started in another browser, please try again".to_string(),
+        ));
+    }
+    let pending = oidc
+        .pending
+        .lock()
+        .unwrap()
+        .remove(&csrf)
+        .filter(|login| login.started.elapsed() < LOGIN_TTL)
+        .ok_or_else(|| ApiError::BadRequest("login expired, please try again".to_string()))?;
+
+    let mut client = oidc.client(config, METADATA_TTL).await?;
+    let response = client
+        .exchange_code(AuthorizationCode::new(code))
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .set_pkce_verifier(pending.verifier)
+        .request_async(&oidc.http)
+        .await
+        .map_err(|e| {
+            tracing::warn!("OIDC code exchange failed: {}", e);
+            ApiError::BadRequest("identity provider rejected the login".to_string())
+        })?;
+    let id_token = response.id_token().ok_or_else(|| {
+        ApiError::BadRequest("identity provider returned no ID token".to_string())
+    })?;
+    // The provider may have rotated its signing keys since the last discovery
+    if id_token
+        .claims(&client.id_token_verifier(), &pending.nonce)
+        .is_err()
+    {
+        client = oidc.client(config, METADATA_REFRESH_INTERVAL).await?;
+    }
+    let claims = id_token
+        .claims(&client.id_token_verifier(), &pending.nonce)
+        .map_err(|e| ApiError::BadRequest(format!("invalid ID token: {e}")))?;
+
+    let mut user = map_identity(state, config, claims)?;
+    if let Some(id) = &pending.device_id {
+        user.device_id = state
+            .store
+            .active_device(user.id, id)?
+            .map(|device| device.id);
+    }
+    Ok(user)
+}
+
+/// Maps a verified identity to its account, linking or creating one on first sign-in.
+fn map_identity(
+    state: &AppState,
+    config: &OidcConfig,
+    claims: &CoreIdTokenClaims,
+) -> Result<User, ApiError> {
+    let issuer = claims.issuer().as_str();
+    let subject = claims.subject().as_str();
+    if let Some(user) = state.store.identity_user(issuer, subject)? {
+        return Ok(user);
+    }
+
+    // First sign-in with this identity: find or create the account it maps to
+    let verified_email = claims
+        .email()
+        .filter(|_| claims.email_verified() == Some(true));
+    let username = match config.username_claim {
+        UsernameClaim::Email => verified_email.map(|email| email.as_str()),
+        UsernameClaim::PreferredUsername => claims.preferred_username().map(|name| name.as_str()),
+        UsernameClaim::Sub => Some(subject),
+    }
+    .map(str::trim)
+    .filter(|name| !name.is_empty())
+    .ok_or_else(|| {
+        ApiError::Forbidden("identity provider did not supply a usable username".to_string())
+    })?;
+
+    let now = now_millis();
+    let user = match state.store.user_by_name(username)? {
+        // Only a verified email is trusted to claim an existing local account
+        Some((user, _)) if matches!(config.username_claim, UsernameClaim::Email) => user,
+        Some(_) => {
+            return Err(ApiError::Forbidden(format!(
+                "account {username} already exists and cannot be linked"
+            )))
+        }
+        None if config.allow_signup => {
+            let user = state
+                .store
+                .create_user(username, None, now)?
+                .ok_or_else(|| {
+                    ApiError::Forbidden(format!(
+                        "account {username} already exists and cannot be linked"
+                    ))
+                })?;
+            tracing::info!("registered user {} via OIDC", user.username);
+            user
+        }
+        None => {
+            return Err(ApiError::Forbidden(
+                "no Synapse account exists for this identity".to_string(),
+            ))
+        }
+    };
+    state.store.link_identity(issuer, subject, user.id, now)?;
+    Ok(user)
+}
+
+fn enabled(state: &AppState) -> Result<(&Oidc, &OidcConfig), ApiError> {
+    match (&state.oidc, &state.config.oidc) {
+        (Some(oidc), Some(config)) => Ok((oidc, config)),
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+fn error_redirect(err: ApiError) -> Response {
+    let message = match err {
+        ApiError::BadRequest(msg)
+        | ApiError::Forbidden(msg)
+        | ApiError::Conflict(msg)
+        | ApiError::Unavailable(msg) => msg,
+        ApiError::Unauthorized => "authentication failed".to_string(),
+        ApiError::NotFound => "single sign-on is not enabled".to_string(),
+        ApiError::Internal(msg) => {
+            tracing::error!("internal error during OIDC login: {}", msg);
+            "internal server error".to_string()
+        }
+    };
+    let message: String = form_urlencoded::byte_serialize(message.as_bytes()).collect();
+    Redirect::to(&format!("/?sso_error={message}")).into_response()
+}
@@ -0,0 +1,98 @@
+//! History retention.
+//!
+//! Each user can limit their history by item count, age and total size; users
+//! who have not set a policy follow the server's `[retention]` default. A
+//! background task deletes the clips outside the policy, oldest first, and
+//! tells subscribers about it like any other deletion. Pinned and favorite
+//! clips and clips in a collection are exempt.
+
+use crate::{
+    clips::now_millis,
+    error::ApiError,
+    store::{Retention, User},
+    AppState,
+};
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use std::time::Duration;
+
+/// How often histories are compacted.
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Serialize)]
+pub struct RetentionStatus {
+    #[serde(flatten)]
+    retention: Retention,
+    /// Whether this is the server default rather than the user's own policy.
+    default: bool,
+}
+
+pub async fn get_retention(
+    State(state): State<AppState>,
+    user: User,
+) -> Result<Json<RetentionStatus>, ApiError> {
+    let status = match state.store.retention(user.id)? {
+        Some(retention) => RetentionStatus {
+            retention,
+            default: false,
+        },
+        None => RetentionStatus {
+            retention: state.config.retention.clone(),
+            default: true,
+        },
+    };
+    Ok(Json(status))
+}
+
+/// Replaces the user's policy. Omitted limits are unlimited, not inherited.
+pub async fn set_retention(
+    State(state): State<AppState>,
+    user: User,
+    Json(retention): Json<Retention>,
+) -> Result<Json<RetentionStatus>, ApiError> {
+    if retention.max_items == Some(0) || retention.max_age_days == Some(0) {
+        return Err(ApiError::BadRequest(
+            "max_items and max_age_days must be at least 1".to_string(),
+        ));
+    }
+    let out_of_range = |limit: Option<u64>| limit.is_some_and(|n| i64::try_from(n).is_err());
+    if out_of_range(retention.max_items.map(|n| n as u64)) || out_of_range(retention.max_bytes) {
+        return Err(ApiError::BadRequest(format!(
+            "max_items and max_bytes must be at most {}",
+            i64::MAX
+        )));
+    }
+    state.store.set_retention(user.id, &retention)?;
+    Ok(Json(RetentionStatus {
+        retention,
+        default: false,
+    }))
+}
+
+/// Goes back to the server default.
+pub async fn reset_retention(
+    State(state): State<AppState>,
+    user: User,
+) -> Result<StatusCode, ApiError> {
+    state.store.reset_retention(user.id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Periodically deletes the clips outside each user's retention policy.
+pub async fn compact_history(state: AppState) {
+    let mut interval = tokio::time::interval(COMPACTION_INTERVAL);
+    loop {
+        interval.tick().await;
+        match state.store.compact(&state.config.retention, now_millis()) {
+            Ok(events) => {
+                if !events.is_empty() {
+                    tracing::info!("compacted history, deleted {} clips", events.len());
+                }
+                for event in events {
+                    state.publish(event);
+                }
+            }
+            Err(e) => tracing::error!("failed to compact history: {}", e),
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use super::Store;
+use crate::thumbnails::ImagePreview;
+use rusqlite::{params, OptionalExtension};
+
+/// A stored blob. Its bytes are in `blobs/<hash>` in the data directory.
+#[derive(Clone, Debug)]
+pub struct Blob {
+    pub hash: String,
+    pub size: u64,
+    pub mime_type: Option<String>,
+}
+
+/// Image clip payloads that still sit in `clips.content` as base64.
+pub struct InlineImage {
+    pub id: i64,
+    pub user_id: i64,
+    pub content: String,
+    pub mime_type: Option<String>,
+}
+
+/// The blob store's index. Blobs are shared between users; a user may only
+/// read the blobs they added.
+impl Store {
+    /// Returns the blob if the user added it.
+    pub fn blob(&self, user_id: i64, hash: &str) -> rusqlite::Result<Option<Blob>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT b.hash, b.size, b.mime_type FROM blobs b
+             JOIN user_blobs u ON u.hash = b.hash
+             WHERE u.user_id = ?1 AND b.hash = ?2",
+            params![user_id, hash],
+            |row| {
+                Ok(Blob {
+                    hash: row.get(0)?,
+                    size: row.get::<_, i64>(1)? as u64,
+                    mime_type: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    /// Records a blob whose bytes are in place and lets the user read it.
+    /// Adding a blob again restarts its grace period, and fills in its MIME
+    /// type if it had none.
+    pub fn add_blob(
+        &self,
+        user_id: i64,
+        hash: &str,
+        size: u64,
+        mime_type: Option<&str>,
+        now: i64,
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        insert_blob(&tx, user_id, hash, size, mime_type, now)?;
+        tx.commit()
+    }
+
+    /// Marks a stored blob as just added, so the sweep spares it for another
+    /// grace period. Returns false if there is no such blob, in which case its
+    /// bytes may already be gone and have to be written again.
+    pub fn keep_blob(&self, hash: &str, now: i64) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE blobs SET added_at = ?2 WHERE hash = ?1",
+            params![hash, now],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Deletes the blobs no clip has referenced since before `before` and
+    /// calls `remove` with each hash so its bytes go too. Both happen under
+    /// the lock, so no one can see a blob's row without its bytes.
+    pub fn delete_unreferenced_blobs(
+        &self,
+        before: i64,
+        mut remove: impl FnMut(&str),
+    ) -> rusqlite::Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("DELETE FROM blobs WHERE refs = 0 AND added_at < ?1 RETURNING hash")?;
+        let hashes = stmt
+            .query_map(params![before], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for hash in &hashes {
+            remove(hash);
+        }
+        Ok(hashes.len())
+    }
+
+    /// The next image clip after `after` whose payload is still inline.
+    pub fn next_inline_image(&self, after: i64) -> rusqlite::Result<Option<InlineImage>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id, user_id, content, mime_type FROM clips
+             WHERE id > ?1 AND item_type = 'image' AND blob IS NULL AND key_version IS NULL
+             ORDER BY id LIMIT 1",
+            params![after],
+            |row| {
+                Ok(InlineImage {
+                    id: row.get(0)?,
+                    user_id: row.get(1)?,
+                    content: row.get(2)?,
+                    mime_type: row.get(3)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    /// Replaces the inline payload of an image clip with a reference to the
+    /// blob now holding it.
+    pub fn move_to_blob(
+        &self,
+        image: &InlineImage,
+        hash: &str,
+        size: u64,
+        now: i64,
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        insert_blob(
+            &tx,
+            image.user_id,
+            hash,
+            size,
+            image.mime_type.as_deref(),
+            now,
+        )?;
+        tx.execute(
+            "UPDATE clips SET content = '', blob = ?2, content_hash = ?2, size = ?3 WHERE id = ?1",
+            params![image.id, hash, size as i64],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO clip_blobs (clip_id, hash) VALUES (?1, ?2)",
+            params![image.id, hash],
+        )?;
+        tx.commit()
+    }
+
+    /// The next image clip after `after` in the blob store without a preview,
+    /// as its id, owner and blob.
+    pub fn next_image_without_preview(
+        &self,
+        after: i64,
+    ) -> rusqlite::Result<Option<(i64, i64, String)>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id, user_id, blob FROM clips
+             WHERE id > ?1 AND blob IS NOT NULL AND blurhash IS NULL
+             ORDER BY id LIMIT 1",
+            params![after],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+    }
+
+    /// Records the preview generated for an existing image clip.
+    pub fn set_preview(&self, id: i64, preview: &ImagePreview) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        record_preview(&tx, id, preview)?;
+        tx.commit()
+    }
+}
+
+/// Stores a preview on a clip that has none, and references its thumbnail.
+pub(super) fn record_preview(
+    conn: &rusqlite::Connection,
+    id: i64,
+    preview: &ImagePreview,
+) -> rusqlite::Result<()> {
+    let updated = conn.execute(
+        "UPDATE clips SET thumbnail = ?2, blurhash = ?3, width = ?4, height = ?5
+         WHERE id = ?1 AND blurhash IS NULL",
+        params![
+            id,
+            preview.thumbnail,
+            preview.blurhash,
+            preview.width,
+            preview.height
+        ],
+    )?;
+    if let (1, Some(thumbnail)) = (updated, &preview.thumbnail) {
+        conn.execute(
+            "INSERT OR IGNORE INTO clip_blobs (clip_id, hash) VALUES (?1, ?2)",
+            params![id, thumbnail],
+        )?;
+    }
+    Ok(())
+}
+
+pub(super) fn insert_blob(
+    conn: &rusqlite::Connection,
+    user_id: i64,
+    hash: &str,
+    size: u64,
+    mime_type: Option<&str>,
+    now: i64,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO blobs (hash, size, mime_type, added_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (hash) DO UPDATE SET
+             added_at = excluded.added_at,
+             mime_type = COALESCE(blobs.mime_type, excluded.mime_type)",
+        params![hash, size as i64, mime_type, now],
+    )?;
+    conn.execute(
+        "INSERT OR IGNORE INTO user_blobs (user_id, hash) VALUES (?1, ?2)",
+        params![user_id, hash],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clips::ClipboardItem;
+    use serde_json::json;
+
+    fn refs(store: &Store, hash: &str) -> i64 {
+        let conn = store.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT refs FROM blobs WHERE hash = ?1",
+            params![hash],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    fn sweep(store: &Store, before: i64) -> Vec<String> {
+        let mut removed = Vec::new();
+        store
+            .delete_unreferenced_blobs(before, |hash| removed.push(hash.to_string()))
+            .unwrap();
+        removed
+    }
+
+    #[test]
+    fn references_follow_clips() {
+        let store = Store::open_in_memory().unwrap();
+        let user = store.create_user("alice", None, 0).unwrap().unwrap().id;
+        store
+            .add_blob(user, "image", 10, Some("image/png"), 100)
+            .unwrap();
+        store.add_blob(user, "file", 20, None, 100).unwrap();
+        let image: ClipboardItem =
+            serde_json::from_value(json!({ "type": "image", "content": "", "blob": "image" }))
+                .unwrap();
+        let (image, _) = store.insert(user, image).unwrap();
+        let files = json!([{ "path": "a.txt", "size": 20, "hash": "file" }]);
+        let files: ClipboardItem = serde_json::from_value(
+            json!({ "type": "file", "content": files.to_string(), "timestamp": 1 }),
+        )
+        .unwrap();
+        store.insert(user, files).unwrap();
+        assert_eq!((refs(&store, "image"), refs(&store, "file")), (1, 1));
+
+        store.delete(user, image.id).unwrap();
+        assert_eq!(refs(&store, "image"), 0);
+        // Deleting the account cascades to its clips and their references
+        store
+            .conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM users WHERE id = ?1", params![user])
+            .unwrap();
+        assert_eq!(refs(&store, "file"), 0);
+    }
+
+    #[test]
+    fn sweep_spares_referenced_and_recent_blobs() {
+        let store = Store::open_in_memory().unwrap();
+        let user = store.create_user("alice", None, 0).unwrap().unwrap().id;
+        store.add_blob(user, "used", 10, None, 100).unwrap();
+        store.add_blob(user, "old", 10, None, 100).unwrap();
+        store.add_blob(user, "new", 10, None, 300).unwrap();
+        let used: ClipboardItem =
+            serde_json::from_value(json!({ "type": "image", "content": "", "blob": "used" }))
+                .unwrap();
+        store.insert(user, used).unwrap();
+
+        // Still within the grace period of every blob
+        assert!(sweep(&store, 100).is_empty());
+        assert_eq!(sweep(&store, 200), ["old"]);
+        assert!(store.blob(user, "old").unwrap().is_none());
+        assert!(store.blob(user, "used").unwrap().is_some());
+
+        // Keeping a blob restarts its grace period
+        assert!(store.keep_blob("new", 500).unwrap());
+        assert!(sweep(&store, 400).is_empty());
+        assert!(!store.keep_blob("old", 500).unwrap());
+    }
+}
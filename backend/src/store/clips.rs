@@ -0,0 +1,436 @@
+use super::{blobs::record_preview, Store};
+use crate::{
+    clips::{Clip, ClipFilter, ClipUpdate, ClipboardItem},
+    events::{ClipEvent, EventKind},
+    thumbnails::ImagePreview,
+};
+use rusqlite::{params, OptionalExtension, Row, Transaction};
+use serde::Serialize;
+
+/// Columns read by `clip_from_row_at`, selected from `CLIPS`. The device name
+/// follows renames of registered devices.
+pub(super) const CLIP_COLUMNS: &str = "c.id, c.item_type, c.content, c.mime_type, c.timestamp,
+    COALESCE(d.name, c.device), c.device_id, c.username, c.size, c.key_version, c.expires_at,
+    c.source_app, c.pinned, c.favorite, c.collection, c.copy_count, c.blob,
+    c.thumbnail, c.blurhash, c.width, c.height";
+pub(super) const CLIPS: &str = "clips c LEFT JOIN devices d ON d.id = c.device_id";
+/// Conditions for a `ClipFilter`, bound as `?2` to `?4` after the user id.
+const FILTER: &str = "c.user_id = ?1 AND (?2 = 0 OR c.pinned = 1) AND (?3 = 0 OR c.favorite = 1)
+    AND (?4 IS NULL OR c.collection = ?4)";
+
+#[derive(Serialize)]
+pub struct Collection {
+    pub name: String,
+    pub count: usize,
+}
+
+/// Clip history, always scoped to a single user.
+impl Store {
+    /// Returns a page of the clips matching `filter`, pinned ones first and
+    /// otherwise most recently copied first, together with the total count.
+    pub fn list(
+        &self,
+        user_id: i64,
+        filter: &ClipFilter,
+        offset: usize,
+        limit: usize,
+    ) -> rusqlite::Result<(Vec<Clip>, usize)> {
+        let conn = self.conn.lock().unwrap();
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM clips c WHERE {FILTER}"),
+            params![user_id, filter.pinned, filter.favorite, filter.collection],
+            |row| row.get(0),
+        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {CLIP_COLUMNS} FROM {CLIPS} WHERE {FILTER}
+             ORDER BY c.pinned DESC, c.timestamp DESC, c.id DESC LIMIT ?5 OFFSET ?6"
+        ))?;
+        let items = stmt
+            .query_map(
+                params![
+                    user_id,
+                    filter.pinned,
+                    filter.favorite,
+                    filter.collection,
+                    limit as i64,
+                    offset as i64
+                ],
+                clip_from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok((items, total as usize))
+    }
+
+    /// The user's collections by name, with how many clips each holds.
+    pub fn collections(&self, user_id: i64) -> rusqlite::Result<Vec<Collection>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT collection, COUNT(*) FROM clips
+             WHERE user_id = ?1 AND collection IS NOT NULL
+             GROUP BY collection ORDER BY collection",
+        )?;
+        let collections = stmt
+            .query_map(params![user_id], |row| {
+                Ok(Collection {
+                    name: row.get(0)?,
+                    count: row.get::<_, i64>(1)? as usize,
+                })
+            })?
+            .collect();
+        collections
+    }
+
+    pub fn get(&self, user_id: i64, id: i64) -> rusqlite::Result<Option<Clip>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {CLIP_COLUMNS} FROM {CLIPS} WHERE c.id = ?1 AND c.user_id = ?2"),
+            params![id, user_id],
+            clip_from_row,
+        )
+        .optional()
+    }
+
+    /// Records a clip. A clip whose content the user already has is not
+    /// stored again; the existing one is moved up to the new copy's time and
+    /// device and its `copy_count` goes up, reported as an update.
+    pub fn insert(&self, user_id: i64, item: ClipboardItem) -> rusqlite::Result<(Clip, ClipEvent)> {
+        let hash = item.content_hash();
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let existing: Option<i64> = match &hash {
+            Some(hash) => tx
+                .query_row(
+                    "SELECT id FROM clips WHERE user_id = ?1 AND content_hash = ?2 AND item_type = ?3",
+                    params![user_id, hash, item.item_type],
+                    |row| row.get(0),
+                )
+                .optional()?,
+            None => None,
+        };
+        if let Some(id) = existing {
+            tx.execute(
+                "UPDATE clips SET timestamp = ?2, device = ?3, device_id = ?4, username = ?5,
+                     source_app = ?6, expires_at = ?7, copy_count = copy_count + 1
+                 WHERE id = ?1",
+                params![
+                    id,
+                    item.timestamp.unwrap_or_default(),
+                    item.device,
+                    item.device_id,
+                    item.username,
+                    item.source_app,
+                    item.expires_at,
+                ],
+            )?;
+            // Clips stored before previews existed take the new one
+            if let Some(preview) = &item.preview {
+                record_preview(&tx, id, preview)?;
+            }
+            return record_update(tx, user_id, id);
+        }
+
+        let preview = item.preview.as_ref();
+        tx.execute(
+            "INSERT INTO clips (item_type, content, mime_type, timestamp, device, device_id, username, size, key_version, expires_at, source_app, pinned, favorite, collection, copy_count, content_hash, user_id, blob, thumbnail, blurhash, width, height, search_text)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23)",
+            params![
+                item.item_type,
+                item.content,
+                item.mime_type,
+                item.timestamp.unwrap_or_default(),
+                item.device,
+                item.device_id,
+                item.username,
+                item.size.unwrap_or_default() as i64,
+                item.key_version,
+                item.expires_at,
+                item.source_app,
+                item.pinned,
+                item.favorite,
+                item.collection,
+                item.copy_count,
+                hash,
+                user_id,
+                item.blob,
+                preview.and_then(|p| p.thumbnail.as_ref()),
+                preview.map(|p| &p.blurhash),
+                preview.map(|p| p.width),
+                preview.map(|p| p.height),
+                item.search_text(),
+            ],
+        )?;
+        let clip = Clip {
+            id: tx.last_insert_rowid(),
+            item,
+        };
+        for blob in clip.item.blob_refs() {
+            tx.execute(
+                "INSERT OR IGNORE INTO clip_blobs (clip_id, hash) VALUES (?1, ?2)",
+                params![clip.id, blob],
+            )?;
+        }
+        let seq = record_event(&tx, user_id, "created", Some(clip.id))?;
+        tx.commit()?;
+        let event = ClipEvent {
+            seq,
+            user_id,
+            kind: EventKind::Created {
+                clip: Box::new(clip.clone()),
+            },
+        };
+        Ok((clip, event))
+    }
+
+    /// Applies `update` to a clip, returning `None` if it did not exist.
+    /// An empty collection name takes the clip out of its collection.
+    pub fn update(
+        &self,
+        user_id: i64,
+        id: i64,
+        update: &ClipUpdate,
+    ) -> rusqlite::Result<Option<(Clip, ClipEvent)>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        if tx.execute(
+            "UPDATE clips SET
+                 pinned = COALESCE(?3, pinned),
+                 favorite = COALESCE(?4, favorite),
+                 collection = CASE WHEN ?5 IS NULL THEN collection ELSE NULLIF(?5, '') END
+             WHERE id = ?1 AND user_id = ?2",
+            params![
+                id,
+                user_id,
+                update.pinned,
+                update.favorite,
+                update.collection
+            ],
+        )? == 0
+        {
+            return Ok(None);
+        }
+        record_update(tx, user_id, id).map(Some)
+    }
+
+    /// Removes a clip, returning `None` if it did not exist.
+    pub fn delete(&self, user_id: i64, id: i64) -> rusqlite::Result<Option<ClipEvent>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        if tx.execute(
+            "DELETE FROM clips WHERE id = ?1 AND user_id = ?2",
+            params![id, user_id],
+        )? == 0
+        {
+            return Ok(None);
+        }
+        // The creation and updates can no longer be replayed, so drop them from the log
+        tx.execute(
+            "DELETE FROM events WHERE kind IN ('created', 'updated') AND clip_id = ?1",
+            params![id],
+        )?;
+        let seq = record_event(&tx, user_id, "deleted", Some(id))?;
+        tx.commit()?;
+        Ok(Some(ClipEvent {
+            seq,
+            user_id,
+            kind: EventKind::Deleted { id },
+        }))
+    }
+
+    /// Removes every clip (of any user) whose `expires_at` is not after `now`.
+    pub fn delete_expired(&self, now: i64) -> rusqlite::Result<Vec<ClipEvent>> {
+        let expired = {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn.prepare(
+                "SELECT user_id, id FROM clips WHERE expires_at <= ?1 AND user_id IS NOT NULL",
+            )?;
+            let rows = stmt
+                .query_map(params![now], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<Vec<(i64, i64)>>>()?;
+            rows
+        };
+        let mut events = Vec::new();
+        for (user_id, id) in expired {
+            events.extend(self.delete(user_id, id)?);
+        }
+        Ok(events)
+    }
+
+    /// Removes every clip of the user and returns how many were deleted.
+    pub fn clear(&self, user_id: i64) -> rusqlite::Result<(usize, ClipEvent)> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let count = tx.execute("DELETE FROM clips WHERE user_id = ?1", params![user_id])?;
+        // A clear supersedes everything before it
+        tx.execute("DELETE FROM events WHERE user_id = ?1", params![user_id])?;
+        let seq = record_event(&tx, user_id, "cleared", None)?;
+        tx.commit()?;
+        Ok((
+            count,
+            ClipEvent {
+                seq,
+                user_id,
+                kind: EventKind::Cleared,
+            },
+        ))
+    }
+
+    /// Returns every logged event of the user with a sequence number greater than `cursor`.
+    pub fn events_since(&self, user_id: i64, cursor: i64) -> rusqlite::Result<Vec<ClipEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT e.seq, e.kind, e.clip_id, {CLIP_COLUMNS} FROM events e
+             LEFT JOIN clips c ON c.id = e.clip_id
+             LEFT JOIN devices d ON d.id = c.device_id
+             WHERE e.user_id = ?1 AND e.seq > ?2 ORDER BY e.seq"
+        ))?;
+        let mut rows = stmt.query(params![user_id, cursor])?;
+        let mut events = Vec::new();
+        while let Some(row) = rows.next()? {
+            let seq: i64 = row.get(0)?;
+            let kind: String = row.get(1)?;
+            let clip_id: Option<i64> = row.get(2)?;
+            let kind = match (kind.as_str(), clip_id) {
+                ("created", Some(_)) => match row.get::<_, Option<i64>>(3)? {
+                    Some(_) => EventKind::Created {
+                        clip: Box::new(clip_from_row_at(row, 3)?),
+                    },
+                    None => continue,
+                },
+                ("updated", Some(_)) => match row.get::<_, Option<i64>>(3)? {
+                    Some(_) => EventKind::Updated {
+                        clip: Box::new(clip_from_row_at(row, 3)?),
+                    },
+                    None => continue,
+                },
+                ("deleted", Some(id)) => EventKind::Deleted { id },
+                ("cleared", _) => EventKind::Cleared,
+                _ => continue,
+            };
+            events.push(ClipEvent { seq, user_id, kind });
+        }
+        Ok(events)
+    }
+}
+
+/// Logs an update of clip `id` and commits `tx`, returning the clip as it
+/// now is.
+fn record_update(tx: Transaction, user_id: i64, id: i64) -> rusqlite::Result<(Clip, ClipEvent)> {
+    // Replaying the latest update is enough to catch up
+    tx.execute(
+        "DELETE FROM events WHERE kind = 'updated' AND clip_id = ?1",
+        params![id],
+    )?;
+    let seq = record_event(&tx, user_id, "updated", Some(id))?;
+    let clip = tx.query_row(
+        &format!("SELECT {CLIP_COLUMNS} FROM {CLIPS} WHERE c.id = ?1"),
+        params![id],
+        clip_from_row,
+    )?;
+    tx.commit()?;
+    let event = ClipEvent {
+        seq,
+        user_id,
+        kind: EventKind::Updated {
+            clip: Box::new(clip.clone()),
+        },
+    };
+    Ok((clip, event))
+}
+
+fn record_event(
+    tx: &Transaction,
+    user_id: i64,
+    kind: &str,
+    clip_id: Option<i64>,
+) -> rusqlite::Result<i64> {
+    tx.execute(
+        "INSERT INTO events (kind, clip_id, user_id) VALUES (?1, ?2, ?3)",
+        params![kind, clip_id, user_id],
+    )?;
+    Ok(tx.last_insert_rowid())
+}
+
+pub(super) fn clip_from_row(row: &Row) -> rusqlite::Result<Clip> {
+    clip_from_row_at(row, 0)
+}
+
+/// Reads the `CLIP_COLUMNS` starting at column index `start`.
+fn clip_from_row_at(row: &Row, start: usize) -> rusqlite::Result<Clip> {
+    Ok(Clip {
+        id: row.get(start)?,
+        item: ClipboardItem {
+            item_type: row.get(start + 1)?,
+            content: row.get(start + 2)?,
+            mime_type: row.get(start + 3)?,
+            timestamp: Some(row.get(start + 4)?),
+            device: row.get(start + 5)?,
+            device_id: row.get(start + 6)?,
+            username: row.get(start + 7)?,
+            size: Some(row.get::<_, i64>(start + 8)? as usize),
+            key_version: row.get(start + 9)?,
+            expires_at: row.get(start + 10)?,
+            source_app: row.get(start + 11)?,
+            pinned: row.get(start + 12)?,
+            favorite: row.get(start + 13)?,
+            collection: row.get(start + 14)?,
+            copy_count: row.get(start + 15)?,
+            blob: row.get(start + 16)?,
+            preview: match row.get::<_, Option<String>>(start + 18)? {
+                Some(blurhash) => Some(ImagePreview::new(
+                    row.get(start + 17)?,
+                    blurhash,
+                    row.get(start + 19)?,
+                    row.get(start + 20)?,
+                )),
+                None => None,
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn item(value: serde_json::Value) -> ClipboardItem {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn repeated_copies_are_merged() {
+        let store = Store::open_in_memory().unwrap();
+        let user = store.create_user("alice", None, 0).unwrap().unwrap().id;
+        let text = json!({ "type": "text", "content": "hello", "timestamp": 1 });
+
+        let (first, event) = store.insert(user, item(text.clone())).unwrap();
+        assert!(matches!(event.kind, EventKind::Created { .. }));
+        let (again, event) = store
+            .insert(
+                user,
+                item(json!({ "type": "text", "content": "hello", "timestamp": 2 })),
+            )
+            .unwrap();
+        assert!(matches!(event.kind, EventKind::Updated { .. }));
+        assert_eq!(again.id, first.id);
+        assert_eq!(again.item.copy_count, 2);
+        assert_eq!(again.item.timestamp, Some(2));
+
+        // The same text as HTML, or encrypted, is a different clip
+        let (html, _) = store
+            .insert(user, item(json!({ "type": "html", "content": "hello" })))
+            .unwrap();
+        assert_ne!(html.id, first.id);
+        let sealed = json!({ "type": "text", "content": "hello", "key_version": 1 });
+        let (a, _) = store.insert(user, item(sealed.clone())).unwrap();
+        let (b, _) = store.insert(user, item(sealed)).unwrap();
+        assert_ne!(a.id, b.id);
+        assert_eq!(b.item.copy_count, 1);
+
+        // Nor is another user's copy
+        let bob = store.create_user("bob", None, 0).unwrap().unwrap().id;
+        let (theirs, _) = store.insert(bob, item(text)).unwrap();
+        assert_ne!(theirs.id, first.id);
+        assert_eq!(theirs.item.copy_count, 1);
+    }
+}
@@ -0,0 +1,108 @@
+use super::Store;
+use rusqlite::{params, OptionalExtension, Row};
+use serde::Serialize;
+
+const DEVICE_COLUMNS: &str = "id, name, platform, public_key, created_at, last_seen, revoked_at";
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    pub platform: Option<String>,
+    /// Base64 X25519 public key generated by the device on first run.
+    pub public_key: Option<String>,
+    pub created_at: i64,
+    pub last_seen: Option<i64>,
+    pub revoked_at: Option<i64>,
+    /// Whether this is the device making the request.
+    pub current: bool,
+}
+
+/// Registered devices. A revoked device is kept so its clips stay attributed,
+/// but every session and token bound to it is deleted.
+impl Store {
+    pub fn create_device(&self, user_id: i64, device: &Device) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO devices (id, user_id, name, platform, public_key, created_at, last_seen)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                device.id,
+                user_id,
+                device.name,
+                device.platform,
+                device.public_key,
+                device.created_at,
+                device.last_seen,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Every device of the user, most recently seen first.
+    pub fn devices(&self, user_id: i64) -> rusqlite::Result<Vec<Device>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DEVICE_COLUMNS} FROM devices WHERE user_id = ?1
+             ORDER BY revoked_at IS NOT NULL, last_seen DESC, created_at DESC"
+        ))?;
+        let devices = stmt.query_map(params![user_id], device_from_row)?.collect();
+        devices
+    }
+
+    /// Looks up a device of the user that has not been revoked.
+    pub fn active_device(&self, user_id: i64, id: &str) -> rusqlite::Result<Option<Device>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!(
+                "SELECT {DEVICE_COLUMNS} FROM devices
+                 WHERE id = ?1 AND user_id = ?2 AND revoked_at IS NULL"
+            ),
+            params![id, user_id],
+            device_from_row,
+        )
+        .optional()
+    }
+
+    /// Renames an active device, returning `false` if the user has no such device.
+    pub fn rename_device(&self, user_id: i64, id: &str, name: &str) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "UPDATE devices SET name = ?1
+             WHERE id = ?2 AND user_id = ?3 AND revoked_at IS NULL",
+            params![name, id, user_id],
+        )? > 0)
+    }
+
+    /// Revokes a device and signs it out everywhere, returning `false` if the
+    /// user has no such active device.
+    pub fn revoke_device(&self, user_id: i64, id: &str, now: i64) -> rusqlite::Result<bool> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        if tx.execute(
+            "UPDATE devices SET revoked_at = ?1
+             WHERE id = ?2 AND user_id = ?3 AND revoked_at IS NULL",
+            params![now, id, user_id],
+        )? == 0
+        {
+            return Ok(false);
+        }
+        tx.execute("DELETE FROM sessions WHERE device_id = ?1", params![id])?;
+        tx.execute("DELETE FROM api_tokens WHERE device_id = ?1", params![id])?;
+        tx.commit()?;
+        Ok(true)
+    }
+}
+
+fn device_from_row(row: &Row) -> rusqlite::Result<Device> {
+    Ok(Device {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        platform: row.get(2)?,
+        public_key: row.get(3)?,
+        created_at: row.get(4)?,
+        last_seen: row.get(5)?,
+        revoked_at: row.get(6)?,
+        current: false,
+    })
+}
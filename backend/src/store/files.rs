@@ -0,0 +1,133 @@
+use super::{blobs::insert_blob, Store};
+use rusqlite::{params, OptionalExtension};
+
+/// A file upload in progress. Its bytes so far are in `uploads/<id>` in the
+/// data directory.
+#[derive(Clone, Debug)]
+pub struct Upload {
+    pub id: String,
+    pub hash: String,
+    pub size: u64,
+    pub received: u64,
+}
+
+/// Uploads in progress. A finished upload becomes a blob of the user.
+impl Store {
+    /// Returns the user's upload of `hash`, starting one with `id` if there is
+    /// none yet, so an interrupted client can pick up where it left off.
+    pub fn start_upload(
+        &self,
+        user_id: i64,
+        id: &str,
+        hash: &str,
+        size: u64,
+        now: i64,
+    ) -> rusqlite::Result<Upload> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO uploads (id, user_id, hash, size, received, updated_at)
+             VALUES (?1, ?2, ?3, ?4, 0, ?5)",
+            params![id, user_id, hash, size as i64, now],
+        )?;
+        conn.query_row(
+            "SELECT id, hash, size, received FROM uploads WHERE user_id = ?1 AND hash = ?2",
+            params![user_id, hash],
+            upload_from_row,
+        )
+    }
+
+    pub fn upload(&self, user_id: i64, id: &str) -> rusqlite::Result<Option<Upload>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id, hash, size, received FROM uploads WHERE id = ?1 AND user_id = ?2",
+            params![id, user_id],
+            upload_from_row,
+        )
+        .optional()
+    }
+
+    /// Claims the bytes from `offset` to `offset + len` of an upload. Returns
+    /// `false` when the upload is not at `offset`, e.g. because another request
+    /// already sent that chunk.
+    pub fn claim_chunk(&self, id: &str, offset: u64, len: u64, now: i64) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "UPDATE uploads SET received = ?3, updated_at = ?4 WHERE id = ?1 AND received = ?2",
+            params![id, offset as i64, (offset + len) as i64, now],
+        )? > 0)
+    }
+
+    /// Moves an upload back to `received`, after a chunk failed to be written.
+    pub fn rewind_upload(&self, id: &str, received: u64) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE uploads SET received = ?2 WHERE id = ?1",
+            params![id, received as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Turns a complete upload into a blob of the user.
+    pub fn finish_upload(&self, user_id: i64, upload: &Upload, now: i64) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM uploads WHERE id = ?1", params![upload.id])?;
+        insert_blob(&tx, user_id, &upload.hash, upload.size, None, now)?;
+        tx.commit()
+    }
+
+    /// Drops an upload, returning `false` if the user had no such upload.
+    pub fn cancel_upload(&self, user_id: i64, id: &str) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "DELETE FROM uploads WHERE id = ?1 AND user_id = ?2",
+            params![id, user_id],
+        )? > 0)
+    }
+
+    /// Drops every upload that has not received anything since `before` and
+    /// returns their ids.
+    pub fn delete_stale_uploads(&self, before: i64) -> rusqlite::Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("DELETE FROM uploads WHERE updated_at < ?1 RETURNING id")?;
+        let ids = stmt.query_map(params![before], |row| row.get(0))?.collect();
+        ids
+    }
+}
+
+fn upload_from_row(row: &rusqlite::Row) -> rusqlite::Result<Upload> {
+    Ok(Upload {
+        id: row.get(0)?,
+        hash: row.get(1)?,
+        size: row.get::<_, i64>(2)? as u64,
+        received: row.get::<_, i64>(3)? as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_chunks_can_be_sent_again() {
+        let store = Store::open_in_memory().unwrap();
+        let user = store.create_user("alice", None, 0).unwrap().unwrap().id;
+        let upload = store.start_upload(user, "up", "hash", 30, 1).unwrap();
+        assert_eq!(upload.received, 0);
+
+        assert!(store.claim_chunk("up", 0, 10, 2).unwrap());
+        // Another request for the same bytes loses the race
+        assert!(!store.claim_chunk("up", 0, 10, 2).unwrap());
+        assert!(store.claim_chunk("up", 10, 10, 3).unwrap());
+
+        // Writing the second chunk failed
+        store.rewind_upload("up", 10).unwrap();
+        assert_eq!(store.upload(user, "up").unwrap().unwrap().received, 10);
+        assert!(!store.claim_chunk("up", 20, 10, 4).unwrap());
+        assert!(store.claim_chunk("up", 10, 10, 4).unwrap());
+
+        // Resuming finds the same upload
+        let resumed = store.start_upload(user, "other", "hash", 30, 5).unwrap();
+        assert_eq!((resumed.id.as_str(), resumed.received), ("up", 20));
+    }
+}
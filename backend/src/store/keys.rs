@@ -0,0 +1,266 @@
+use super::Store;
+use rusqlite::{params, Transaction};
+use serde::{Deserialize, Serialize};
+
+/// A version of the user key, wrapped for one device.
+#[derive(Clone, Debug, Serialize)]
+pub struct WrappedKey {
+    pub version: i64,
+    pub wrapped_key: String,
+}
+
+/// An active device that cannot read some key versions yet.
+#[derive(Clone, Debug, Serialize)]
+pub struct MissingGrants {
+    pub device_id: String,
+    pub public_key: String,
+    pub versions: Vec<i64>,
+}
+
+/// A key version wrapped for a device by a client that holds it.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Grant {
+    pub version: i64,
+    pub device_id: String,
+    pub wrapped_key: String,
+}
+
+/// Wrapped user keys. The server never sees a key in the clear; it only
+/// relays what one device wrapped for another.
+impl Store {
+    pub fn current_key_version(&self, user_id: i64) -> rusqlite::Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT MAX(version) FROM key_versions WHERE user_id = ?1",
+            params![user_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Every key version wrapped for the device, oldest first.
+    pub fn device_keys(&self, user_id: i64, device_id: &str) -> rusqlite::Result<Vec<WrappedKey>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT version, wrapped_key FROM wrapped_keys
+             WHERE user_id = ?1 AND device_id = ?2 ORDER BY version",
+        )?;
+        let keys = stmt
+            .query_map(params![user_id, device_id], |row| {
+                Ok(WrappedKey {
+                    version: row.get(0)?,
+                    wrapped_key: row.get(1)?,
+                })
+            })?
+            .collect();
+        keys
+    }
+
+    /// Active devices with a public key that are missing some key versions.
+    pub fn missing_grants(&self, user_id: i64) -> rusqlite::Result<Vec<MissingGrants>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT d.id, d.public_key, k.version FROM devices d
+             JOIN key_versions k ON k.user_id = d.user_id
+             WHERE d.user_id = ?1 AND d.revoked_at IS NULL AND d.public_key IS NOT NULL
+               AND NOT EXISTS (
+                   SELECT 1 FROM wrapped_keys w
+                   WHERE w.user_id = d.user_id AND w.version = k.version AND w.device_id = d.id
+               )
+             ORDER BY d.id, k.version",
+        )?;
+        let mut rows = stmt.query(params![user_id])?;
+        let mut missing: Vec<MissingGrants> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let device_id: String = row.get(0)?;
+            let version: i64 = row.get(2)?;
+            match missing.last_mut() {
+                Some(last) if last.device_id == device_id => last.versions.push(version),
+                _ => missing.push(MissingGrants {
+                    device_id,
+                    public_key: row.get(1)?,
+                    versions: vec![version],
+                }),
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Whether a revoked device held the current key, which then has to be rotated.
+    pub fn rotation_needed(&self, user_id: i64) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS (
+                 SELECT 1 FROM wrapped_keys w JOIN devices d ON d.id = w.device_id
+                 WHERE w.user_id = ?1 AND d.revoked_at IS NOT NULL
+                   AND w.version = (SELECT MAX(version) FROM key_versions WHERE user_id = ?1)
+             )",
+            params![user_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Starts key version `version` with its initial grants. Returns `false`
+    /// if `version` does not directly follow the current version or a grant
+    /// names a device that is not an active device of the user.
+    pub fn add_key_version(
+        &self,
+        user_id: i64,
+        version: i64,
+        grants: &[Grant],
+        now: i64,
+    ) -> rusqlite::Result<bool> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let current: Option<i64> = tx.query_row(
+            "SELECT MAX(version) FROM key_versions WHERE user_id = ?1",
+            params![user_id],
+            |row| row.get(0),
+        )?;
+        if version != current.unwrap_or(0) + 1 {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT INTO key_versions (user_id, version, created_at) VALUES (?1, ?2, ?3)",
+            params![user_id, version, now],
+        )?;
+        if !insert_grants(&tx, user_id, grants)? {
+            return Ok(false);
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Stores grants for existing key versions, returning `false` (and storing
+    /// nothing) if any grant is invalid. A device keeps the key it was first
+    /// given: grants for keys it already holds are ignored.
+    pub fn add_grants(&self, user_id: i64, grants: &[Grant]) -> rusqlite::Result<bool> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        if !insert_grants(&tx, user_id, grants)? {
+            return Ok(false);
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+}
+
+fn insert_grants(tx: &Transaction, user_id: i64, grants: &[Grant]) -> rusqlite::Result<bool> {
+    for grant in grants {
+        // Only existing versions and active devices of the same user qualify
+        let valid: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM devices d
+             JOIN key_versions k ON k.user_id = d.user_id AND k.version = ?2
+             WHERE d.id = ?3 AND d.user_id = ?1 AND d.revoked_at IS NULL)",
+            params![user_id, grant.version, grant.device_id],
+            |row| row.get(0),
+        )?;
+        if !valid {
+            return Ok(false);
+        }
+        // Never replace a wrapped key: whoever can call the API must not be
+        // able to swap a device's key for one it cannot unwrap
+        tx.execute(
+            "INSERT OR IGNORE INTO wrapped_keys (user_id, version, device_id, wrapped_key)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![user_id, grant.version, grant.device_id, grant.wrapped_key],
+        )?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Device;
+
+    fn add_device(store: &Store, user_id: i64, id: &str, public_key: Option<&str>) {
+        let device = Device {
+            id: id.to_string(),
+            name: id.to_string(),
+            platform: None,
+            public_key: public_key.map(str::to_string),
+            created_at: 0,
+            last_seen: None,
+            revoked_at: None,
+            current: false,
+        };
+        store.create_device(user_id, &device).unwrap();
+    }
+
+    fn grant(version: i64, device_id: &str, wrapped_key: &str) -> Grant {
+        Grant {
+            version,
+            device_id: device_id.to_string(),
+            wrapped_key: wrapped_key.to_string(),
+        }
+    }
+
+    fn missing(store: &Store, user_id: i64) -> Vec<(String, Vec<i64>)> {
+        store
+            .missing_grants(user_id)
+            .unwrap()
+            .into_iter()
+            .map(|missing| (missing.device_id, missing.versions))
+            .collect()
+    }
+
+    #[test]
+    fn missing_grants_list_devices_without_keys() {
+        let store = Store::open_in_memory().unwrap();
+        let user = store.create_user("alice", None, 0).unwrap().unwrap().id;
+        add_device(&store, user, "laptop", Some("laptop-key"));
+        add_device(&store, user, "phone", Some("phone-key"));
+        add_device(&store, user, "old", Some("old-key"));
+        // Browsers have no key pair and are never listed
+        add_device(&store, user, "browser", None);
+        assert!(missing(&store, user).is_empty());
+
+        let first = [grant(1, "laptop", "k1")];
+        assert!(store.add_key_version(user, 1, &first, 1).unwrap());
+        let second = [grant(2, "laptop", "k2"), grant(2, "phone", "k2")];
+        assert!(store.add_key_version(user, 2, &second, 2).unwrap());
+        store.revoke_device(user, "old", 3).unwrap();
+        assert_eq!(missing(&store, user), [("phone".to_string(), vec![1])]);
+        assert!(!store.rotation_needed(user).unwrap());
+
+        assert!(store.add_grants(user, &[grant(1, "phone", "k1")]).unwrap());
+        assert!(missing(&store, user).is_empty());
+        // Existing grants are never replaced
+        assert!(store
+            .add_grants(user, &[grant(1, "phone", "evil")])
+            .unwrap());
+        let keys = store.device_keys(user, "phone").unwrap();
+        assert_eq!(keys[0].wrapped_key, "k1");
+
+        // Grants for revoked devices or unknown versions are rejected
+        assert!(!store.add_grants(user, &[grant(1, "old", "k1")]).unwrap());
+        assert!(!store.add_grants(user, &[grant(3, "phone", "k3")]).unwrap());
+
+        // Other users' devices never show up
+        let bob = store.create_user("bob", None, 0).unwrap().unwrap().id;
+        add_device(&store, bob, "bob-laptop", Some("bob-key"));
+        assert!(missing(&store, bob).is_empty());
+    }
+
+    #[test]
+    fn revoking_a_key_holder_asks_for_rotation() {
+        let store = Store::open_in_memory().unwrap();
+        let user = store.create_user("alice", None, 0).unwrap().unwrap().id;
+        add_device(&store, user, "laptop", Some("laptop-key"));
+        add_device(&store, user, "phone", Some("phone-key"));
+        let grants = [grant(1, "laptop", "k1"), grant(1, "phone", "k1")];
+        assert!(store.add_key_version(user, 1, &grants, 1).unwrap());
+        store.revoke_device(user, "phone", 2).unwrap();
+        assert!(store.rotation_needed(user).unwrap());
+
+        // Versions must follow on directly
+        assert!(!store
+            .add_key_version(user, 3, &[grant(3, "laptop", "k3")], 3)
+            .unwrap());
+        assert!(store
+            .add_key_version(user, 2, &[grant(2, "laptop", "k2")], 3)
+            .unwrap());
+        assert!(!store.rotation_needed(user).unwrap());
+        assert_eq!(store.current_key_version(user).unwrap(), Some(2));
+    }
+}
@@ -0,0 +1,304 @@
+use rusqlite::Connection;
+use std::{path::Path, sync::Mutex};
+
+mod blobs;
+mod clips;
+mod devices;
+mod files;
+mod keys;
+mod retention;
+mod search;
+mod users;
+
+pub use clips::Collection;
+pub use devices::Device;
+pub use files::Upload;
+pub use keys::{Grant, MissingGrants, WrappedKey};
+pub use retention::Retention;
+pub use search::Search;
+pub use users::{ApiToken, User};
+
+/// Schema migrations, applied in order and tracked through `PRAGMA user_version`.
+///
+/// Never edit an entry once it has shipped; append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // v1: initial clip table
+    r#"
+    CREATE TABLE clips (
+        id        INTEGER PRIMARY KEY AUTOINCREMENT,
+        item_type TEXT    NOT NULL,
+        content   TEXT    NOT NULL,
+        mime_type TEXT,
+        timestamp INTEGER NOT NULL,
+        device    TEXT,
+        username  TEXT,
+        size      INTEGER NOT NULL
+    );
+    CREATE INDEX clips_timestamp ON clips (timestamp);
+    "#,
+    // v2: change log backing the event stream, seeded with the existing history
+    r#"
+    CREATE TABLE events (
+        seq     INTEGER PRIMARY KEY AUTOINCREMENT,
+        kind    TEXT    NOT NULL,
+        clip_id INTEGER
+    );
+    INSERT INTO events (kind, clip_id) SELECT 'created', id FROM clips ORDER BY id;
+    "#,
+    // v3: user accounts; clips and events become per-user (existing rows are
+    // adopted by the first account that gets created)
+    r#"
+    CREATE TABLE users (
+        id            INTEGER PRIMARY KEY AUTOINCREMENT,
+        username      TEXT    NOT NULL UNIQUE,
+        password_hash TEXT,
+        created_at    INTEGER NOT NULL
+    );
+    CREATE TABLE sessions (
+        token_hash TEXT    PRIMARY KEY,
+        user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE TABLE api_tokens (
+        id         INTEGER PRIMARY KEY AUTOINCREMENT,
+        user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        name       TEXT    NOT NULL,
+        token_hash TEXT    NOT NULL UNIQUE,
+        created_at INTEGER NOT NULL,
+        last_used  INTEGER
+    );
+    ALTER TABLE clips ADD COLUMN user_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+    ALTER TABLE events ADD COLUMN user_id INTEGER;
+    CREATE INDEX clips_user ON clips (user_id, id);
+    "#,
+    // v4: external (OpenID Connect) identities linked to accounts
+    r#"
+    CREATE TABLE user_identities (
+        issuer     TEXT    NOT NULL,
+        subject    TEXT    NOT NULL,
+        user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (issuer, subject)
+    );
+    "#,
+    // v5: registered devices; sessions, API tokens and clips can belong to one
+    r#"
+    CREATE TABLE devices (
+        id         TEXT    PRIMARY KEY,
+        user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        name       TEXT    NOT NULL,
+        platform   TEXT,
+        public_key TEXT,
+        created_at INTEGER NOT NULL,
+        last_seen  INTEGER,
+        revoked_at INTEGER
+    );
+    ALTER TABLE sessions ADD COLUMN device_id TEXT REFERENCES devices (id) ON DELETE CASCADE;
+    ALTER TABLE api_tokens ADD COLUMN device_id TEXT REFERENCES devices (id) ON DELETE CASCADE;
+    ALTER TABLE clips ADD COLUMN device_id TEXT REFERENCES devices (id) ON DELETE SET NULL;
+    "#,
+    // v6: end-to-end encryption; the server only keeps user keys wrapped per device
+    r#"
+    CREATE TABLE key_versions (
+        user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        version    INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (user_id, version)
+    );
+    CREATE TABLE wrapped_keys (
+        user_id     INTEGER NOT NULL,
+        version     INTEGER NOT NULL,
+        device_id   TEXT    NOT NULL REFERENCES devices (id) ON DELETE CASCADE,
+        wrapped_key TEXT    NOT NULL,
+        PRIMARY KEY (user_id, version, device_id),
+        FOREIGN KEY (user_id, version) REFERENCES key_versions (user_id, version) ON DELETE CASCADE
+    );
+    ALTER TABLE clips ADD COLUMN key_version INTEGER;
+    "#,
+    // v7: clips that delete themselves, e.g. captures flagged as sensitive
+    r#"
+    ALTER TABLE clips ADD COLUMN expires_at INTEGER;
+    CREATE INDEX clips_expiry ON clips (expires_at) WHERE expires_at IS NOT NULL;
+    "#,
+    // v8: the application a clip was copied from
+    r#"
+    ALTER TABLE clips ADD COLUMN source_app TEXT;
+    "#,
+    // v9: retention policies; pinned clips are exempt from them
+    r#"
+    ALTER TABLE clips ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE retention (
+        user_id      INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+        max_items    INTEGER,
+        max_age_days INTEGER,
+        max_bytes    INTEGER
+    );
+    "#,
+    // v10: favorites and snippet collections
+    r#"
+    ALTER TABLE clips ADD COLUMN favorite INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE clips ADD COLUMN collection TEXT;
+    CREATE INDEX clips_collection ON clips (user_id, collection) WHERE collection IS NOT NULL;
+    "#,
+    // v11: full-text index over unencrypted text and HTML; clip contents
+    // never change, so inserts and deletes are all it has to follow
+    r#"
+    CREATE VIRTUAL TABLE clips_fts USING fts5 (
+        content, content = 'clips', content_rowid = 'id', tokenize = 'trigram'
+    );
+    INSERT INTO clips_fts (rowid, content)
+        SELECT id, content FROM clips WHERE item_type IN ('text', 'html') AND key_version IS NULL;
+    CREATE TRIGGER clips_fts_insert AFTER INSERT ON clips
+        WHEN new.item_type IN ('text', 'html') AND new.key_version IS NULL
+    BEGIN
+        INSERT INTO clips_fts (rowid, content) VALUES (new.id, new.content);
+    END;
+    CREATE TRIGGER clips_fts_delete AFTER DELETE ON clips
+        WHEN old.item_type IN ('text', 'html') AND old.key_version IS NULL
+    BEGIN
+        INSERT INTO clips_fts (clips_fts, rowid, content) VALUES ('delete', old.id, old.content);
+    END;
+    "#,
+    // v12: repeated copies are merged by content hash; clips recorded before
+    // this have no hash, so only later copies are merged
+    r#"
+    ALTER TABLE clips ADD COLUMN content_hash TEXT;
+    ALTER TABLE clips ADD COLUMN copy_count INTEGER NOT NULL DEFAULT 1;
+    CREATE INDEX clips_content_hash ON clips (user_id, content_hash) WHERE content_hash IS NOT NULL;
+    "#,
+    // v13: files listed by file clips, uploaded in chunks; the contents live
+    // in the data directory under their SHA-256
+    r#"
+    CREATE TABLE files (
+        user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        hash       TEXT    NOT NULL,
+        size       INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (user_id, hash)
+    );
+    CREATE TABLE uploads (
+        id         TEXT    PRIMARY KEY,
+        user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        hash       TEXT    NOT NULL,
+        size       INTEGER NOT NULL,
+        received   INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        UNIQUE (user_id, hash)
+    );
+    "#,
+    // v14: content-addressed blob store for images and files. `clip_blobs`
+    // lists the blobs each clip references and triggers keep `blobs.refs` in
+    // step; `user_blobs` (formerly `files`) says who may read a blob.
+    // Existing images move out of `content` when the server starts.
+    r#"
+    CREATE TABLE blobs (
+        hash      TEXT    PRIMARY KEY,
+        size      INTEGER NOT NULL,
+        mime_type TEXT,
+        refs      INTEGER NOT NULL DEFAULT 0,
+        added_at  INTEGER NOT NULL
+    );
+    INSERT INTO blobs (hash, size, added_at)
+        SELECT hash, MAX(size), MAX(created_at) FROM files GROUP BY hash;
+    CREATE TABLE user_blobs (
+        user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        hash    TEXT    NOT NULL REFERENCES blobs (hash) ON DELETE CASCADE,
+        PRIMARY KEY (user_id, hash)
+    );
+    INSERT INTO user_blobs (user_id, hash) SELECT user_id, hash FROM files;
+    DROP TABLE files;
+    ALTER TABLE clips ADD COLUMN blob TEXT;
+    CREATE TABLE clip_blobs (
+        clip_id INTEGER NOT NULL REFERENCES clips (id) ON DELETE CASCADE,
+        hash    TEXT    NOT NULL REFERENCES blobs (hash),
+        PRIMARY KEY (clip_id, hash)
+    );
+    CREATE INDEX clip_blobs_hash ON clip_blobs (hash);
+    CREATE TRIGGER clip_blobs_insert AFTER INSERT ON clip_blobs
+    BEGIN
+        UPDATE blobs SET refs = refs + 1 WHERE hash = new.hash;
+    END;
+    CREATE TRIGGER clip_blobs_delete AFTER DELETE ON clip_blobs
+    BEGIN
+        UPDATE blobs SET refs = refs - 1 WHERE hash = old.hash;
+    END;
+    INSERT OR IGNORE INTO clip_blobs (clip_id, hash)
+        SELECT c.id, json_extract(f.value, '$.hash') FROM clips c, json_each(c.content) f
+        WHERE c.item_type = 'file' AND json_extract(f.value, '$.hash') IN (SELECT hash FROM blobs);
+    "#,
+    // v15: previews of image clips. The thumbnail is a blob of the clip and
+    // is listed in `clip_blobs`; existing images get theirs at startup.
+    r#"
+    ALTER TABLE clips ADD COLUMN thumbnail TEXT;
+    ALTER TABLE clips ADD COLUMN blurhash TEXT;
+    ALTER TABLE clips ADD COLUMN width INTEGER;
+    ALTER TABLE clips ADD COLUMN height INTEGER;
+    "#,
+    // v16: HTML clips are searched by their text rather than their markup.
+    // `search_text` holds it (text clips are their own text), and the index
+    // no longer reads `content`, so it becomes contentless. Existing HTML
+    // clips get their text at startup.
+    r#"
+    ALTER TABLE clips ADD COLUMN search_text TEXT;
+    DROP TRIGGER clips_fts_insert;
+    DROP TRIGGER clips_fts_delete;
+    DROP TABLE clips_fts;
+    CREATE VIRTUAL TABLE clips_fts USING fts5 (
+        content, content = '', contentless_delete = 1, tokenize = 'trigram'
+    );
+    INSERT INTO clips_fts (rowid, content)
+        SELECT id, content FROM clips WHERE item_type = 'text' AND key_version IS NULL;
+    CREATE TRIGGER clips_fts_insert AFTER INSERT ON clips
+        WHEN new.key_version IS NULL
+            AND (new.item_type = 'text' OR (new.item_type = 'html' AND new.search_text IS NOT NULL))
+    BEGIN
+        INSERT INTO clips_fts (rowid, content)
+            VALUES (new.id, COALESCE(new.search_text, new.content));
+    END;
+    CREATE TRIGGER clips_fts_delete AFTER DELETE ON clips
+    BEGIN
+        DELETE FROM clips_fts WHERE rowid = old.id;
+    END;
+    "#,
+];
+
+/// Durable clipboard history backed by an embedded SQLite database.
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    /// Opens (or creates) `history.db` inside `data_dir` and brings the schema up to date.
+    pub fn open(data_dir: &Path) -> rusqlite::Result<Self> {
+        let mut conn = Connection::open(data_dir.join("history.db"))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// A fresh store that lives only as long as it does, for tests.
+    #[cfg(test)]
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        let mut conn = Connection::open_in_memory()?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+        tracing::info!("applied schema migration v{}", index + 1);
+    }
+    Ok(())
+}
@@ -0,0 +1,264 @@
+use super::Store;
+use crate::events::ClipEvent;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// How much history a user keeps. Every limit is optional; pinned and
+/// favorite clips and clips in a collection count towards none of them and
+/// are never removed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retention {
+    /// Keep only the newest this many clips.
+    pub max_items: Option<usize>,
+    /// Delete clips older than this.
+    pub max_age_days: Option<u32>,
+    /// Keep only the newest clips whose contents add up to at most this many bytes.
+    pub max_bytes: Option<u64>,
+}
+
+impl Retention {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_items.is_none() && self.max_age_days.is_none() && self.max_bytes.is_none()
+    }
+}
+
+/// Per-user retention policies. Users without one follow the server default.
+impl Store {
+    pub fn retention(&self, user_id: i64) -> rusqlite::Result<Option<Retention>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT max_items, max_age_days, max_bytes FROM retention WHERE user_id = ?1",
+            params![user_id],
+            |row| {
+                Ok(Retention {
+                    max_items: row.get::<_, Option<i64>>(0)?.map(|n| n as usize),
+                    max_age_days: row.get(1)?,
+                    max_bytes: row.get::<_, Option<i64>>(2)?.map(|n| n as u64),
+                })
+            },
+        )
+        .optional()
+    }
+
+    pub fn set_retention(&self, user_id: i64, retention: &Retention) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO retention (user_id, max_items, max_age_days, max_bytes)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (user_id) DO UPDATE SET
+                 max_items = excluded.max_items,
+                 max_age_days = excluded.max_age_days,
+                 max_bytes = excluded.max_bytes",
+            params![
+                user_id,
+                retention.max_items.map(|n| to_sql(n as u64)),
+                retention.max_age_days,
+                retention.max_bytes.map(to_sql),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Drops the user's own policy so the server default applies again.
+    pub fn reset_retention(&self, user_id: i64) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM retention WHERE user_id = ?1", params![user_id])?;
+        Ok(())
+    }
+
+    /// Deletes the clips of every user that fall outside their policy, or
+    /// `default` for users without one.
+    pub fn compact(&self, default: &Retention, now: i64) -> rusqlite::Result<Vec<ClipEvent>> {
+        let users = {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn.prepare(
+                "SELECT DISTINCT c.user_id, r.user_id IS NOT NULL, r.max_items, r.max_age_days, r.max_bytes
+                 FROM clips c LEFT JOIN retention r ON r.user_id = c.user_id
+                 WHERE c.user_id IS NOT NULL",
+            )?;
+            let rows = stmt
+                .query_map([], |row| {
+                    let own: bool = row.get(1)?;
+                    let retention = if own {
+                        Retention {
+                            max_items: row.get::<_, Option<i64>>(2)?.map(|n| n as usize),
+                            max_age_days: row.get(3)?,
+                            max_bytes: row.get::<_, Option<i64>>(4)?.map(|n| n as u64),
+                        }
+                    } else {
+                        default.clone()
+                    };
+                    Ok((row.get(0)?, retention))
+                })?
+                .collect::<rusqlite::Result<Vec<(i64, Retention)>>>()?;
+            rows
+        };
+
+        let mut events = Vec::new();
+        for (user_id, retention) in users {
+            if retention.is_unlimited() {
+                continue;
+            }
+            for id in self.outside_retention(user_id, &retention, now)? {
+                events.extend(self.delete(user_id, id)?);
+            }
+        }
+        Ok(events)
+    }
+
+    /// Ids of the user's ordinary clips beyond any of the limits.
+    fn outside_retention(
+        &self,
+        user_id: i64,
+        retention: &Retention,
+        now: i64,
+    ) -> rusqlite::Result<Vec<i64>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id FROM (
+                 SELECT id, timestamp,
+                     ROW_NUMBER() OVER newest AS position,
+                     SUM(size) OVER newest AS running_bytes
+                 FROM clips
+                 WHERE user_id = ?1 AND pinned = 0 AND favorite = 0 AND collection IS NULL
+                 WINDOW newest AS (ORDER BY timestamp DESC, id DESC)
+             )
+             WHERE position > ?2 OR timestamp < ?3 OR running_bytes > ?4",
+        )?;
+        let ids = stmt
+            .query_map(
+                params![
+                    user_id,
+                    retention.max_items.map_or(i64::MAX, |n| to_sql(n as u64)),
+                    retention
+                        .max_age_days
+                        .map_or(i64::MIN, |days| now - days as i64 * MILLIS_PER_DAY),
+                    retention.max_bytes.map_or(i64::MAX, to_sql),
+                ],
+                |row| row.get(0),
+            )?
+            .collect();
+        ids
+    }
+}
+
+/// A limit as an SQLite integer. Limits past `i64::MAX` cannot be reached, so
+/// they saturate instead of wrapping around to a negative limit that would
+/// delete everything.
+fn to_sql(limit: u64) -> i64 {
+    i64::try_from(limit).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clips::{ClipFilter, ClipUpdate, ClipboardItem};
+    use serde_json::json;
+
+    /// A user with clips `0..count` of `size` bytes each, copied in that order.
+    fn history(count: i64, size: usize) -> (Store, i64, Vec<i64>) {
+        let store = Store::open_in_memory().unwrap();
+        let user = store.create_user("alice", None, 0).unwrap().unwrap().id;
+        let ids = (0..count)
+            .map(|n| {
+                let item: ClipboardItem = serde_json::from_value(json!({
+                    "type": "text",
+                    "content": n.to_string(),
+                    "timestamp": n,
+                    "size": size,
+                }))
+                .unwrap();
+                store.insert(user, item).unwrap().0.id
+            })
+            .collect();
+        (store, user, ids)
+    }
+
+    fn outside(store: &Store, user: i64, retention: &Retention, now: i64) -> Vec<i64> {
+        let mut ids = store.outside_retention(user, retention, now).unwrap();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn count_limit_keeps_the_newest_clips() {
+        let (store, user, ids) = history(5, 1);
+        let retention = Retention {
+            max_items: Some(2),
+            ..Retention::default()
+        };
+        assert_eq!(outside(&store, user, &retention, 10), ids[..3]);
+    }
+
+    #[test]
+    fn byte_limit_keeps_the_newest_clips() {
+        let (store, user, ids) = history(5, 10);
+        let retention = Retention {
+            max_bytes: Some(25),
+            ..Retention::default()
+        };
+        assert_eq!(outside(&store, user, &retention, 10), ids[..3]);
+    }
+
+    #[test]
+    fn age_limit_drops_old_clips() {
+        let (store, user, ids) = history(3, 1);
+        let retention = Retention {
+            max_age_days: Some(1),
+            ..Retention::default()
+        };
+        assert!(outside(&store, user, &retention, 10).is_empty());
+        let later = outside(&store, user, &retention, 2 + MILLIS_PER_DAY);
+        assert_eq!(later, ids[..2]);
+    }
+
+    #[test]
+    fn kept_clips_are_exempt() {
+        let (store, user, ids) = history(5, 1);
+        let keep = [
+            json!({ "pinned": true }),
+            json!({ "favorite": true }),
+            json!({ "collection": "snippets" }),
+        ];
+        for (id, update) in ids.iter().zip(keep) {
+            let update: ClipUpdate = serde_json::from_value(update).unwrap();
+            store.update(user, *id, &update).unwrap();
+        }
+        let retention = Retention {
+            max_items: Some(1),
+            max_bytes: Some(1),
+            ..Retention::default()
+        };
+        // Only ordinary clips count, so the newest of them stays
+        assert_eq!(outside(&store, user, &retention, 10), ids[3..4]);
+
+        let events = store.compact(&retention, 10).unwrap();
+        assert_eq!(events.len(), 1);
+        let all = ClipFilter {
+            pinned: false,
+            favorite: false,
+            collection: None,
+        };
+        assert_eq!(store.list(user, &all, 0, 10).unwrap().1, 4);
+    }
+
+    #[test]
+    fn huge_limits_are_unlimited() {
+        let (store, user, _) = history(3, 1);
+        let retention = Retention {
+            max_items: Some(usize::MAX),
+            max_age_days: None,
+            max_bytes: Some(u64::MAX),
+        };
+        assert!(outside(&store, user, &retention, 10).is_empty());
+        store.set_retention(user, &retention).unwrap();
+        assert!(store.compact(&Retention::default(), 10).unwrap().is_empty());
+        assert_eq!(
+            store.retention(user).unwrap().unwrap().max_bytes,
+            Some(i64::MAX as u64)
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use super::{
+    clips::{clip_from_row, CLIPS, CLIP_COLUMNS},
+    Store,
+};
+use crate::clips::Clip;
+use rusqlite::{params, params_from_iter, types::Value, OptionalExtension};
+
+/// The trigram index only answers terms of at least this many characters;
+/// shorter ones are matched by scanning.
+const MIN_INDEXED_TERM: usize = 3;
+
+/// A history search. Every part is optional; an empty search lists everything.
+pub struct Search {
+    /// Whitespace-separated terms that must all occur in the content.
+    pub query: String,
+    pub item_type: Option<String>,
+    /// Device name or id.
+    pub device: Option<String>,
+    pub username: Option<String>,
+    /// Inclusive lower bound of the timestamp, in Unix milliseconds.
+    pub from: Option<i64>,
+    /// Exclusive upper bound of the timestamp, in Unix milliseconds.
+    pub to: Option<i64>,
+}
+
+/// Full-text search over the unencrypted text and HTML clips.
+impl Store {
+    /// Returns a page of the matching clips, most recently copied first,
+    /// together with the total count. Encrypted clips only match searches
+    /// without terms.
+    pub fn search(
+        &self,
+        user_id: i64,
+        search: &Search,
+        offset: usize,
+        limit: usize,
+    ) -> rusqlite::Result<(Vec<Clip>, usize)> {
+        let mut conditions = vec!["c.user_id = ?"];
+        let mut values = vec![Value::from(user_id)];
+
+        let (indexed, short): (Vec<&str>, Vec<&str>) = search
+            .query
+            .split_whitespace()
+            .partition(|term| term.chars().count() >= MIN_INDEXED_TERM);
+        if !indexed.is_empty() {
+            conditions.push("c.id IN (SELECT rowid FROM clips_fts WHERE clips_fts MATCH ?)");
+            let phrases = indexed
+                .iter()
+                .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+                .collect::<Vec<_>>();
+            values.push(Value::from(phrases.join(" ")));
+        }
+        for term in short {
+            conditions.push(
+                "c.item_type IN ('text', 'html') AND c.key_version IS NULL
+                 AND instr(lower(COALESCE(c.search_text, c.content)), lower(?)) > 0",
+            );
+            values.push(Value::from(term.to_string()));
+        }
+        if let Some(item_type) = &search.item_type {
+            conditions.push("c.item_type = ?");
+            values.push(Value::from(item_type.clone()));
+        }
+        if let Some(device) = &search.device {
+            conditions.push("(COALESCE(d.name, c.device) = ? OR c.device_id = ?)");
+            values.push(Value::from(device.clone()));
+            values.push(Value::from(device.clone()));
+        }
+        if let Some(username) = &search.username {
+            conditions.push("c.username = ?");
+            values.push(Value::from(username.clone()));
+        }
+        if let Some(from) = search.from {
+            conditions.push("c.timestamp >= ?");
+            values.push(Value::from(from));
+        }
+        if let Some(to) = search.to {
+            conditions.push("c.timestamp < ?");
+            values.push(Value::from(to));
+        }
+        let filter = conditions.join(" AND ");
+
+        let conn = self.conn.lock().unwrap();
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM {CLIPS} WHERE {filter}"),
+            params_from_iter(&values),
+            |row| row.get(0),
+        )?;
+        values.push(Value::from(limit as i64));
+        values.push(Value::from(offset as i64));
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {CLIP_COLUMNS} FROM {CLIPS} WHERE {filter}
+             ORDER BY c.timestamp DESC, c.id DESC LIMIT ? OFFSET ?"
+        ))?;
+        let items = stmt
+            .query_map(params_from_iter(&values), clip_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok((items, total as usize))
+    }
+
+    /// The next unencrypted HTML clip after `after` that is not indexed by
+    /// its text yet, as its id and markup.
+    pub fn next_html_without_text(&self, after: i64) -> rusqlite::Result<Option<(i64, String)>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id, content FROM clips
+             WHERE id > ?1 AND item_type = 'html' AND key_version IS NULL AND search_text IS NULL
+             ORDER BY id LIMIT 1",
+            params![after],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+    }
+
+    /// Records and indexes the text of an HTML clip found by
+    /// `next_html_without_text`.
+    pub fn set_search_text(&self, id: i64, text: &str) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let updated = tx.execute(
+            "UPDATE clips SET search_text = ?2
+             WHERE id = ?1 AND item_type = 'html' AND key_version IS NULL AND search_text IS NULL",
+            params![id, text],
+        )?;
+        if updated > 0 {
+            tx.execute(
+                "INSERT INTO clips_fts (rowid, content) VALUES (?1, ?2)",
+                params![id, text],
+            )?;
+        }
+        tx.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clips::ClipboardItem;
+    use serde_json::json;
+    use synapse_sensitive::html;
+
+    fn search(store: &Store, user: i64, query: &str) -> Vec<i64> {
+        let search = Search {
+            query: query.to_string(),
+            item_type: None,
+            device: None,
+            username: None,
+            from: None,
+            to: None,
+        };
+        let (items, _) = store.search(user, &search, 0, 10).unwrap();
+        items.into_iter().map(|clip| clip.id).collect()
+    }
+
+    #[test]
+    fn html_is_searched_by_its_text() {
+        let store = Store::open_in_memory().unwrap();
+        let user = store.create_user("alice", None, 0).unwrap().unwrap().id;
+        let html: ClipboardItem = serde_json::from_value(json!({
+            "type": "html",
+            "content": r#"<div class="note"><a href="https://example.com">Fish &amp; chips</a></div>"#,
+        }))
+        .unwrap();
+        let (html, _) = store.insert(user, html).unwrap();
+        let text: ClipboardItem =
+            serde_json::from_value(json!({ "type": "text", "content": "a div class" })).unwrap();
+        let (text, _) = store.insert(user, text).unwrap();
+
+        assert_eq!(search(&store, user, "chips"), [html.id]);
+        assert_eq!(search(&store, user, "fish & chips"), [html.id]);
+        // Markup only matches clips that contain it as text
+        assert_eq!(search(&store, user, "class"), [text.id]);
+        assert!(search(&store, user, "href").is_empty());
+        assert_eq!(search(&store, user, "a div"), [text.id]);
+
+        store.delete(user, html.id).unwrap();
+        assert!(search(&store, user, "chips").is_empty());
+    }
+
+    #[test]
+    fn older_html_is_indexed_once() {
+        let store = Store::open_in_memory().unwrap();
+        let user = store.create_user("alice", None, 0).unwrap().unwrap().id;
+        // An HTML clip as stored before its text was recorded
+        store
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO clips (item_type, content, timestamp, size, user_id)
+                 VALUES ('html', '<b>bold</b> <i>move</i>', 1, 23, ?1)",
+                params![user],
+            )
+            .unwrap();
+        assert!(search(&store, user, "bold").is_empty());
+
+        let (id, content) = store.next_html_without_text(0).unwrap().unwrap();
+        store.set_search_text(id, &html::to_text(&content)).unwrap();
+        assert_eq!(search(&store, user, "bold move"), [id]);
+        assert!(store.next_html_without_text(0).unwrap().is_none());
+        // Indexing it again changes nothing
+        store.set_search_text(id, "other").unwrap();
+        assert!(search(&store, user, "other").is_empty());
+    }
+}
@@ -0,0 +1,346 @@
+use super::Store;
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Row};
+use serde::Serialize;
+
+/// Minimum time between recorded uses of a token or device.
+const TOUCH_INTERVAL_MS: i64 = 60 * 1000;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    /// Device the request was authenticated for, if it is bound to one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+}
+
+/// API token metadata; the token itself is only ever shown once, at creation.
+#[derive(Clone, Debug, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub created_at: i64,
+    pub last_used: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+}
+
+/// Accounts, browser sessions and API tokens. Secrets are stored as hashes only.
+impl Store {
+    pub fn user_count(&self) -> rusqlite::Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
+    }
+
+    /// Creates an account. The very first account adopts history recorded
+    /// before accounts existed. Returns `None` if the username is taken,
+    /// including by a registration that raced this one.
+    pub fn create_user(
+        &self,
+        username: &str,
+        password_hash: Option<&str>,
+        now: i64,
+    ) -> rusqlite::Result<Option<User>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let inserted = tx.execute(
+            "INSERT INTO users (username, password_hash, created_at) VALUES (?1, ?2, ?3)",
+            params![username, password_hash, now],
+        );
+        match inserted {
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                return Ok(None)
+            }
+            result => result?,
+        };
+        let id = tx.last_insert_rowid();
+        let existing: i64 = tx.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+        if existing == 1 {
+            tx.execute(
+                "UPDATE clips SET user_id = ?1 WHERE user_id IS NULL",
+                params![id],
+            )?;
+            tx.execute(
+                "UPDATE events SET user_id = ?1 WHERE user_id IS NULL",
+                params![id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(Some(User {
+            id,
+            username: username.to_string(),
+            device_id: None,
+        }))
+    }
+
+    /// Looks up an account together with its password hash, if it has one.
+    pub fn user_by_name(&self, username: &str) -> rusqlite::Result<Option<(User, Option<String>)>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id, username, password_hash FROM users WHERE username = ?1",
+            params![username],
+            |row| {
+                Ok((
+                    User {
+                        id: row.get(0)?,
+                        username: row.get(1)?,
+                        device_id: None,
+                    },
+                    row.get(2)?,
+                ))
+            },
+        )
+        .optional()
+    }
+
+    /// Resolves the account linked to an external identity.
+    pub fn identity_user(&self, issuer: &str, subject: &str) -> rusqlite::Result<Option<User>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT u.id, u.username FROM user_identities i JOIN users u ON u.id = i.user_id
+             WHERE i.issuer = ?1 AND i.subject = ?2",
+            params![issuer, subject],
+            |row| {
+                Ok(User {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    device_id: None,
+                })
+            },
+        )
+        .optional()
+    }
+
+    pub fn link_identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+        user_id: i64,
+        now: i64,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO user_identities (issuer, subject, user_id, created_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![issuer, subject, user_id, now],
+        )?;
+        Ok(())
+    }
+
+    pub fn create_session(
+        &self,
+        user_id: i64,
+        token_hash: &str,
+        expires_at: i64,
+        device_id: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO sessions (token_hash, user_id, expires_at, device_id)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![token_hash, user_id, expires_at, device_id],
+        )?;
+        Ok(())
+    }
+
+    /// Binds an existing session to a device of its user.
+    pub fn bind_session(&self, token_hash: &str, device_id: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET device_id = ?1 WHERE token_hash = ?2",
+            params![device_id, token_hash],
+        )?;
+        Ok(())
+    }
+
+    /// Resolves an unexpired session.
+    pub fn session_user(&self, token_hash: &str, now: i64) -> rusqlite::Result<Option<User>> {
+        let conn = self.conn.lock().unwrap();
+        let found = conn
+            .query_row(
+                "SELECT u.id, u.username, s.device_id, d.last_seen FROM sessions s
+                 JOIN users u ON u.id = s.user_id
+                 LEFT JOIN devices d ON d.id = s.device_id
+                 WHERE s.token_hash = ?1 AND s.expires_at > ?2",
+                params![token_hash, now],
+                |row| Ok((user_from_row(row)?, row.get::<_, Option<i64>>(3)?)),
+            )
+            .optional()?;
+        let Some((user, last_seen)) = found else {
+            return Ok(None);
+        };
+        touch_device(&conn, &user, last_seen, now)?;
+        Ok(Some(user))
+    }
+
+    /// Deletes the sessions that expired before `now`, returning how many.
+    pub fn delete_expired_sessions(&self, now: i64) -> rusqlite::Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM sessions WHERE expires_at <= ?1", params![now])
+    }
+
+    pub fn delete_session(&self, token_hash: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM sessions WHERE token_hash = ?1",
+            params![token_hash],
+        )?;
+        Ok(())
+    }
+
+    pub fn create_api_token(
+        &self,
+        user_id: i64,
+        name: &str,
+        token_hash: &str,
+        now: i64,
+        device_id: Option<&str>,
+    ) -> rusqlite::Result<ApiToken> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO api_tokens (user_id, name, token_hash, created_at, device_id)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![user_id, name, token_hash, now, device_id],
+        )?;
+        Ok(ApiToken {
+            id: conn.last_insert_rowid(),
+            name: name.to_string(),
+            created_at: now,
+            last_used: None,
+            device_id: device_id.map(str::to_string),
+        })
+    }
+
+    /// Resolves the owner of an API token and records its use.
+    pub fn api_token_user(&self, token_hash: &str, now: i64) -> rusqlite::Result<Option<User>> {
+        let conn = self.conn.lock().unwrap();
+        let found = conn
+            .query_row(
+                "SELECT u.id, u.username, t.device_id, t.last_used, d.last_seen FROM api_tokens t
+                 JOIN users u ON u.id = t.user_id
+                 LEFT JOIN devices d ON d.id = t.device_id
+                 WHERE t.token_hash = ?1",
+                params![token_hash],
+                |row| {
+                    Ok((
+                        user_from_row(row)?,
+                        row.get::<_, Option<i64>>(3)?,
+                        row.get::<_, Option<i64>>(4)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((user, last_used, last_seen)) = found else {
+            return Ok(None);
+        };
+        if is_stale(last_used, now) {
+            conn.execute(
+                "UPDATE api_tokens SET last_used = ?1 WHERE token_hash = ?2",
+                params![now, token_hash],
+            )?;
+        }
+        touch_device(&conn, &user, last_seen, now)?;
+        Ok(Some(user))
+    }
+
+    pub fn list_api_tokens(&self, user_id: i64) -> rusqlite::Result<Vec<ApiToken>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, name, created_at, last_used, device_id FROM api_tokens
+             WHERE user_id = ?1 ORDER BY id",
+        )?;
+        let tokens = stmt
+            .query_map(params![user_id], |row| {
+                Ok(ApiToken {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    created_at: row.get(2)?,
+                    last_used: row.get(3)?,
+                    device_id: row.get(4)?,
+                })
+            })?
+            .collect();
+        tokens
+    }
+
+    /// Revokes an API token, returning `false` if the user has no such token.
+    pub fn delete_api_token(&self, user_id: i64, id: i64) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "DELETE FROM api_tokens WHERE id = ?1 AND user_id = ?2",
+            params![id, user_id],
+        )? > 0)
+    }
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        device_id: row.get(2)?,
+    })
+}
+
+/// Records that the device behind an authenticated request was just seen.
+fn touch_device(
+    conn: &Connection,
+    user: &User,
+    last_seen: Option<i64>,
+    now: i64,
+) -> rusqlite::Result<()> {
+    if let (Some(device_id), true) = (&user.device_id, is_stale(last_seen, now)) {
+        conn.execute(
+            "UPDATE devices SET last_seen = ?1 WHERE id = ?2",
+            params![now, device_id],
+        )?;
+    }
+    Ok(())
+}
+
+/// Whether a last-use time is old enough to record a newer one. Updating it
+/// at most once a minute keeps ordinary requests from writing to the database.
+fn is_stale(last: Option<i64>, now: i64) -> bool {
+    last.is_none_or(|last| now - last >= TOUCH_INTERVAL_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taken_usernames_are_reported_not_failed() {
+        let store = Store::open_in_memory().unwrap();
+        let alice = store.create_user("alice", Some("hash"), 1).unwrap();
+        assert!(alice.is_some());
+        assert!(store
+            .create_user("alice", Some("other"), 2)
+            .unwrap()
+            .is_none());
+        assert_eq!(store.user_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn identities_are_linked_per_issuer() {
+        let store = Store::open_in_memory().unwrap();
+        let alice = store.create_user("alice", None, 1).unwrap().unwrap();
+        assert!(store.identity_user("https://idp", "123").unwrap().is_none());
+
+        store
+            .link_identity("https://idp", "123", alice.id, 2)
+            .unwrap();
+        let linked = store.identity_user("https://idp", "123").unwrap().unwrap();
+        assert_eq!((linked.id, linked.username.as_str()), (alice.id, "alice"));
+        // The same subject at another provider is someone else
+        assert!(store
+            .identity_user("https://other", "123")
+            .unwrap()
+            .is_none());
+        // An identity cannot be moved to another account
+        let bob = store.create_user("bob", None, 3).unwrap().unwrap();
+        assert!(store
+            .link_identity("https://idp", "123", bob.id, 4)
+            .is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! Thumbnails and blurhash placeholders for image clips.
+//!
+//! When an image clip is stored the server decodes its blob once and records
+//! the image's dimensions and a [blurhash](https://blurha.sh), a string of a
+//! few dozen characters that clients draw as a blurred placeholder. Images
+//! larger than `THUMBNAIL_SIZE` also get a downscaled PNG, stored as another
+//! blob of the clip, which the history list loads instead of the original.
+//! Images in formats the server cannot decode (anything but PNG and JPEG), or
+//! too large to decode within `MAX_DECODE_BYTES`, get no preview and are shown
+//! as before.
+
+use crate::{
+    blobs,
+    clips::{now_millis, ClipboardItem},
+    AppState,
+};
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageReader, Limits, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::{f32::consts::PI, io::Cursor};
+
+/// Longest side of a thumbnail, in pixels.
+const THUMBNAIL_SIZE: u32 = 320;
+/// Memory a decoder may allocate for one image; enough for a 4K screenshot
+/// at 16 bits per channel. The default allows 512 MiB.
+const MAX_DECODE_BYTES: u64 = 128 * 1024 * 1024;
+/// Longest side of an image the server decodes.
+const MAX_DECODE_SIDE: u32 = 16_384;
+/// The blurhash is computed from a copy at most this large; the placeholder
+/// has no detail to lose.
+const BLURHASH_SOURCE_SIZE: u32 = 32;
+/// Horizontal and vertical blurhash components.
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// What the server derived from an image clip.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImagePreview {
+    /// Hash of the thumbnail blob; images no larger than a thumbnail have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+    pub blurhash: String,
+    pub width: u32,
+    pub height: u32,
+    /// Size of the thumbnail, or of the image itself when it has none, so
+    /// clients can reserve the space before it loads.
+    #[serde(default)]
+    pub thumbnail_width: u32,
+    #[serde(default)]
+    pub thumbnail_height: u32,
+}
+
+impl ImagePreview {
+    pub fn new(thumbnail: Option<String>, blurhash: String, width: u32, height: u32) -> Self {
+        let (thumbnail_width, thumbnail_height) = thumbnail_dimensions(width, height);
+        Self {
+            thumbnail,
+            blurhash,
+            width,
+            height,
+            thumbnail_width,
+            thumbnail_height,
+        }
+    }
+}
+
+/// Fills in the preview of an image clip whose payload is in the blob store.
+/// Images that cannot be decoded are stored without one.
+pub fn attach(state: &AppState, user_id: i64, item: &mut ClipboardItem) {
+    let Some(hash) = &item.blob else {
+        return;
+    };
+    match generate(state, user_id, hash) {
+        Ok(preview) => item.preview = Some(preview),
+        Err(e) => tracing::warn!("no preview for image {}: {}", hash, e),
+    }
+}
+
+/// Generates previews for the image clips stored before the server made them.
+pub fn backfill(state: &AppState) {
+    let mut after = 0;
+    let mut generated = 0;
+    loop {
+        let (id, user_id, hash) = match state.store.next_image_without_preview(after) {
+            Ok(Some(image)) => image,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::error!("failed to read image clips: {}", e);
+                break;
+            }
+        };
+        after = id;
+        let preview = match generate(state, user_id, &hash) {
+            Ok(preview) => preview,
+            // Undecodable images stay without a preview and are retried on the next start
+            Err(e) => {
+                tracing::debug!("no preview for image clip {}: {}", id, e);
+                continue;
+            }
+        };
+        match state.store.set_preview(id, &preview) {
+            Ok(()) => generated += 1,
+            Err(e) => tracing::error!("failed to record the preview of image clip {}: {}", id, e),
+        }
+    }
+    if generated > 0 {
+        tracing::info!("generated previews for {} image clips", generated);
+    }
+}
+
+/// Decodes an image a user stored, within `MAX_DECODE_BYTES` and
+/// `MAX_DECODE_SIDE`. Decoding is slow, so callers run it on a blocking thread.
+pub fn decode(
+    reader: ImageReader<impl std::io::BufRead + std::io::Seek>,
+) -> Result<DynamicImage, String> {
+    let mut reader = reader.with_guessed_format().map_err(|e| e.to_string())?;
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+    limits.max_image_width = Some(MAX_DECODE_SIDE);
+    limits.max_image_height = Some(MAX_DECODE_SIDE);
+    reader.limits(limits);
+    reader.decode().map_err(|e| e.to_string())
+}
+
+/// The size an image is scaled to for its thumbnail: its longest side
+/// becomes `THUMBNAIL_SIZE`, and smaller images keep their size.
+fn thumbnail_dimensions(width: u32, height: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= THUMBNAIL_SIZE {
+        return (width, height);
+    }
+    let scale = |side: u32| {
+        ((side as u64 * THUMBNAIL_SIZE as u64 + longest as u64 / 2) / longest as u64).max(1) as u32
+    };
+    (scale(width), scale(height))
+}
+
+fn generate(state: &AppState, user_id: i64, hash: &str) -> Result<ImagePreview, String> {
+    let path = blobs::blob_path(&state.config.data_dir, hash);
+    let image = decode(ImageReader::open(path).map_err(|e| e.to_string())?)?;
+    let (width, height) = image.dimensions();
+    let blurhash = blurhash(
+        &image
+            .thumbnail(BLURHASH_SOURCE_SIZE, BLURHASH_SOURCE_SIZE)
+            .to_rgb8(),
+    );
+    let thumbnail = if width.max(height) > THUMBNAIL_SIZE {
+        let (thumbnail_width, thumbnail_height) = thumbnail_dimensions(width, height);
+        let png = encode_png(&image.thumbnail_exact(thumbnail_width, thumbnail_height))?;
+        let thumbnail = blobs::put(state, &png).map_err(|e| e.to_string())?;
+        state
+            .store
+            .add_blob(
+                user_id,
+                &thumbnail,
+                png.len() as u64,
+                Some("image/png"),
+                now_millis(),
+            )
+            .map_err(|e| e.to_string())?;
+        Some(thumbnail)
+    } else {
+        None
+    };
+    Ok(ImagePreview::new(thumbnail, blurhash, width, height))
+}
+
+fn encode_png(image: &DynamicImage) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+/// Encodes an image as a blurhash: the average colour and a few cosine
+/// components of the image in linear RGB, quantised to base 83 digits.
+fn blurhash(image: &RgbImage) -> String {
+    let (components_x, components_y) = BLURHASH_COMPONENTS;
+    let (width, height) = image.dimensions();
+    let pixels: Vec<[f32; 3]> = image
+        .pixels()
+        .map(|pixel| pixel.0.map(srgb_to_linear))
+        .collect();
+
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0; 3];
+            for y in 0..height {
+                for x in 0..width {
+                    let basis = normalisation
+                        * (PI * i as f32 * x as f32 / width as f32).cos()
+                        * (PI * j as f32 * y as f32 / height as f32).cos();
+                    let pixel = pixels[(y * width + x) as usize];
+                    for (value, channel) in factor.iter_mut().zip(pixel) {
+                        *value += basis * channel;
+                    }
+                }
+            }
+            let scale = 1.0 / (width * height) as f32;
+            factors.push(factor.map(|value| value * scale));
+        }
+    }
+
+    let mut hash = String::new();
+    encode_base83((components_x - 1) + (components_y - 1) * 9, 1, &mut hash);
+    let (dc, ac) = factors.split_first().unwrap();
+    let maximum = ac
+        .iter()
+        .flatten()
+        .fold(0.0_f32, |maximum, value| maximum.max(value.abs()));
+    let (quantised_maximum, maximum) = if ac.is_empty() {
+        (0, 1.0)
+    } else {
+        let quantised = (maximum * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        (quantised, (quantised + 1) as f32 / 166.0)
+    };
+    encode_base83(quantised_maximum, 1, &mut hash);
+    let [r, g, b] = dc.map(linear_to_srgb);
+    encode_base83((r << 16) + (g << 8) + b, 4, &mut hash);
+    for factor in ac {
+        let [r, g, b] = factor.map(|value| {
+            let value = value / maximum;
+            (value.signum() * value.abs().sqrt() * 9.0 + 9.5)
+                .floor()
+                .clamp(0.0, 18.0) as u32
+        });
+        encode_base83(r * 19 * 19 + g * 19 + b, 2, &mut hash);
+    }
+    hash
+}
+
+fn encode_base83(value: u32, length: u32, out: &mut String) {
+    const DIGITS: &[u8] =
+        b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+    for position in (0..length).rev() {
+        let digit = value / 83_u32.pow(position) % 83;
+        out.push(DIGITS[digit as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u32 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hashes from an f64 port of the reference implementation
+    // (github.com/woltapp/blurhash) for the same images.
+
+    #[test]
+    fn encodes_solid_black() {
+        let image = RgbImage::new(8, 8);
+        assert_eq!(blurhash(&image), "L00000fQfQfQfQfQfQfQfQfQfQfQ");
+    }
+
+    #[test]
+    fn encodes_gradient() {
+        let image = RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8 * 8, y as u8 * 8, 128]));
+        assert_eq!(blurhash(&image), "LxH2cX2swxX8l}WDjte;gJfjfQfj");
+    }
+
+    #[test]
+    fn encodes_quadrants() {
+        let image = RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb(match (x < 16, y < 16) {
+                (true, true) => [255, 0, 0],
+                (false, true) => [0, 0, 255],
+                (true, false) => [0, 255, 0],
+                (false, false) => [255, 255, 0],
+            })
+        });
+        assert_eq!(blurhash(&image), "L~LqdfoWfOohm3[msSJDess9jsa#");
+    }
+
+    #[test]
+    fn scales_thumbnails_to_the_longest_side() {
+        assert_eq!(thumbnail_dimensions(1988, 1362), (320, 219));
+        assert_eq!(thumbnail_dimensions(100, 4000), (8, 320));
+        assert_eq!(thumbnail_dimensions(320, 200), (320, 200));
+    }
+}
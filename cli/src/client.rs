@@ -0,0 +1,446 @@
+use anyhow::{bail, Context, Result};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader};
+
+// Same JSON shape as the backend's clips
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClipboardItem {
+    #[serde(rename = "type")]
+    pub item_type: String,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_version: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_app: Option<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    #[serde(default = "first_copy")]
+    pub copy_count: u32,
+    /// Hash of the image in the server's blob store; `content` is empty then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
+fn first_copy() -> u32 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Clip {
+    pub id: i64,
+    #[serde(flatten)]
+    pub item: ClipboardItem,
+}
+
+/// Query parameters narrowing down a listing.
+#[derive(Debug, Default, Serialize)]
+pub struct ClipFilter {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub favorite: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+}
+
+/// Query parameters of a history search.
+#[derive(Debug, Default, Serialize)]
+pub struct Search {
+    pub q: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub item_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<i64>,
+}
+
+/// Changes to a clip; `None` leaves a field as it is.
+#[derive(Debug, Default, Serialize)]
+pub struct ClipUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favorite: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Collection {
+    pub name: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClipPage {
+    pub items: Vec<Clip>,
+    pub total: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    pub platform: Option<String>,
+    pub public_key: Option<String>,
+    pub last_seen: Option<i64>,
+    pub revoked_at: Option<i64>,
+    pub current: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisteredDevice {
+    #[serde(flatten)]
+    pub device: Device,
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WrappedKey {
+    pub version: i64,
+    pub wrapped_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MissingGrants {
+    pub device_id: String,
+    pub public_key: String,
+    pub versions: Vec<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyStatus {
+    pub current_version: Option<i64>,
+    pub rotation_needed: bool,
+    pub keys: Vec<WrappedKey>,
+    pub missing: Vec<MissingGrants>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Grant {
+    pub version: i64,
+    pub device_id: String,
+    pub wrapped_key: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Retention {
+    pub max_items: Option<usize>,
+    pub max_age_days: Option<u32>,
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RetentionStatus {
+    #[serde(flatten)]
+    pub retention: Retention,
+    pub default: bool,
+}
+
+/// One file of a file clip, whose content is a JSON array of them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// `/`-separated path relative to the folder the files are received in.
+    pub path: String,
+    pub size: u64,
+    /// Hex encoded SHA-256 of the contents.
+    pub hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadStatus {
+    /// Absent when the server already has the file.
+    pub id: Option<String>,
+    pub received: u64,
+    pub complete: bool,
+    pub chunk_size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    Created { clip: Box<Clip> },
+    Updated { clip: Box<Clip> },
+    Deleted { id: i64 },
+    Cleared,
+}
+
+/// Thin blocking client for the backend REST API.
+pub struct ApiClient {
+    http: Client,
+    base_url: String,
+}
+
+impl ApiClient {
+    /// Creates a client that authenticates every request with `token`, if given.
+    pub fn new(base_url: &str, token: Option<&str>) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = token {
+            let mut value = HeaderValue::from_str(&format!("Bearer {token}"))
+                .context("API token contains invalid characters")?;
+            value.set_sensitive(true);
+            headers.insert(header::AUTHORIZATION, value);
+        }
+        let http = Client::builder()
+            .default_headers(headers)
+            // `watch` keeps its response open indefinitely
+            .timeout(None)
+            .build()
+            .context("Failed to build HTTP client")?;
+        Ok(Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Logs in with a password and registers this machine as a new device,
+    /// returning it together with its device-bound API token.
+    pub fn register_device(
+        &self,
+        username: &str,
+        password: &str,
+        name: &str,
+        public_key: &str,
+    ) -> Result<RegisteredDevice> {
+        let credentials = serde_json::json!({ "username": username, "password": password });
+        let response = self
+            .http
+            .post(self.url("/api/auth/login"))
+            .json(&credentials)
+            .send();
+        let response = check(response)?;
+        // Reuse the session cookie just for this request
+        let session = response
+            .headers()
+            .get(header::SET_COOKIE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .context("Server did not return a session")?
+            .to_string();
+        let device = serde_json::json!({
+            "name": name,
+            "platform": std::env::consts::OS,
+            "public_key": public_key,
+            "issue_token": true,
+        });
+        let response = self
+            .http
+            .post(self.url("/api/devices"))
+            .header(header::COOKIE, &session)
+            .json(&device)
+            .send();
+        let registered = check(response)?.json()?;
+        // The session was only needed to register; the token replaces it
+        let _ = self
+            .http
+            .post(self.url("/api/auth/logout"))
+            .header(header::COOKIE, session)
+            .send();
+        Ok(registered)
+    }
+
+    pub fn devices(&self) -> Result<Vec<Device>> {
+        Ok(check(self.http.get(self.url("/api/devices")).send())?.json()?)
+    }
+
+    pub fn rename_device(&self, id: &str, name: &str) -> Result<Device> {
+        let response = self
+            .http
+            .patch(self.url(&format!("/api/devices/{id}")))
+            .json(&serde_json::json!({ "name": name }))
+            .send();
+        Ok(check(response)?.json()?)
+    }
+
+    pub fn revoke_device(&self, id: &str) -> Result<()> {
+        let response = self
+            .http
+            .delete(self.url(&format!("/api/devices/{id}")))
+            .send();
+        check(response)?;
+        Ok(())
+    }
+
+    pub fn key_status(&self) -> Result<KeyStatus> {
+        Ok(check(self.http.get(self.url("/api/keys")).send())?.json()?)
+    }
+
+    pub fn create_key_version(&self, version: i64, grants: &[Grant]) -> Result<()> {
+        let body = serde_json::json!({ "version": version, "grants": grants });
+        check(self.http.post(self.url("/api/keys")).json(&body).send())?;
+        Ok(())
+    }
+
+    pub fn add_grants(&self, grants: &[Grant]) -> Result<()> {
+        let body = serde_json::json!({ "grants": grants });
+        check(
+            self.http
+                .post(self.url("/api/keys/grants"))
+                .json(&body)
+                .send(),
+        )?;
+        Ok(())
+    }
+
+    pub fn retention(&self) -> Result<RetentionStatus> {
+        Ok(check(self.http.get(self.url("/api/retention")).send())?.json()?)
+    }
+
+    pub fn set_retention(&self, retention: &Retention) -> Result<RetentionStatus> {
+        let response = self
+            .http
+            .put(self.url("/api/retention"))
+            .json(retention)
+            .send();
+        Ok(check(response)?.json()?)
+    }
+
+    pub fn reset_retention(&self) -> Result<()> {
+        check(self.http.delete(self.url("/api/retention")).send())?;
+        Ok(())
+    }
+
+    pub fn list(&self, offset: usize, limit: usize, filter: &ClipFilter) -> Result<ClipPage> {
+        let response = self
+            .http
+            .get(self.url("/api/clips"))
+            .query(&[("offset", offset), ("limit", limit)])
+            .query(filter)
+            .send();
+        Ok(check(response)?.json()?)
+    }
+
+    pub fn search(&self, search: &Search, limit: usize) -> Result<ClipPage> {
+        let response = self
+            .http
+            .get(self.url("/api/search"))
+            .query(search)
+            .query(&[("limit", limit)])
+            .send();
+        Ok(check(response)?.json()?)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Clip> {
+        let response = self.http.get(self.url(&format!("/api/clips/{id}"))).send();
+        Ok(check(response)?.json()?)
+    }
+
+    pub fn create(&self, item: &ClipboardItem) -> Result<Clip> {
+        let response = self.http.post(self.url("/api/clips")).json(item).send();
+        Ok(check(response)?.json()?)
+    }
+
+    pub fn update(&self, id: i64, update: &ClipUpdate) -> Result<Clip> {
+        let response = self
+            .http
+            .patch(self.url(&format!("/api/clips/{id}")))
+            .json(update)
+            .send();
+        Ok(check(response)?.json()?)
+    }
+
+    pub fn collections(&self) -> Result<Vec<Collection>> {
+        Ok(check(self.http.get(self.url("/api/collections")).send())?.json()?)
+    }
+
+    pub fn delete(&self, id: i64) -> Result<()> {
+        let response = self
+            .http
+            .delete(self.url(&format!("/api/clips/{id}")))
+            .send();
+        check(response)?;
+        Ok(())
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        check(self.http.delete(self.url("/api/clips")).send())?;
+        Ok(())
+    }
+
+    /// Starts the upload of a file, or resumes the one already in progress.
+    pub fn start_upload(&self, hash: &str, size: u64) -> Result<UploadStatus> {
+        let body = serde_json::json!({ "hash": hash, "size": size });
+        let response = self.http.post(self.url("/api/uploads")).json(&body).send();
+        Ok(check(response)?.json()?)
+    }
+
+    pub fn upload_chunk(&self, id: &str, offset: u64, chunk: Vec<u8>) -> Result<UploadStatus> {
+        let response = self
+            .http
+            .put(self.url(&format!("/api/uploads/{id}")))
+            .query(&[("offset", offset)])
+            .body(chunk)
+            .send();
+        Ok(check(response)?.json()?)
+    }
+
+    /// Downloads a blob, an image or an uploaded file, from byte `offset` on.
+    pub fn download(&self, hash: &str, offset: u64) -> Result<Response> {
+        let mut request = self.http.get(self.url(&format!("/api/blobs/{hash}")));
+        if offset > 0 {
+            request = request.header(header::RANGE, format!("bytes={offset}-"));
+        }
+        check(request.send())
+    }
+
+    /// Follows the server event stream, calling `on_event` for every event.
+    pub fn watch(&self, mut on_event: impl FnMut(ServerEvent) -> Result<()>) -> Result<()> {
+        let response = check(self.http.get(self.url("/api/events")).send())?;
+        let mut data = String::new();
+        for line in BufReader::new(response).lines() {
+            let line = line.context("Event stream interrupted")?;
+            if let Some(chunk) = line.strip_prefix("data:") {
+                data.push_str(chunk.trim_start());
+            } else if line.is_empty() && !data.is_empty() {
+                let event = serde_json::from_str(&data)
+                    .with_context(|| format!("Failed to parse event: {data}"))?;
+                data.clear();
+                on_event(event)?;
+            }
+        }
+        bail!("Server closed the event stream")
+    }
+}
+
+fn check(response: reqwest::Result<Response>) -> Result<Response> {
+    let response = response.context("Failed to reach the Synapse server")?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = response
+        .json::<serde_json::Value>()
+        .ok()
+        .and_then(|body| body.get("error")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| status.to_string());
+    bail!("Server returned {}: {}", status.as_u16(), message)
+}
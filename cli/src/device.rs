@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use synapse_crypto::DeviceKey;
+
+/// Identity of this machine, saved by `synapse login`.
+#[derive(Serialize, Deserialize)]
+pub struct DeviceConfig {
+    pub server: String,
+    pub device_id: String,
+    pub name: String,
+    /// API token bound to this device.
+    pub token: String,
+    /// Base64 X25519 secret key; only its public half is sent to the server.
+    pub secret_key: String,
+    /// Devices the user approved with `synapse keys approve`, by id, with the
+    /// public key whose fingerprint they checked. Only these are given keys.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub approved: BTreeMap<String, String>,
+}
+
+impl DeviceConfig {
+    pub fn path() -> Result<PathBuf> {
+        let dir = dirs::config_dir().context("Could not determine the config directory")?;
+        Ok(dir.join("synapse").join("device.json"))
+    }
+
+    pub fn load() -> Result<Option<Self>> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        let config = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid device file {:?}", path))?;
+        Ok(Some(config))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // The file holds a token and a secret key
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&path)
+            .with_context(|| format!("Failed to write {:?}", path))?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())
+            .with_context(|| format!("Failed to write {:?}", path))?;
+        Ok(())
+    }
+
+    pub fn key(&self) -> Result<DeviceKey> {
+        DeviceKey::from_base64(&self.secret_key).context("Saved device secret key is corrupt")
+    }
+
+    pub fn remove() -> Result<()> {
+        let path = Self::path()?;
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use crate::client::{ApiClient, Clip, ClipboardItem, Grant, MissingGrants};
+use crate::device::DeviceConfig;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use synapse_crypto::{DeviceKey, UserKey};
+
+/// The user keys this device can read.
+pub struct Keyring {
+    keys: BTreeMap<i64, UserKey>,
+    current: Option<i64>,
+    device_id: String,
+    device: DeviceKey,
+    /// Device ids the user approved, with the public key they approved.
+    approved: BTreeMap<String, String>,
+    /// Devices missing keys that the user has not approved.
+    waiting: Vec<MissingGrants>,
+}
+
+impl Keyring {
+    /// Fetches the keys wrapped for this device, shares them with approved
+    /// devices still missing them and rotates to a new key after a
+    /// revocation. Devices nobody approved are left waiting: their public keys
+    /// come from the server, which must not be able to add a device of its own.
+    pub fn sync(
+        client: &ApiClient,
+        device_id: &str,
+        device: DeviceKey,
+        approved: BTreeMap<String, String>,
+    ) -> Result<Self> {
+        let status = client.key_status()?;
+        let mut keys = BTreeMap::new();
+        for wrapped in &status.keys {
+            let key = device
+                .unwrap(&wrapped.wrapped_key)
+                .with_context(|| format!("Failed to unwrap key version {}", wrapped.version))?;
+            keys.insert(wrapped.version, key);
+        }
+        let mut keyring = Self {
+            keys,
+            current: status.current_version,
+            device_id: device_id.to_string(),
+            device,
+            approved,
+            waiting: Vec::new(),
+        };
+
+        let mut grants = Vec::new();
+        for missing in status.missing {
+            if !keyring.is_approved(&missing.device_id, &missing.public_key) {
+                keyring.waiting.push(missing);
+                continue;
+            }
+            for version in &missing.versions {
+                if let Some(key) = keyring.keys.get(version) {
+                    grants.push(Grant {
+                        version: *version,
+                        device_id: missing.device_id.clone(),
+                        wrapped_key: key.wrap_for(&missing.public_key)?,
+                    });
+                }
+            }
+        }
+        if !grants.is_empty() {
+            client.add_grants(&grants)?;
+            eprintln!("Shared {} key version(s) with other devices", grants.len());
+        }
+
+        if status.rotation_needed {
+            let version = keyring.rotate(client)?;
+            eprintln!("A device was revoked; rotated to key version {version}");
+        }
+        Ok(keyring)
+    }
+
+    fn is_approved(&self, device_id: &str, public_key: &str) -> bool {
+        self.approved.get(device_id).map(String::as_str) == Some(public_key)
+    }
+
+    /// Fingerprint of this device's public key, for approving it elsewhere.
+    pub fn fingerprint(&self) -> Result<String> {
+        Ok(synapse_crypto::fingerprint(&self.device.public_base64())?)
+    }
+
+    /// Devices missing keys that wait for the user's approval.
+    pub fn waiting(&self) -> &[MissingGrants] {
+        &self.waiting
+    }
+
+    /// Shares the keys another device is missing, after the user checked its
+    /// fingerprint, and trusts it with later key versions. Returns how many
+    /// key versions were shared.
+    pub fn approve(
+        &mut self,
+        client: &ApiClient,
+        device_id: &str,
+        public_key: &str,
+    ) -> Result<usize> {
+        let mut grants = Vec::new();
+        let missing = self
+            .waiting
+            .iter()
+            .filter(|missing| missing.device_id == device_id && missing.public_key == public_key);
+        for version in missing.flat_map(|missing| &missing.versions) {
+            if let Some(key) = self.keys.get(version) {
+                grants.push(Grant {
+                    version: *version,
+                    device_id: device_id.to_string(),
+                    wrapped_key: key.wrap_for(public_key)?,
+                });
+            }
+        }
+        if !grants.is_empty() {
+            client.add_grants(&grants)?;
+        }
+        self.waiting
+            .retain(|missing| missing.device_id != device_id);
+        self.approved
+            .insert(device_id.to_string(), public_key.to_string());
+        Ok(grants.len())
+    }
+
+    pub fn current_version(&self) -> Option<i64> {
+        self.current
+    }
+
+    pub fn versions(&self) -> impl Iterator<Item = &i64> {
+        self.keys.keys()
+    }
+
+    /// Creates the next key version, wrapped for this device and every active
+    /// device the user approved.
+    pub fn rotate(&mut self, client: &ApiClient) -> Result<i64> {
+        let version = self.current.unwrap_or(0) + 1;
+        let key = UserKey::generate();
+        let mut grants = vec![Grant {
+            version,
+            device_id: self.device_id.clone(),
+            wrapped_key: key.wrap_for(&self.device.public_base64())?,
+        }];
+        for device in client.devices()? {
+            let Some(public_key) = &device.public_key else {
+                continue;
+            };
+            if device.revoked_at.is_none() && self.is_approved(&device.id, public_key) {
+                grants.push(Grant {
+                    version,
+                    device_id: device.id,
+                    wrapped_key: key.wrap_for(public_key)?,
+                });
+            }
+        }
+        client.create_key_version(version, &grants)?;
+        self.keys.insert(version, key);
+        self.current = Some(version);
+        Ok(version)
+    }
+
+    /// Replaces the item's content with ciphertext under the current key,
+    /// creating the first key version if the user has none yet.
+    pub fn encrypt(&mut self, client: &ApiClient, item: &mut ClipboardItem) -> Result<()> {
+        let version = match self.current {
+            Some(version) => version,
+            None => self.rotate(client)?,
+        };
+        let Some(key) = self.keys.get(&version) else {
+            bail!("This device has not been given key version {version} yet; approve it with `synapse keys approve` on another device first");
+        };
+        item.content = key.encrypt(item.content.as_bytes());
+        item.key_version = Some(version);
+        Ok(())
+    }
+
+    /// Replaces encrypted content with the plaintext.
+    pub fn decrypt(&self, clip: &mut Clip) -> Result<()> {
+        let Some(version) = clip.item.key_version else {
+            return Ok(());
+        };
+        let key = self.keys.get(&version).with_context(|| {
+            format!(
+                "Clip {} needs key version {version}, which this device does not have",
+                clip.id
+            )
+        })?;
+        let plaintext = key
+            .decrypt(&clip.item.content)
+            .with_context(|| format!("Failed to decrypt clip {}", clip.id))?;
+        clip.item.content =
+            String::from_utf8(plaintext).context("Decrypted content is not valid UTF-8")?;
+        clip.item.key_version = None;
+        Ok(())
+    }
+}
+
+/// Loads the keyring on first use, so commands that never meet an encrypted
+/// clip never touch keys.
+pub struct Vault<'a> {
+    device: Option<&'a DeviceConfig>,
+    keyring: Option<Keyring>,
+}
+
+impl<'a> Vault<'a> {
+    /// `device` is the saved login, if this invocation authenticates as it.
+    pub fn new(device: Option<&'a DeviceConfig>) -> Self {
+        Self {
+            device,
+            keyring: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.device.is_some()
+    }
+
+    pub fn keyring(&mut self, client: &ApiClient) -> Result<&mut Keyring> {
+        if self.keyring.is_none() {
+            let device = self.device.context(
+                "Encryption keys are only available to a logged-in device; run `synapse login`",
+            )?;
+            self.keyring = Some(Keyring::sync(
+                client,
+                &device.device_id,
+                device.key()?,
+                device.approved.clone(),
+            )?);
+        }
+        Ok(self.keyring.as_mut().expect("keyring was just loaded"))
+    }
+
+    pub fn decrypt(&mut self, client: &ApiClient, clip: &mut Clip) -> Result<()> {
+        if clip.item.key_version.is_none() {
+            return Ok(());
+        }
+        self.keyring(client)?.decrypt(clip)
+    }
+}
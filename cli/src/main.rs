@@ -0,0 +1,924 @@
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{NaiveDate, NaiveTime};
+use clap::{Parser, Subcommand};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use synapse_crypto::DeviceKey;
+
+mod client;
+mod device;
+mod keys;
+mod transfer;
+use client::{
+    ApiClient, Clip, ClipFilter, ClipUpdate, ClipboardItem, FileEntry, Retention, RetentionStatus,
+    Search, ServerEvent,
+};
+use device::DeviceConfig;
+use keys::Vault;
+
+const DEFAULT_SERVER: &str = "http://127.0.0.1:3000";
+
+#[derive(Parser)]
+#[command(name = "synapse")]
+#[command(about = "Access the Synapse clipboard history from the terminal")]
+struct Args {
+    /// Base URL of the Synapse server [default: the server of `synapse login`,
+    /// otherwise http://127.0.0.1:3000]
+    #[arg(long, env = "SYNAPSE_SERVER")]
+    server: Option<String>,
+
+    /// API token [default: the device token saved by `synapse login`]
+    #[arg(long, env = "SYNAPSE_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Log in with a password and register this machine as a device
+    Login {
+        username: String,
+
+        /// Device name shown in the device list [default: this machine's name]
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// List, rename or revoke your devices
+    Devices {
+        #[command(subcommand)]
+        command: Option<DeviceCommand>,
+    },
+    /// Show or rotate the end-to-end encryption keys
+    Keys {
+        #[command(subcommand)]
+        command: Option<KeyCommand>,
+    },
+    /// Show or change how much history the server keeps
+    Retention {
+        #[command(subcommand)]
+        command: Option<RetentionCommand>,
+    },
+    /// Add a clip from a file or from stdin
+    Push {
+        /// File to read; reads stdin when omitted
+        file: Option<PathBuf>,
+
+        /// Item type (text, image, html); guessed from the file extension by default
+        #[arg(long = "type")]
+        item_type: Option<String>,
+    },
+    /// Send files and folders as a file clip
+    Send {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Download the files of a file clip
+    Receive {
+        /// Clip to download [default: the most recent file clip]
+        id: Option<i64>,
+
+        /// Folder to write the files to
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Write the most recent clip to stdout
+    Pull,
+    /// Write a clip to stdout
+    Get { id: i64 },
+    /// List recent clips
+    List {
+        /// Maximum number of clips to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+
+        /// Number of recent clips to skip
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+
+        /// Only show clips of this type
+        #[arg(long = "type")]
+        item_type: Option<String>,
+
+        /// Only show clips captured on this device
+        #[arg(long)]
+        device: Option<String>,
+
+        /// Only show pinned clips
+        #[arg(long)]
+        pinned: bool,
+
+        /// Only show favorite clips
+        #[arg(long)]
+        favorites: bool,
+
+        /// Only show clips in this collection
+        #[arg(long)]
+        collection: Option<String>,
+
+        /// Print clips as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+    /// Search the history. Only unencrypted text and HTML clips are searchable by content
+    Search {
+        /// Words that must all occur in the clip
+        query: Vec<String>,
+
+        /// Only show clips of this type
+        #[arg(long = "type")]
+        item_type: Option<String>,
+
+        /// Only show clips captured on this device (name or id)
+        #[arg(long)]
+        device: Option<String>,
+
+        /// Only show clips copied by this user
+        #[arg(long)]
+        user: Option<String>,
+
+        /// Only show clips from this day on (YYYY-MM-DD)
+        #[arg(long)]
+        since: Option<NaiveDate>,
+
+        /// Only show clips up to and including this day (YYYY-MM-DD)
+        #[arg(long)]
+        until: Option<NaiveDate>,
+
+        /// Maximum number of clips to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+
+        /// Print clips as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+    /// Pin clips to the top of the history
+    Pin {
+        #[arg(required = true)]
+        ids: Vec<i64>,
+
+        /// Unpin them instead
+        #[arg(long)]
+        off: bool,
+    },
+    /// Mark clips as favorites
+    #[command(alias = "fav")]
+    Favorite {
+        #[arg(required = true)]
+        ids: Vec<i64>,
+
+        /// Remove the mark instead
+        #[arg(long)]
+        off: bool,
+    },
+    /// Move clips into a snippet collection
+    Collect {
+        /// Collection name; an empty name takes the clips out of their collection
+        name: String,
+
+        #[arg(required = true)]
+        ids: Vec<i64>,
+    },
+    /// List snippet collections
+    Collections,
+    /// Delete clips
+    Rm {
+        /// Ids of the clips to delete
+        #[arg(required_unless_present = "all")]
+        ids: Vec<i64>,
+
+        /// Delete the whole history
+        #[arg(long, conflicts_with = "ids")]
+        all: bool,
+    },
+    /// Stream new clips as they arrive
+    Watch {
+        /// Print clips as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum DeviceCommand {
+    /// List devices (the default)
+    List,
+    /// Give a device a new name
+    Rename { id: String, name: String },
+    /// Revoke a device; its sessions and tokens stop working immediately
+    Revoke { id: String },
+}
+
+#[derive(Subcommand)]
+enum KeyCommand {
+    /// Show key versions, this device's fingerprint and devices waiting for keys (the default)
+    Status,
+    /// Create a new key version; clips are then encrypted with it
+    Rotate,
+    /// Share keys with a new device after checking its fingerprint
+    Approve {
+        id: String,
+
+        /// The fingerprint `synapse keys` shows on that device; asks for confirmation when omitted
+        #[arg(long)]
+        fingerprint: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RetentionCommand {
+    /// Show the policy in effect (the default)
+    Show,
+    /// Set your own policy; omitted limits are unlimited. Pinned, favorite and collected clips are always kept
+    Set {
+        /// Keep only the newest this many clips
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        max_items: Option<u64>,
+
+        /// Delete clips older than this many days
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_age_days: Option<u32>,
+
+        /// Keep only the newest clips that add up to at most this many bytes
+        #[arg(long)]
+        max_bytes: Option<u64>,
+    },
+    /// Go back to the server's default policy
+    Reset,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let saved = DeviceConfig::load()?;
+    let server = args
+        .server
+        .or_else(|| saved.as_ref().map(|device| device.server.clone()))
+        .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+    // The saved token only applies to the server it was issued by
+    let token = args.token.or_else(|| {
+        saved
+            .as_ref()
+            .filter(|device| device.server == server)
+            .map(|device| device.token.clone())
+    });
+    let client = ApiClient::new(&server, token.as_deref())?;
+    // Clips are end-to-end encrypted when acting as the saved device
+    let device = saved
+        .as_ref()
+        .filter(|device| device.server == server && token.as_deref() == Some(&device.token));
+    let mut vault = Vault::new(device);
+
+    match args.command {
+        Command::Login { username, name } => {
+            let password = match std::env::var("SYNAPSE_PASSWORD") {
+                Ok(password) => password,
+                Err(_) => rpassword::prompt_password(format!("Password for {username}: "))
+                    .context("Failed to read password")?,
+            };
+            // Keep the keypair across logins; it identifies this machine
+            let key = match &saved {
+                Some(device) => device.key()?,
+                None => DeviceKey::generate(),
+            };
+            let name = name.unwrap_or_else(whoami::devicename);
+            let registered =
+                client.register_device(&username, &password, &name, &key.public_base64())?;
+            let config = DeviceConfig {
+                server,
+                device_id: registered.device.id,
+                name: registered.device.name,
+                token: registered.token,
+                secret_key: key.secret_base64(),
+                approved: saved
+                    .as_ref()
+                    .map(|device| device.approved.clone())
+                    .unwrap_or_default(),
+            };
+            config.save()?;
+            eprintln!(
+                "Logged in as {username} on device {} ({})",
+                config.name, config.device_id
+            );
+            eprintln!("Credentials saved to {:?}", DeviceConfig::path()?);
+
+            let status = ApiClient::new(&config.server, Some(&config.token))?.key_status()?;
+            if status.current_version.is_some() && status.keys.is_empty() {
+                eprintln!(
+                    "Encrypted clips become readable once another device approves this one: run `synapse keys approve {}` there and check it shows fingerprint {}",
+                    config.device_id,
+                    synapse_crypto::fingerprint(&key.public_base64())?
+                );
+            }
+            Ok(())
+        }
+        Command::Devices { command } => match command.unwrap_or(DeviceCommand::List) {
+            DeviceCommand::List => {
+                for device in client.devices()? {
+                    print_device(&device);
+                }
+                Ok(())
+            }
+            DeviceCommand::Rename { id, name } => {
+                let device = client.rename_device(&id, &name)?;
+                eprintln!("Renamed device {} to {}", device.id, device.name);
+                Ok(())
+            }
+            DeviceCommand::Revoke { id } => {
+                client.revoke_device(&id)?;
+                eprintln!("Revoked device {id}");
+                if saved.is_some_and(|device| device.device_id == id) {
+                    DeviceConfig::remove()?;
+                    eprintln!("This was the current device; run `synapse login` again");
+                }
+                Ok(())
+            }
+        },
+        Command::Keys { command } => {
+            let keyring = vault.keyring(&client)?;
+            match command.unwrap_or(KeyCommand::Status) {
+                KeyCommand::Status => {}
+                KeyCommand::Rotate => {
+                    let version = keyring.rotate(&client)?;
+                    eprintln!("Rotated to key version {version}");
+                }
+                KeyCommand::Approve { id, fingerprint } => {
+                    let device = client
+                        .devices()?
+                        .into_iter()
+                        .find(|device| device.id == id && device.revoked_at.is_none())
+                        .with_context(|| format!("No active device {id}"))?;
+                    let public_key = device
+                        .public_key
+                        .as_deref()
+                        .with_context(|| format!("Device {id} has no encryption key"))?;
+                    let actual = synapse_crypto::fingerprint(public_key)?;
+                    println!("Device:      {} ({})", device.name, device.id);
+                    println!("Fingerprint: {actual}");
+                    let confirmed = match fingerprint {
+                        Some(expected) => same_fingerprint(&expected, &actual),
+                        None => confirm(
+                            "Does `synapse keys` on that device show the same fingerprint?",
+                        )?,
+                    };
+                    if !confirmed {
+                        bail!("Device {id} was not approved; its fingerprint was not confirmed");
+                    }
+                    let shared = keyring.approve(&client, &device.id, public_key)?;
+                    let mut config =
+                        DeviceConfig::load()?.context("Not logged in; run `synapse login`")?;
+                    config
+                        .approved
+                        .insert(device.id.clone(), public_key.to_string());
+                    config.save()?;
+                    eprintln!(
+                        "Approved device {}; shared {shared} key version(s)",
+                        device.name
+                    );
+                }
+            }
+            match keyring.current_version() {
+                Some(version) => {
+                    let held = keyring
+                        .versions()
+                        .map(|v| v.to_string())
+                        .collect::<Vec<_>>();
+                    println!("Current key version: {version}");
+                    println!("This device holds: {}", held.join(", "));
+                }
+                None => println!("No key yet; the first push creates one"),
+            }
+            println!("This device's fingerprint: {}", keyring.fingerprint()?);
+            for missing in keyring.waiting() {
+                println!(
+                    "Device {} is waiting for keys (fingerprint {}); run `synapse keys approve {}` after checking it",
+                    missing.device_id,
+                    synapse_crypto::fingerprint(&missing.public_key)?,
+                    missing.device_id
+                );
+            }
+            Ok(())
+        }
+        Command::Retention { command } => {
+            let status = match command.unwrap_or(RetentionCommand::Show) {
+                RetentionCommand::Show => client.retention()?,
+                RetentionCommand::Set {
+                    max_items,
+                    max_age_days,
+                    max_bytes,
+                } => client.set_retention(&Retention {
+                    max_items: max_items.map(|n| n as usize),
+                    max_age_days,
+                    max_bytes,
+                })?,
+                RetentionCommand::Reset => {
+                    client.reset_retention()?;
+                    client.retention()?
+                }
+            };
+            print_retention(&status);
+            Ok(())
+        }
+        Command::Push { file, item_type } => push(&client, &mut vault, file.as_deref(), item_type),
+        Command::Send { paths } => send(&client, &vault, &paths),
+        Command::Receive { id, dir } => {
+            let clip = match id {
+                Some(id) => client.get(id)?,
+                None => {
+                    let search = Search {
+                        item_type: Some("file".to_string()),
+                        ..Default::default()
+                    };
+                    let page = client.search(&search, 1)?;
+                    page.items.into_iter().next().context("No file clips yet")?
+                }
+            };
+            if clip.item.item_type != "file" {
+                bail!("Clip {} holds {}, not files", clip.id, clip.item.item_type);
+            }
+            let entries: Vec<FileEntry> =
+                serde_json::from_str(&clip.item.content).context("Invalid file list")?;
+            transfer::download(&client, &entries, &dir)?;
+            eprintln!("Received {} files into {:?}", entries.len(), dir);
+            Ok(())
+        }
+        Command::Pull => {
+            let mut clip = newest_clip(&client)?.context("History is empty")?;
+            vault.decrypt(&client, &mut clip)?;
+            write_content(&client, &clip)
+        }
+        Command::Get { id } => {
+            let mut clip = client.get(id)?;
+            vault.decrypt(&client, &mut clip)?;
+            write_content(&client, &clip)
+        }
+        Command::List {
+            limit,
+            offset,
+            item_type,
+            device,
+            pinned,
+            favorites,
+            collection,
+            json,
+        } => {
+            let filter = ClipFilter {
+                pinned,
+                favorite: favorites,
+                collection,
+            };
+            let mut page = client.list(offset, limit, &filter)?;
+            for clip in &mut page.items {
+                if let Err(e) = vault.decrypt(&client, clip) {
+                    eprintln!("{e:#}");
+                }
+            }
+            let clips = page.items.iter().filter(|clip| {
+                item_type.as_ref().is_none_or(|t| &clip.item.item_type == t)
+                    && device
+                        .as_ref()
+                        .is_none_or(|d| clip.item.device.as_ref() == Some(d))
+            });
+            for clip in clips {
+                print_clip(clip, json)?;
+            }
+            if !json {
+                eprintln!("({} clips in history)", page.total);
+            }
+            Ok(())
+        }
+        Command::Search {
+            query,
+            item_type,
+            device,
+            user,
+            since,
+            until,
+            limit,
+            json,
+        } => {
+            let search = Search {
+                q: query.join(" "),
+                item_type,
+                device,
+                username: user,
+                from: since.map(start_of_day).transpose()?,
+                to: until
+                    .and_then(|day| day.succ_opt())
+                    .map(start_of_day)
+                    .transpose()?,
+            };
+            let mut page = client.search(&search, limit)?;
+            for clip in &mut page.items {
+                if let Err(e) = vault.decrypt(&client, clip) {
+                    eprintln!("{e:#}");
+                }
+                print_clip(clip, json)?;
+            }
+            if !json {
+                eprintln!("({} matching clips)", page.total);
+            }
+            Ok(())
+        }
+        Command::Pin { ids, off } => {
+            let update = ClipUpdate {
+                pinned: Some(!off),
+                ..Default::default()
+            };
+            for id in ids {
+                client.update(id, &update)?;
+                eprintln!("{} clip {id}", if off { "Unpinned" } else { "Pinned" });
+            }
+            Ok(())
+        }
+        Command::Favorite { ids, off } => {
+            let update = ClipUpdate {
+                favorite: Some(!off),
+                ..Default::default()
+            };
+            for id in ids {
+                client.update(id, &update)?;
+                if off {
+                    eprintln!("Clip {id} is no longer a favorite");
+                } else {
+                    eprintln!("Clip {id} is now a favorite");
+                }
+            }
+            Ok(())
+        }
+        Command::Collect { name, ids } => {
+            let update = ClipUpdate {
+                collection: Some(name),
+                ..Default::default()
+            };
+            for id in ids {
+                let clip = client.update(id, &update)?;
+                match &clip.item.collection {
+                    Some(collection) => eprintln!("Moved clip {id} to {collection}"),
+                    None => eprintln!("Took clip {id} out of its collection"),
+                }
+            }
+            Ok(())
+        }
+        Command::Collections => {
+            for collection in client.collections()? {
+                println!("{:>6}  {}", collection.count, collection.name);
+            }
+            Ok(())
+        }
+        Command::Rm { ids, all } => {
+            if all {
+                client.clear()?;
+                eprintln!("History cleared");
+            }
+            for id in ids {
+                client.delete(id)?;
+                eprintln!("Deleted clip {id}");
+            }
+            Ok(())
+        }
+        Command::Watch { json } => client.watch(|event| match event {
+            ServerEvent::Created { mut clip } => {
+                if let Err(e) = vault.decrypt(&client, &mut clip) {
+                    eprintln!("{e:#}");
+                }
+                print_clip(&clip, json)
+            }
+            ServerEvent::Updated { clip } if !json => {
+                eprintln!("Clip {} updated", clip.id);
+                Ok(())
+            }
+            ServerEvent::Deleted { id } if !json => {
+                eprintln!("Clip {id} deleted");
+                Ok(())
+            }
+            ServerEvent::Cleared if !json => {
+                eprintln!("History cleared");
+                Ok(())
+            }
+            _ => Ok(()),
+        }),
+    }
+}
+
+fn push(
+    client: &ApiClient,
+    vault: &mut Vault,
+    file: Option<&Path>,
+    item_type: Option<String>,
+) -> Result<()> {
+    let bytes = match file {
+        Some(path) => fs::read(path).with_context(|| format!("Failed to read {:?}", path))?,
+        None => {
+            let mut bytes = Vec::new();
+            io::stdin()
+                .read_to_end(&mut bytes)
+                .context("Failed to read stdin")?;
+            bytes
+        }
+    };
+
+    let item_type = item_type
+        .or_else(|| file.and_then(guess_type))
+        .unwrap_or_else(|| "text".to_string());
+    let (content, mime_type) = match item_type.as_str() {
+        "image" => {
+            let mime = file.and_then(image_mime).unwrap_or("image/png").to_string();
+            (STANDARD.encode(&bytes), mime)
+        }
+        "text" | "html" => {
+            let text = String::from_utf8(bytes)
+                .context("Input is not valid UTF-8; use --type image for binary data")?;
+            let mime = if item_type == "html" {
+                "text/html"
+            } else {
+                "text/plain"
+            };
+            (text, mime.to_string())
+        }
+        other => bail!("Unsupported item type: {other}"),
+    };
+
+    let mut item = ClipboardItem {
+        size: Some(content.len()),
+        item_type,
+        content,
+        mime_type: Some(mime_type),
+        timestamp: None,
+        device: None,
+        device_id: None,
+        username: None,
+        key_version: None,
+        expires_at: None,
+        source_app: None,
+        pinned: false,
+        favorite: false,
+        collection: None,
+        copy_count: 1,
+        blob: None,
+    };
+    if vault.is_enabled() {
+        vault.keyring(client)?.encrypt(client, &mut item)?;
+    } else {
+        eprintln!("Not logged in as a device; the clip is stored unencrypted");
+    }
+    let clip = client.create(&item)?;
+    if clip.item.copy_count > 1 {
+        eprintln!(
+            "Clip {} already holds this content, copied {} times",
+            clip.id, clip.item.copy_count
+        );
+    } else {
+        eprintln!("Pushed clip {}", clip.id);
+    }
+    Ok(())
+}
+
+/// Uploads files and records a file clip listing them.
+fn send(client: &ApiClient, vault: &Vault, paths: &[PathBuf]) -> Result<()> {
+    let entries = transfer::upload(client, paths)?;
+    let size: u64 = entries.iter().map(|entry| entry.size).sum();
+    let item = ClipboardItem {
+        item_type: "file".to_string(),
+        content: serde_json::to_string(&entries)?,
+        mime_type: None,
+        timestamp: None,
+        device: None,
+        device_id: None,
+        username: None,
+        size: Some(size as usize),
+        key_version: None,
+        expires_at: None,
+        source_app: None,
+        pinned: false,
+        favorite: false,
+        collection: None,
+        copy_count: 1,
+        blob: None,
+    };
+    if vault.is_enabled() {
+        eprintln!("File clips are not end-to-end encrypted");
+    }
+    let clip = client.create(&item)?;
+    eprintln!(
+        "Sent {} files ({}) as clip {}",
+        entries.len(),
+        transfer::format_size(size),
+        clip.id
+    );
+    Ok(())
+}
+
+fn guess_type(path: &Path) -> Option<String> {
+    if image_mime(path).is_some() {
+        return Some("image".to_string());
+    }
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "html" | "htm" => Some("html".to_string()),
+        _ => None,
+    }
+}
+
+fn image_mime(path: &Path) -> Option<&'static str> {
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// The most recently copied clip. Listings put pinned clips first, so this
+/// is the newer of the first pinned clip and the first clip after them.
+fn newest_clip(client: &ApiClient) -> Result<Option<Clip>> {
+    let pinned_only = ClipFilter {
+        pinned: true,
+        ..Default::default()
+    };
+    let pinned = client.list(0, 1, &pinned_only)?;
+    let unpinned = client.list(pinned.total, 1, &ClipFilter::default())?;
+    Ok(pinned
+        .items
+        .into_iter()
+        .chain(unpinned.items)
+        .max_by_key(|clip| clip.item.timestamp))
+}
+
+/// Writes the raw clip content to stdout, fetching images from the blob
+/// store or decoding inline base64 ones.
+fn write_content(client: &ApiClient, clip: &Clip) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    if let Some(blob) = &clip.item.blob {
+        client
+            .download(blob, 0)?
+            .copy_to(&mut stdout)
+            .context("Failed to download the image")?;
+    } else if clip.item.item_type == "image" {
+        let data = clip
+            .item
+            .content
+            .split_once(',')
+            .map_or(clip.item.content.as_str(), |(_, data)| data);
+        let bytes = STANDARD
+            .decode(data.trim())
+            .context("Failed to decode image data")?;
+        stdout.write_all(&bytes)?;
+    } else {
+        stdout.write_all(clip.item.content.as_bytes())?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Unix milliseconds of local midnight at the start of `day`.
+fn start_of_day(day: NaiveDate) -> Result<i64> {
+    let midnight = day
+        .and_time(NaiveTime::MIN)
+        .and_local_timezone(chrono::Local)
+        .earliest()
+        .with_context(|| format!("{day} has no local midnight"))?;
+    Ok(midnight.timestamp_millis())
+}
+
+fn format_time(millis: Option<i64>) -> String {
+    millis
+        .and_then(chrono::DateTime::from_timestamp_millis)
+        .map(|t| {
+            t.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Compares fingerprints ignoring spacing and case, so either can be typed.
+fn same_fingerprint(a: &str, b: &str) -> bool {
+    let normalize = |fingerprint: &str| {
+        fingerprint
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_uppercase()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Asks a yes/no question on the terminal; anything but "y" means no.
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{question} [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read the answer")?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn print_device(device: &client::Device) {
+    let status = if device.revoked_at.is_some() {
+        "revoked"
+    } else if device.current {
+        "current"
+    } else {
+        ""
+    };
+    println!(
+        "{}  {:<20}  {:<8}  {}  {}",
+        device.id,
+        device.name,
+        device.platform.as_deref().unwrap_or("-"),
+        format_time(device.last_seen),
+        status
+    );
+}
+
+fn print_retention(status: &RetentionStatus) {
+    let limit = |value: Option<String>| value.unwrap_or_else(|| "unlimited".to_string());
+    let retention = &status.retention;
+    println!(
+        "Max items:  {}",
+        limit(retention.max_items.map(|n| n.to_string()))
+    );
+    println!(
+        "Max age:    {}",
+        limit(retention.max_age_days.map(|days| format!("{days} days")))
+    );
+    println!(
+        "Max size:   {}",
+        limit(retention.max_bytes.map(|bytes| format!("{bytes} bytes")))
+    );
+    if status.default {
+        println!("(server default)");
+    }
+}
+
+fn print_clip(clip: &Clip, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string(clip)?);
+        return Ok(());
+    }
+
+    let time = format_time(clip.item.timestamp);
+    let preview = if let Some(version) = clip.item.key_version {
+        format!("<encrypted with key version {version}>")
+    } else if clip.item.item_type == "image" {
+        format!("<image, {} bytes>", clip.item.size.unwrap_or_default())
+    } else if clip.item.item_type == "file" {
+        let entries: Vec<FileEntry> = serde_json::from_str(&clip.item.content).unwrap_or_default();
+        let names = entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "<{} files, {}: {}>",
+            entries.len(),
+            transfer::format_size(clip.item.size.unwrap_or_default() as u64),
+            names.chars().take(40).collect::<String>()
+        )
+    } else {
+        let flat = clip
+            .item
+            .content
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut preview: String = flat.chars().take(60).collect();
+        if flat.chars().count() > 60 {
+            preview.push('…');
+        }
+        preview
+    };
+    let mut marks = Vec::new();
+    if clip.item.pinned {
+        marks.push("pinned".to_string());
+    }
+    if clip.item.favorite {
+        marks.push("favorite".to_string());
+    }
+    if let Some(collection) = &clip.item.collection {
+        marks.push(format!("in {collection}"));
+    }
+    if clip.item.copy_count > 1 {
+        marks.push(format!("copied {} times", clip.item.copy_count));
+    }
+    let marks = if marks.is_empty() {
+        String::new()
+    } else {
+        format!("  ({})", marks.join(", "))
+    };
+    println!(
+        "{:>6}  {}  {:<5}  {:<16}  {}{}",
+        clip.id,
+        time,
+        clip.item.item_type,
+        clip.item.device.as_deref().unwrap_or("-"),
+        preview,
+        marks
+    );
+    Ok(())
+}
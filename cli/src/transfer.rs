@@ -0,0 +1,213 @@
+use crate::client::{ApiClient, FileEntry};
+use anyhow::{bail, Context, Result};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// A local file to send, with the path it gets in the clip.
+struct LocalFile {
+    path: String,
+    source: PathBuf,
+    size: u64,
+}
+
+/// Uploads files and folders, skipping files the server already has and
+/// resuming uploads that were interrupted, and returns the entries of a
+/// file clip listing them.
+pub fn upload(client: &ApiClient, paths: &[PathBuf]) -> Result<Vec<FileEntry>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let name = path
+            .canonicalize()
+            .with_context(|| format!("Failed to read {path:?}"))?
+            .file_name()
+            .with_context(|| format!("{path:?} has no file name"))?
+            .to_string_lossy()
+            .into_owned();
+        collect(path, name, &mut files)?;
+    }
+    if files.is_empty() {
+        bail!("No files to send");
+    }
+
+    let mut entries = Vec::new();
+    for file in files {
+        let hash = hash_file(&file.source)?;
+        let mut status = client.start_upload(&hash, file.size)?;
+        let mut source = File::open(&file.source)
+            .with_context(|| format!("Failed to read {:?}", file.source))?;
+        source.seek(SeekFrom::Start(status.received))?;
+        let mut progress = Progress::new("Uploading", &file.path, file.size);
+        while !status.complete {
+            let id = status
+                .id
+                .as_deref()
+                .context("Server did not start an upload")?;
+            let offset = status.received;
+            let mut chunk = Vec::new();
+            (&mut source)
+                .take(status.chunk_size)
+                .read_to_end(&mut chunk)?;
+            if chunk.is_empty() {
+                bail!("{} changed while it was being sent", file.path);
+            }
+            status = client.upload_chunk(id, offset, chunk)?;
+            progress.update(status.received);
+        }
+        progress.finish();
+        entries.push(FileEntry {
+            path: file.path,
+            size: file.size,
+            hash,
+        });
+    }
+    Ok(entries)
+}
+
+/// Downloads the files of a file clip below `dir`. Partly downloaded files
+/// are resumed and files already there with the right contents are kept.
+pub fn download(client: &ApiClient, entries: &[FileEntry], dir: &Path) -> Result<()> {
+    for entry in entries {
+        let target = dir.join(relative_path(&entry.path)?);
+        if target.exists() && hash_file(&target)? == entry.hash {
+            eprintln!("{} is up to date", entry.path);
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {parent:?}"))?;
+        }
+        let partial = target.with_file_name(format!(
+            "{}.part",
+            target.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        let mut offset = fs::metadata(&partial).map_or(0, |meta| meta.len());
+        if offset > entry.size {
+            offset = 0;
+        }
+        let mut response = client.download(&entry.hash, offset)?;
+        // A server that ignores the range sends the whole file again
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            offset = 0;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(offset == 0)
+            .open(&partial)
+            .with_context(|| format!("Failed to write {partial:?}"))?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut progress = Progress::new("Downloading", &entry.path, entry.size);
+        let mut received = offset;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let n = response.read(&mut buffer).context("Download interrupted")?;
+            if n == 0 {
+                break;
+            }
+            file.write_all(&buffer[..n])?;
+            received += n as u64;
+            progress.update(received);
+        }
+        progress.finish();
+        drop(file);
+        if hash_file(&partial)? != entry.hash {
+            fs::remove_file(&partial)?;
+            bail!("{} does not match its hash; receive it again", entry.path);
+        }
+        fs::rename(&partial, &target).with_context(|| format!("Failed to write {target:?}"))?;
+    }
+    Ok(())
+}
+
+/// Lists the regular files at `path`, recursing into folders.
+fn collect(path: &Path, relative: String, files: &mut Vec<LocalFile>) -> Result<()> {
+    let meta = fs::metadata(path).with_context(|| format!("Failed to read {path:?}"))?;
+    if meta.is_dir() {
+        let mut children = fs::read_dir(path)
+            .with_context(|| format!("Failed to read {path:?}"))?
+            .collect::<io::Result<Vec<_>>>()?;
+        children.sort_by_key(|child| child.file_name());
+        for child in children {
+            let name = child.file_name().to_string_lossy().into_owned();
+            collect(&child.path(), format!("{relative}/{name}"), files)?;
+        }
+    } else if meta.is_file() {
+        files.push(LocalFile {
+            path: relative,
+            source: path.to_path_buf(),
+            size: meta.len(),
+        });
+    }
+    Ok(())
+}
+
+/// A clip path as a relative path that stays inside the target folder.
+fn relative_path(path: &str) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for part in path.split('/') {
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) => relative.push(name),
+            _ => bail!("Refusing to write to {path:?}"),
+        }
+    }
+    Ok(relative)
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("Failed to read {path:?}"))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read {path:?}"))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Transfer progress on one stderr line.
+struct Progress<'a> {
+    action: &'static str,
+    path: &'a str,
+    size: u64,
+    shown: Option<u64>,
+}
+
+impl<'a> Progress<'a> {
+    fn new(action: &'static str, path: &'a str, size: u64) -> Self {
+        Self {
+            action,
+            path,
+            size,
+            shown: None,
+        }
+    }
+
+    fn update(&mut self, done: u64) {
+        let percent = (done * 100).checked_div(self.size).unwrap_or(100);
+        if self.shown == Some(percent) {
+            return;
+        }
+        self.shown = Some(percent);
+        eprint!(
+            "\r{} {}  {percent:>3}%  {} / {}",
+            self.action,
+            self.path,
+            format_size(done),
+            format_size(self.size)
+        );
+    }
+
+    fn finish(&mut self) {
+        self.update(self.size);
+        eprintln!();
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        1_048_576..1_073_741_824 => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+        _ => format!("{:.1} GiB", bytes as f64 / 1_073_741_824.0),
+    }
+}
@@ -0,0 +1,301 @@
+//! End-to-end encryption shared by the Synapse clients.
+//!
+//! Every user has a symmetric *user key* per key version. Clip contents are
+//! sealed with it using XChaCha20-Poly1305, so the server only ever stores
+//! ciphertext. The user key reaches a device wrapped to that device's X25519
+//! public key: an ephemeral X25519 exchange derives a one-off wrapping key
+//! that seals the user key, much like a libsodium sealed box.
+//!
+//! All binary values cross the API as standard base64.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+const CLIP_AAD: &[u8] = b"synapse clip v1";
+const WRAP_AAD: &[u8] = b"synapse user key v1";
+const WRAP_KDF_DOMAIN: &[u8] = b"synapse key wrap v1";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Input was not valid base64 or had the wrong length.
+    Malformed(&'static str),
+    /// Authentication failed: wrong key or tampered data.
+    Decrypt,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed(what) => write!(f, "malformed {what}"),
+            Error::Decrypt => write!(f, "decryption failed (wrong key or corrupted data)"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A version of the user's symmetric clip key.
+#[derive(Clone)]
+pub struct UserKey([u8; KEY_LEN]);
+
+impl UserKey {
+    pub fn generate() -> Self {
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        Self(key)
+    }
+
+    /// Encrypts clip content, returning base64 `nonce || ciphertext`.
+    pub fn encrypt(&self, plaintext: &[u8]) -> String {
+        STANDARD.encode(seal(&self.0, plaintext, CLIP_AAD))
+    }
+
+    /// Decrypts content produced by [`UserKey::encrypt`].
+    pub fn decrypt(&self, content: &str) -> Result<Vec<u8>, Error> {
+        let data = STANDARD
+            .decode(content.trim())
+            .map_err(|_| Error::Malformed("ciphertext"))?;
+        open(&self.0, &data, CLIP_AAD)
+    }
+
+    /// Wraps this key for the device owning `public_key` (base64 X25519).
+    pub fn wrap_for(&self, public_key: &str) -> Result<String, Error> {
+        let recipient = PublicKey::from(decode_key(public_key, "public key")?);
+        let ephemeral = StaticSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(&recipient);
+        let wrapping_key = wrapping_key(shared.as_bytes(), &ephemeral_public, &recipient);
+
+        let mut out = ephemeral_public.as_bytes().to_vec();
+        out.extend(seal(&wrapping_key, &self.0, WRAP_AAD));
+        Ok(STANDARD.encode(out))
+    }
+}
+
+/// The X25519 keypair identifying a device. The secret never leaves it.
+pub struct DeviceKey(StaticSecret);
+
+impl DeviceKey {
+    pub fn generate() -> Self {
+        Self(StaticSecret::random_from_rng(OsRng))
+    }
+
+    pub fn from_base64(secret: &str) -> Result<Self, Error> {
+        Ok(Self(StaticSecret::from(decode_key(secret, "secret key")?)))
+    }
+
+    pub fn secret_base64(&self) -> String {
+        STANDARD.encode(self.0.to_bytes())
+    }
+
+    pub fn public_base64(&self) -> String {
+        STANDARD.encode(PublicKey::from(&self.0).as_bytes())
+    }
+
+    /// Recovers a user key wrapped for this device by [`UserKey::wrap_for`].
+    pub fn unwrap(&self, wrapped: &str) -> Result<UserKey, Error> {
+        let data = STANDARD
+            .decode(wrapped.trim())
+            .map_err(|_| Error::Malformed("wrapped key"))?;
+        if data.len() < KEY_LEN {
+            return Err(Error::Malformed("wrapped key"));
+        }
+        let (ephemeral, sealed) = data.split_at(KEY_LEN);
+        let ephemeral_public = PublicKey::from(<[u8; KEY_LEN]>::try_from(ephemeral).unwrap());
+        let shared = self.0.diffie_hellman(&ephemeral_public);
+        let wrapping_key = wrapping_key(
+            shared.as_bytes(),
+            &ephemeral_public,
+            &PublicKey::from(&self.0),
+        );
+        let key = open(&wrapping_key, sealed, WRAP_AAD)?;
+        let key = key
+            .try_into()
+            .map_err(|_| Error::Malformed("wrapped key"))?;
+        Ok(UserKey(key))
+    }
+}
+
+/// A short digest of a device's base64 public key for people to compare:
+/// the first 16 bytes of its SHA-256 in hex, in groups of four digits. A new
+/// device is only given keys after its owner has checked that both devices
+/// show the same fingerprint, so a server cannot slip in a key of its own.
+pub fn fingerprint(public_key: &str) -> Result<String, Error> {
+    let digest = Sha256::digest(decode_key(public_key, "public key")?);
+    let groups: Vec<String> = digest[..16]
+        .chunks(2)
+        .map(|pair| format!("{:02X}{:02X}", pair[0], pair[1]))
+        .collect();
+    Ok(groups.join(" "))
+}
+
+fn wrapping_key(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> [u8; KEY_LEN] {
+    Sha256::new()
+        .chain_update(WRAP_KDF_DOMAIN)
+        .chain_update(shared)
+        .chain_update(ephemeral.as_bytes())
+        .chain_update(recipient.as_bytes())
+        .finalize()
+        .into()
+}
+
+fn seal(key: &[u8; KEY_LEN], plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = XChaCha20Poly1305::new(key.into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("XChaCha20-Poly1305 encryption cannot fail for in-memory buffers");
+    let mut out = nonce.to_vec();
+    out.extend(ciphertext);
+    out
+}
+
+fn open(key: &[u8; KEY_LEN], data: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() < NONCE_LEN {
+        return Err(Error::Malformed("ciphertext"));
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| Error::Decrypt)
+}
+
+fn decode_key(value: &str, what: &'static str) -> Result<[u8; KEY_LEN], Error> {
+    STANDARD
+        .decode(value.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(Error::Malformed(what))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flips one bit of the decoded value at `index` (negative counts from the end).
+    fn tamper(value: &str, index: isize) -> String {
+        let mut data = STANDARD.decode(value).unwrap();
+        let index = index.rem_euclid(data.len() as isize) as usize;
+        data[index] ^= 1;
+        STANDARD.encode(data)
+    }
+
+    #[test]
+    fn encrypt_decrypt_round_trip() {
+        let key = UserKey::generate();
+        let sealed = key.encrypt(b"hunter2");
+        assert_ne!(sealed, key.encrypt(b"hunter2"), "nonces must be fresh");
+        assert_eq!(key.decrypt(&sealed).unwrap(), b"hunter2");
+        assert_eq!(key.decrypt(&key.encrypt(b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn wrap_unwrap_round_trip() {
+        let key = UserKey::generate();
+        let device = DeviceKey::generate();
+        let wrapped = key.wrap_for(&device.public_base64()).unwrap();
+        let unwrapped = device.unwrap(&wrapped).unwrap();
+        assert_eq!(unwrapped.decrypt(&key.encrypt(b"clip")).unwrap(), b"clip");
+
+        let restored = DeviceKey::from_base64(&device.secret_base64()).unwrap();
+        assert_eq!(restored.public_base64(), device.public_base64());
+        assert!(restored.unwrap(&wrapped).is_ok());
+    }
+
+    #[test]
+    fn wrong_key_fails_to_decrypt() {
+        let sealed = UserKey::generate().encrypt(b"clip");
+        assert_eq!(UserKey::generate().decrypt(&sealed), Err(Error::Decrypt));
+
+        let wrapped = UserKey::generate()
+            .wrap_for(&DeviceKey::generate().public_base64())
+            .unwrap();
+        assert_eq!(
+            DeviceKey::generate().unwrap(&wrapped).err(),
+            Some(Error::Decrypt)
+        );
+    }
+
+    #[test]
+    fn tampering_fails_to_decrypt() {
+        let key = UserKey::generate();
+        let sealed = key.encrypt(b"clip");
+        // First byte of the nonce, last byte of the tag
+        assert_eq!(key.decrypt(&tamper(&sealed, 0)), Err(Error::Decrypt));
+        assert_eq!(key.decrypt(&tamper(&sealed, -1)), Err(Error::Decrypt));
+
+        let device = DeviceKey::generate();
+        let wrapped = key.wrap_for(&device.public_base64()).unwrap();
+        // Ephemeral public key, nonce and sealed key
+        for index in [0, KEY_LEN as isize, -1] {
+            assert_eq!(
+                device.unwrap(&tamper(&wrapped, index)).err(),
+                Some(Error::Decrypt)
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        let key = UserKey::generate();
+        assert_eq!(
+            key.decrypt("not base64!"),
+            Err(Error::Malformed("ciphertext"))
+        );
+        assert_eq!(key.decrypt("AAAA"), Err(Error::Malformed("ciphertext")));
+        assert_eq!(
+            key.wrap_for("AAAA").err(),
+            Some(Error::Malformed("public key"))
+        );
+        assert_eq!(
+            DeviceKey::generate().unwrap("AAAA").err(),
+            Some(Error::Malformed("wrapped key"))
+        );
+    }
+
+    #[test]
+    fn fingerprints_are_stable_and_grouped() {
+        let zero = STANDARD.encode([0u8; KEY_LEN]);
+        assert_eq!(
+            fingerprint(&zero).unwrap(),
+            "6668 7AAD F862 BD77 6C8F C18B 8E9F 8E20"
+        );
+
+        let device = DeviceKey::generate();
+        let public_key = device.public_base64();
+        let first = fingerprint(&public_key).unwrap();
+        assert_eq!(first, fingerprint(&public_key).unwrap());
+        assert_ne!(
+            first,
+            fingerprint(&DeviceKey::generate().public_base64()).unwrap()
+        );
+        let groups: Vec<&str> = first.split(' ').collect();
+        assert_eq!(groups.len(), 8);
+        assert!(groups.iter().all(|group| group.len() == 4
+            && group
+                .chars()
+                .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())));
+        assert_eq!(fingerprint("AAAA"), Err(Error::Malformed("public key")));
+    }
+}
@@ -0,0 +1,149 @@
+// blurhash 占位图：后端为图片生成的几十个字符（编码见 backend/src/thumbnails.rs），
+// 描述图片的平均颜色和几个余弦分量。这里把它解码成一张很小的 BMP，
+// 图片加载完成前作为背景拉伸显示，列表不会因为图片陆续加载而跳动
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use std::f32::consts::PI;
+
+// 解码出的占位图边长（像素），拉伸后只剩模糊的色块，不需要更大
+const SIZE: usize = 32;
+const DIGITS: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+// 可以直接用作 CSS 背景的 data URL；格式不对时返回 None
+pub fn data_url(hash: &str) -> Option<String> {
+    let pixels = decode(hash)?;
+    Some(format!(
+        "data:image/bmp;base64,{}",
+        STANDARD.encode(bmp(&pixels))
+    ))
+}
+
+// 解码成 SIZE × SIZE 的 sRGB 像素，逐行从上到下
+fn decode(hash: &str) -> Option<Vec<[u8; 3]>> {
+    if !hash.is_ascii() || hash.len() < 6 {
+        return None;
+    }
+    let flag = decode_base83(&hash[..1])? as usize;
+    let (components_x, components_y) = (flag % 9 + 1, flag / 9 + 1);
+    if hash.len() != 4 + 2 * components_x * components_y {
+        return None;
+    }
+    let maximum = (decode_base83(&hash[1..2])? + 1) as f32 / 166.0;
+
+    let dc = decode_base83(&hash[2..6])?;
+    let mut colors = vec![[dc >> 16, (dc >> 8) & 255, dc & 255].map(|c| srgb_to_linear(c as u8))];
+    for index in 1..components_x * components_y {
+        let value = decode_base83(&hash[4 + index * 2..6 + index * 2])?;
+        colors.push(
+            [value / (19 * 19), value / 19 % 19, value % 19].map(|quantised| {
+                let value = (quantised as f32 - 9.0) / 9.0;
+                value.signum() * value * value * maximum
+            }),
+        );
+    }
+
+    let mut pixels = Vec::with_capacity(SIZE * SIZE);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let mut pixel = [0.0; 3];
+            for j in 0..components_y {
+                for i in 0..components_x {
+                    let basis = (PI * (x * i) as f32 / SIZE as f32).cos()
+                        * (PI * (y * j) as f32 / SIZE as f32).cos();
+                    for (channel, color) in pixel.iter_mut().zip(colors[j * components_x + i]) {
+                        *channel += color * basis;
+                    }
+                }
+            }
+            pixels.push(pixel.map(linear_to_srgb));
+        }
+    }
+    Some(pixels)
+}
+
+// 24 位无压缩 BMP：行从下到上、BGR 顺序，SIZE 是 4 的倍数所以每行不需要补齐
+fn bmp(pixels: &[[u8; 3]]) -> Vec<u8> {
+    let data_size = (pixels.len() * 3) as u32;
+    let mut bmp = Vec::with_capacity(54 + data_size as usize);
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&(54 + data_size).to_le_bytes());
+    bmp.extend_from_slice(&[0; 4]);
+    bmp.extend_from_slice(&54u32.to_le_bytes());
+    bmp.extend_from_slice(&40u32.to_le_bytes());
+    bmp.extend_from_slice(&(SIZE as i32).to_le_bytes());
+    bmp.extend_from_slice(&(SIZE as i32).to_le_bytes());
+    bmp.extend_from_slice(&1u16.to_le_bytes());
+    bmp.extend_from_slice(&24u16.to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&data_size.to_le_bytes());
+    bmp.extend_from_slice(&[0; 16]);
+    for row in pixels.chunks(SIZE).rev() {
+        for [r, g, b] in row {
+            bmp.extend_from_slice(&[*b, *g, *r]);
+        }
+    }
+    bmp
+}
+
+fn decode_base83(digits: &str) -> Option<u32> {
+    digits.bytes().try_fold(0, |value, digit| {
+        Some(value * 83 + DIGITS.iter().position(|&d| d == digit)? as u32)
+    })
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 参考实现（github.com/woltapp/blurhash）的 f64 移植在 32 × 32 上解码出的像素
+    #[test]
+    fn decodes_reference_hash() {
+        let pixels = decode("LEHV6nWB2yk8pyo0adR*.7kCMdnj").unwrap();
+        for ((x, y), expected) in [
+            ((0, 0), [135, 164, 177]),
+            ((16, 16), [158, 125, 108]),
+            ((31, 31), [133, 142, 147]),
+            ((5, 20), [132, 139, 146]),
+        ] {
+            let pixel = pixels[y * SIZE + x];
+            for (actual, expected) in pixel.iter().zip(expected) {
+                assert!(
+                    actual.abs_diff(expected) <= 1,
+                    "pixel ({x}, {y}) is {pixel:?}, expected {expected:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_solid_black() {
+        let pixels = decode("L00000fQfQfQfQfQfQfQfQfQfQfQ").unwrap();
+        assert!(pixels.iter().all(|pixel| *pixel == [0, 0, 0]));
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        assert!(decode("LEHV6nWB2yk8pyo0adR*.7kCMdn").is_none());
+        assert!(decode("L00000fQfQfQfQfQfQfQfQfQfQf\u{e9}").is_none());
+        assert!(data_url("").is_none());
+    }
+}
@@ -1,8 +1,11 @@
 use dioxus::document::eval;
 use dioxus::prelude::*;
 use dioxus_logger::tracing::Level;
+use std::collections::{BTreeMap, HashSet};
 
+mod blurhash;
 mod components;
+mod sanitize;
 use components::toast::ToastProvider;
 use dioxus_primitives::toast::use_toast;
 
@@ -73,7 +76,7 @@ fn Root() -> Element {
             href: asset!("/assets/dx-components-theme.css")
         }
         ToastProvider {
-            App {}
+            AuthGate {}
         }
     }
 }
@@ -81,6 +84,9 @@ fn Root() -> Element {
 // 剪贴板内容类型
 #[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 struct ClipboardItem {
+    // 后端分配的 id（仅来自后端的条目有）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
     #[serde(rename = "type")]
     item_type: String,
     content: String,
@@ -91,267 +97,1169 @@ struct ClipboardItem {
     timestamp: Option<i64>, // Unix 时间戳（毫秒）
     #[serde(skip_serializing_if = "Option::is_none")]
     device: Option<String>,
+    // 设备注册表中的 id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     username: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     size: Option<usize>, // 内容大小（字节）
+    // 端到端加密时 content 是密文，这是加密所用的密钥版本
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_version: Option<i64>,
+    // 自动过期时间（毫秒），到期后从历史中删除
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+    // 复制来源的应用名（平台支持时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_app: Option<String>,
+    // 置顶、收藏和归入集合的条目不受保留策略影响
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    favorite: bool,
+    // 所属的片段集合名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    collection: Option<String>,
+    // 被复制的次数，重复复制相同内容只会累加次数
+    #[serde(default = "first_copy")]
+    copy_count: u32,
+    // 同一次复制中 content 以外的格式（键为 MIME 类型，如 text/plain、text/rtf），写回剪贴板时一起恢复
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    formats: BTreeMap<String, String>,
+    // 后端把未加密图片存入 blob 存储，这里是其哈希，content 为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blob: Option<String>,
+    // 后端为 blob 图片生成的预览，列表显示缩略图，弹窗中才加载原图
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preview: Option<ImagePreview>,
+}
+
+fn first_copy() -> u32 {
+    1
+}
+
+// 图片预览：缩略图的 blob 哈希（原图不比缩略图大时没有）、blurhash 占位图、原图尺寸，
+// 以及列表中显示的尺寸（缩略图的，没有缩略图时是原图的），由后端计算
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct ImagePreview {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<String>,
+    blurhash: String,
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    thumbnail_width: u32,
+    #[serde(default)]
+    thumbnail_height: u32,
+}
+
+// 文件条目（type 为 file）的 content 是 FileEntry 的 JSON 数组。
+// 桌面端记录的是本机的绝对路径；经后端传输的是相对路径和内容哈希，文件从 /api/blobs/{hash} 下载
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+struct FileEntry {
+    path: String,
+    #[serde(default)]
+    size: usize,
+    #[serde(default)]
+    hash: Option<String>,
+    #[serde(default)]
+    folder: bool,
+}
+
+impl FileEntry {
+    // 显示用的文件名；相对路径保留所在文件夹
+    fn name(&self) -> &str {
+        match &self.hash {
+            Some(_) => &self.path,
+            None => self.path.rsplit(['/', '\\']).next().unwrap_or(&self.path),
+        }
+    }
+
+    // 后端的下载地址，name 决定保存的文件名和 Content-Type
+    fn download_url(&self) -> Option<String> {
+        let hash = self.hash.as_ref()?;
+        let name = self.path.rsplit('/').next().unwrap_or_default();
+        let name: String = name
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+                _ => format!("%{b:02X}"),
+            })
+            .collect();
+        Some(format!("/api/blobs/{hash}?name={name}"))
+    }
+}
+
+impl ClipboardItem {
+    // 纯文本表示：HTML 条目取同时复制的纯文本格式，没有时取原内容
+    fn plain_text(&self) -> &str {
+        self.formats.get("text/plain").map_or(&self.content, |text| text)
+    }
+
+    // 图片地址：存入 blob 存储的从后端加载，其余内联为 data URL
+    fn image_src(&self) -> String {
+        match &self.blob {
+            Some(hash) => format!("/api/blobs/{hash}"),
+            None => format!(
+                "data:{};base64,{}",
+                self.mime_type.as_deref().unwrap_or("image/png"),
+                self.content
+            ),
+        }
+    }
+
+    // 列表和快速粘贴窗口中的图片地址：有缩略图时用缩略图
+    fn thumbnail_src(&self) -> String {
+        match self.preview.as_ref().and_then(|preview| preview.thumbnail.as_ref()) {
+            Some(hash) => format!("/api/blobs/{hash}"),
+            None => self.image_src(),
+        }
+    }
+
+    // 文件条目中的文件，其他条目为空
+    fn files(&self) -> Vec<FileEntry> {
+        if self.item_type != "file" {
+            return Vec::new();
+        }
+        serde_json::from_str(&self.content).unwrap_or_default()
+    }
+
+    // 文件名列表，用于快速粘贴窗口的预览和搜索
+    fn file_names(&self) -> String {
+        self.files().iter().map(FileEntry::name).collect::<Vec<_>>().join(", ")
+    }
+}
+
+fn format_size(s: usize) -> String {
+    if s < 1024 {
+        format!("{} B", s)
+    } else if s < 1024 * 1024 {
+        format!("{:.2} KB", s as f64 / 1024.0)
+    } else {
+        format!("{:.2} MB", s as f64 / (1024.0 * 1024.0))
+    }
+}
+
+// 历史列表的筛选
+#[derive(Clone, PartialEq)]
+enum HistoryFilter {
+    All,
+    Favorites,
+    Collection(String),
+}
+
+impl HistoryFilter {
+    fn matches(&self, item: &ClipboardItem) -> bool {
+        match self {
+            HistoryFilter::All => true,
+            HistoryFilter::Favorites => item.favorite,
+            HistoryFilter::Collection(name) => item.collection.as_ref() == Some(name),
+        }
+    }
+}
+
+// 后端事件流（/api/events）推送的历史变更；Tauri 模式下 Rust 侧的 history://event 格式相同
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerEvent {
+    Created { clip: Box<ClipboardItem> },
+    Updated { clip: Box<ClipboardItem> },
+    Deleted { id: i64 },
+    Cleared,
+}
+
+
+// 登录状态：Web 模式需要登录，Tauri 桌面端直接使用本地历史
+// 桌面端的快速粘贴窗口（index.html#picker）只显示选择列表
+#[derive(Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum AuthState {
+    Checking,
+    Local,
+    Picker,
+    SignedIn { username: String },
+    SignedOut { oidc: bool, error: Option<String> },
 }
 
 #[component]
-fn App() -> Element {
-    let clipboard_history = use_signal(|| Vec::<ClipboardItem>::new());
-    
-    // 删除剪贴板项的函数（通过信号更新）
-    let clipboard_history_for_delete = clipboard_history;
+fn AuthGate() -> Element {
+    let mut auth = use_context_provider(|| Signal::new(AuthState::Checking));
 
-    // Effect to start monitoring and listen for events
+    // 登录后重新置为 Checking 会再次执行检查（同时完成设备注册）
     use_effect(move || {
+        if *auth.read() != AuthState::Checking {
+            return;
+        }
         spawn(async move {
-            // 1. Start monitoring and setup event listener
-            // 注意：在 Dioxus 中，dioxus.send() 会自动发送到当前 eval 创建的通道
-            // 所以我们需要在同一个 eval 中定义监听器函数和创建 handler
-            let mut handler = eval(
+            let result = eval(
                 r#"
-                (async function() {
-                    // 定义设置监听器的函数（必须在同一个 eval 中，这样 dioxus.send() 才能正确工作）
-                    // 注意：dioxus.send 是绑定到当前 eval 的通道的，所以必须在同一个 eval 中使用
-                    window.__setupClipboardListener = async function() {
+                // 本机设备身份保存在 localStorage。端到端加密的私钥由 CLI 和桌面端的 Rust 侧持有
+                // （桌面端在 Sync 面板登录服务器）；浏览器运行的是服务器下发的代码，不持有密钥，
+                // 加密的剪贴板只显示为占位
+                const loadDevice = () => {
+                    try {
+                        return JSON.parse(localStorage.getItem('synapse-device'));
+                    } catch (e) {
+                        return null;
+                    }
+                };
+                const saveDevice = (device) => {
+                    localStorage.setItem('synapse-device', JSON.stringify(device));
+                    window.__synapseDevice = device;
+                };
+                let device = loadDevice();
+                window.__synapseDevice = device;
+
+                if (window.__TAURI__) {
+                    if (location.hash === '#picker') {
+                        return { mode: 'picker' };
+                    }
+                    if (!device) {
+                        let name = 'Desktop';
+                        let platform = null;
                         try {
-                            const { listen } = window.__TAURI__.event;
-                            const unlisten = await listen('plugin:clipboard://clipboard-monitor/update', async (event) => {
-                                // 检查是否应该忽略这个事件（如果是在复制过程中触发的）
-                                if (window.__ignoreClipboardEvent) {
-                                    return;
-                                }
-                                
-                            try {
-                                let clipboardData = null;
-                                
-                                // 获取元数据
-                                const timestamp = Date.now();
-                                // 获取 hostname 和用户名（跨平台兼容）
-                                let device = 'Unknown';
-                                let username = 'Unknown';
-                                try {
-                                    // 使用 Tauri OS API 获取 hostname（支持所有平台）
-                                    const hostname = await window.__TAURI__.os.hostname();
-                                    device = hostname || 'Unknown';
-                                    
-                                    // 获取系统用户名（仅在桌面平台支持 shell 命令）
-                                    try {
-                                        const platform = await window.__TAURI__.os.platform();
-                                        // 只在桌面平台（windows, macos, linux）尝试执行 shell 命令
-                                        if (platform === 'windows' || platform === 'macos' || platform === 'linux') {
-                                            try {
-                                                const { Command } = window.__TAURI__.shell;
-                                                // 使用 Command.create 创建命令
-                                                const cmd = Command.create('whoami');
-                                                const output = await cmd.execute();
-                                                if (output.code === 0 && output.stdout) {
-                                                    username = output.stdout.trim();
-                                                }
-                                            } catch (e) {
-                                                console.error("Failed to get username via shell: " + e);
-                                            }
+                            name = (await window.__TAURI__.os.hostname()) || name;
+                            platform = await window.__TAURI__.os.platform();
+                        } catch (e) {
+                            // 系统信息获取失败，使用默认值
+                        }
+                        saveDevice({ id: crypto.randomUUID(), name: name, platform: platform });
+                    }
+                    return { mode: 'local' };
+                }
+                // SSO 回调失败时后端会重定向到 /?sso_error=...
+                const params = new URLSearchParams(location.search);
+                const error = params.get('sso_error');
+                if (error) {
+                    history.replaceState(null, '', location.pathname);
+                }
+                const me = await fetch('/api/auth/me');
+                if (me.ok) {
+                    const user = await me.json();
+                    // 会话未绑定到本机设备（首次登录或设备已被撤销）时重新注册
+                    if (!device || user.device_id !== device.id) {
+                        const platform = (navigator.userAgentData && navigator.userAgentData.platform)
+                            || navigator.platform || null;
+                        const name = device ? device.name : 'Browser' + (platform ? ' on ' + platform : '');
+                        const res = await fetch('/api/devices', {
+                            method: 'POST',
+                            headers: { 'Content-Type': 'application/json' },
+                            body: JSON.stringify({ name: name, platform: platform }),
+                        });
+                        if (res.ok) {
+                            const registered = await res.json();
+                            saveDevice({ id: registered.id, name: registered.name, platform: platform });
+                        } else {
+                            console.error("Failed to register device: " + res.status);
+                        }
+                    }
+                    return { mode: 'signed_in', username: user.username };
+                }
+                let oidc = false;
+                try {
+                    oidc = (await (await fetch('/api/auth/methods')).json()).oidc;
+                } catch (e) {
+                    console.error("Failed to load login methods: " + e);
+                }
+                return { mode: 'signed_out', oidc: oidc, error: error };
+            "#,
+            )
+            .await;
+            let state = result
+                .ok()
+                .and_then(|value| serde_json::from_value::<AuthState>(value).ok())
+                .unwrap_or(AuthState::SignedOut { oidc: false, error: None });
+            auth.set(state);
+        });
+    });
+
+    let state = auth.read().clone();
+    match state {
+        AuthState::Checking => rsx! {},
+        AuthState::Picker => rsx! { QuickPicker {} },
+        AuthState::Local | AuthState::SignedIn { .. } => rsx! { App {} },
+        AuthState::SignedOut { oidc, error } => rsx! { Login { oidc, error } },
+    }
+}
+
+#[component]
+fn Login(oidc: bool, error: Option<String>) -> Element {
+    let mut auth = use_context::<Signal<AuthState>>();
+    let mut username = use_signal(String::new);
+    let mut password = use_signal(String::new);
+    let mut message = use_signal(move || error.clone());
+    let mut busy = use_signal(|| false);
+
+    let on_submit = move |evt: FormEvent| {
+        evt.prevent_default();
+        if busy() {
+            return;
+        }
+        busy.set(true);
+        spawn(async move {
+            let credentials = serde_json::json!({
+                "username": username.read().trim(),
+                "password": password.read().clone(),
+            });
+            let result = eval(&format!(
+                r#"
+                const credentials = {credentials};
+                // 已注册的本机设备会直接绑定到新会话
+                if (window.__synapseDevice) {{
+                    credentials.device_id = window.__synapseDevice.id;
+                }}
+                const res = await fetch('/api/auth/login', {{
+                    method: 'POST',
+                    headers: {{ 'Content-Type': 'application/json' }},
+                    body: JSON.stringify(credentials),
+                }});
+                if (res.ok) {{
+                    const user = await res.json();
+                    return {{ username: user.username }};
+                }}
+                if (res.status === 401) {{
+                    return {{ error: 'Invalid username or password' }};
+                }}
+                try {{
+                    return {{ error: (await res.json()).error }};
+                }} catch (e) {{
+                    return {{ error: 'Login failed (' + res.status + ')' }};
+                }}
+            "#
+            ))
+            .await;
+            busy.set(false);
+            let value = result.unwrap_or_default();
+            match value.get("username") {
+                Some(_) => auth.set(AuthState::Checking),
+                None => message.set(Some(
+                    value
+                        .get("error")
+                        .and_then(|error| error.as_str())
+                        .unwrap_or("Login failed")
+                        .to_string(),
+                )),
+            }
+        });
+    };
+
+    rsx! {
+        div {
+            class: "login-page",
+            form {
+                class: "login-card",
+                onsubmit: on_submit,
+                h1 { "Synapse" }
+                if let Some(msg) = message() {
+                    div { class: "login-error", "{msg}" }
+                }
+                input {
+                    class: "login-input",
+                    r#type: "text",
+                    placeholder: "Username",
+                    autocomplete: "username",
+                    value: "{username}",
+                    oninput: move |evt| username.set(evt.value()),
+                }
+                input {
+                    class: "login-input",
+                    r#type: "password",
+                    placeholder: "Password",
+                    autocomplete: "current-password",
+                    value: "{password}",
+                    oninput: move |evt| password.set(evt.value()),
+                }
+                button {
+                    class: "action-button action-button-copy",
+                    r#type: "submit",
+                    disabled: busy(),
+                    "Sign in"
+                }
+                if oidc {
+                    div { class: "login-divider", "or" }
+                    button {
+                        class: "action-button action-button-view",
+                        r#type: "button",
+                        onclick: move |_| {
+                            let _ = eval(
+                                r#"
+                                const device = window.__synapseDevice;
+                                location.href = '/api/auth/oidc/login'
+                                    + (device ? '?device_id=' + encodeURIComponent(device.id) : '');
+                            "#,
+                            );
+                        },
+                        "Sign in with SSO"
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 设备注册表中的设备（/api/devices）
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+struct Device {
+    id: String,
+    name: String,
+    platform: Option<String>,
+    last_seen: Option<i64>,
+    revoked_at: Option<i64>,
+    current: bool,
+}
+
+#[component]
+fn DevicesDialog(on_close: EventHandler<()>) -> Element {
+    // 重命名或撤销后递增，重新加载列表
+    let mut reload = use_signal(|| 0u32);
+    let devices = use_resource(move || async move {
+        let _ = reload();
+        let result = eval(
+            r#"
+            const res = await fetch('/api/devices');
+            return res.ok ? await res.json() : [];
+        "#,
+        )
+        .await;
+        result
+            .ok()
+            .and_then(|value| serde_json::from_value::<Vec<Device>>(value).ok())
+            .unwrap_or_default()
+    });
+
+    let rename = move |device: Device| {
+        spawn(async move {
+            let name = serde_json::to_string(&device.name).unwrap_or_default();
+            let _ = eval(&format!(
+                r#"
+                const name = prompt('Device name', {name});
+                if (name && name.trim()) {{
+                    await fetch('/api/devices/{id}', {{
+                        method: 'PATCH',
+                        headers: {{ 'Content-Type': 'application/json' }},
+                        body: JSON.stringify({{ name: name.trim() }}),
+                    }});
+                }}
+            "#,
+                id = device.id
+            ))
+            .await;
+            reload += 1;
+        });
+    };
+
+    let revoke = move |device: Device| {
+        spawn(async move {
+            let name = serde_json::to_string(&device.name).unwrap_or_default();
+            let _ = eval(&format!(
+                r#"
+                if (confirm('Revoke ' + {name} + '? It will be signed out immediately.')) {{
+                    await fetch('/api/devices/{id}', {{ method: 'DELETE' }});
+                    // 撤销本机后会话已失效，回到登录页
+                    if ({current}) {{
+                        location.reload();
+                    }}
+                }}
+            "#,
+                id = device.id,
+                current = device.current
+            ))
+            .await;
+            reload += 1;
+        });
+    };
+
+    let format_time = |ts: Option<i64>| {
+        ts.and_then(chrono::DateTime::from_timestamp_millis)
+            .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| "never".to_string())
+    };
+
+    rsx! {
+        div {
+            class: "modal-overlay",
+            onclick: move |_| on_close.call(()),
+            div {
+                class: "modal-content",
+                onclick: move |evt| evt.stop_propagation(),
+                div {
+                    class: "modal-header",
+                    h3 { "Devices" }
+                    button {
+                        class: "modal-close",
+                        onclick: move |_| on_close.call(()),
+                        "×"
+                    }
+                }
+                div {
+                    class: "modal-body",
+                    div {
+                        class: "device-list",
+                        for device in devices.read().clone().unwrap_or_default() {
+                            div {
+                                key: "{device.id}",
+                                class: if device.revoked_at.is_some() { "device-row device-row-revoked" } else { "device-row" },
+                                div {
+                                    div {
+                                        span { class: "device-name", "{device.name}" }
+                                        if device.current {
+                                            span { class: "device-badge", "This device" }
                                         }
-                                        // 在移动平台（iOS/Android），shell 命令不支持，保持 'Unknown'
-                                    } catch (e) {
-                                        // 平台信息获取失败，使用默认值
                                     }
-                                } catch (e) {
-                                    // 系统信息获取失败，使用默认值
-                                    device = navigator.platform || 'Unknown';
-                                    username = 'Unknown';
-                                }
-                                const getContentSize = (content) => {
-                                    if (typeof content === 'string') {
-                                        // 对于 base64 图片，计算实际大小
-                                        if (content.startsWith('data:')) {
-                                            return new Blob([content]).size;
+                                    div {
+                                        class: "device-meta",
+                                        if device.revoked_at.is_some() {
+                                            "Revoked {format_time(device.revoked_at)}"
+                                        } else {
+                                            "{device.platform.clone().unwrap_or_else(|| \"Unknown\".to_string())} · last seen {format_time(device.last_seen)}"
                                         }
-                                        // 对于 base64 字符串，计算解码后的大小
-                                        return Math.floor(content.length * 3 / 4);
-                                    }
-                                    return 0;
-                                };
-                                
-                                // 优先尝试读取图片（使用 base64 API）
-                                try {
-                                    const base64Image = await window.__TAURI__.core.invoke('plugin:clipboard|read_image_base64');
-                                    if (base64Image && base64Image.trim() !== '') {
-                                        const size = getContentSize(base64Image);
-                                        clipboardData = {
-                                            type: 'image',
-                                            content: base64Image,
-                                            mimeType: 'image/png',
-                                            timestamp: timestamp,
-                                            device: device,
-                                            username: username,
-                                            size: size
-                                        };
-                                        // 直接使用 dioxus.send，它绑定到创建 handler 的 eval 的通道
-                                        dioxus.send(clipboardData);
-                                        return; // 如果成功读取图片，就不读取文本了
                                     }
-                                } catch (imageError) {
                                 }
-                                
-                                // 如果没有图片，尝试读取文本
-                                try {
-                                    const text = await window.__TAURI__.core.invoke('plugin:clipboard|read_text');
-                                    if (text && text.trim() !== '') {
-                                        const size = new Blob([text]).size;
-                                        clipboardData = {
-                                            type: 'text',
-                                            content: text,
-                                            mimeType: 'text/plain',
-                                            timestamp: timestamp,
-                                            device: device,
-                                            username: username,
-                                            size: size
-                                        };
-                                        // 直接使用 dioxus.send，它绑定到创建 handler 的 eval 的通道
-                                        dioxus.send(clipboardData);
+                                if device.revoked_at.is_none() {
+                                    div {
+                                        class: "history-item-actions-right",
+                                        button {
+                                            class: "action-button action-button-view",
+                                            onclick: {
+                                                let device = device.clone();
+                                                move |_| rename(device.clone())
+                                            },
+                                            "Rename"
+                                        }
+                                        button {
+                                            class: "action-button action-button-delete",
+                                            onclick: {
+                                                let device = device.clone();
+                                                move |_| revoke(device.clone())
+                                            },
+                                            "Revoke"
+                                        }
                                     }
-                                } catch (textError) {
-                                        console.error("Failed to read clipboard text: " + String(textError));
                                 }
-                            } catch (e) {
-                                        console.error("Failed to read clipboard: " + String(e));
                             }
-                            });
-                            // 保存 unlisten 函数到全局变量，以便在复制时可以停止监听
-                            window.__clipboardUnlisten = unlisten;
-                            return unlisten;
-                        } catch (e) {
-                            console.error("Failed to set up event listener: " + e);
-                            throw e;
                         }
-                    };
-                    
-                    // 启动监控并设置监听器
-                    try {
-                        await window.__TAURI__.core.invoke('plugin:clipboard|start_monitor');
-                    } catch (e) {
-                        console.error("Failed to start monitor: " + e);
-                    }
-                    
-                    try {
-                        // 使用之前定义的函数来设置监听器
-                        await window.__setupClipboardListener();
-                    } catch (e) {
-                        console.error("Failed to set up event listener: " + e);
                     }
-                })();
-            "#,
-            );
+                }
+            }
+        }
+    }
+}
 
-            // 3. Also listen for window focus events to check clipboard when window gains focus
-            let _ = eval(
-                r#"
-                (async function() {
-                    try {
-                        // 使用 DOM 的 window.addEventListener 来监听焦点事件
-                        // 在 Tauri 2.0 中，窗口对象没有 onFocus 方法，需要使用 DOM API
-                        window.addEventListener('focus', async () => {
-                            try {
-                                let clipboardData = null;
-                                
-                                // 获取元数据
-                                const timestamp = Date.now();
-                                // 获取 hostname 和用户名
-                                let device = 'Unknown';
-                                let username = 'Unknown';
-                                try {
-                                    // 尝试通过 Tauri 获取系统信息
-                                    const hostname = await window.__TAURI__.os.hostname();
-                                    device = hostname || 'Unknown';
-                                    // 获取系统用户名
-                                    try {
-                                        const { exec } = window.__TAURI__.process;
-                                        const userResult = await exec('whoami', []);
-                                        username = userResult.stdout?.trim() || 'Unknown';
-                                    } catch (e) {
-                                        username = 'Unknown';
+// 桌面端与服务器同步的状态（Rust 侧 sync_* 命令的返回值），密钥只在 Rust 侧，这里只有指纹
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+struct SyncStatus {
+    fingerprint: String,
+    server: Option<String>,
+    username: Option<String>,
+    device_id: Option<String>,
+    device_name: Option<String>,
+    key_version: Option<i64>,
+    has_key: bool,
+    #[serde(default)]
+    waiting: Vec<WaitingDevice>,
+    error: Option<String>,
+}
+
+// 缺少密钥、等待本机批准的设备
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+struct WaitingDevice {
+    id: String,
+    name: String,
+    fingerprint: String,
+}
+
+// 调用一个 sync_* 命令，返回新的同步状态
+async fn invoke_sync(command: &str, args: serde_json::Value) -> Result<SyncStatus, String> {
+    let result = eval(&format!(
+        r#"
+        try {{
+            return {{ status: await window.__TAURI__.core.invoke('{command}', {args}) }};
+        }} catch (e) {{
+            return {{ error: String(e) }};
+        }}
+    "#
+    ))
+    .await
+    .unwrap_or_default();
+    if let Some(error) = result.get("error").and_then(|error| error.as_str()) {
+        return Err(error.to_string());
+    }
+    serde_json::from_value(result["status"].clone()).map_err(|e| e.to_string())
+}
+
+#[component]
+fn SyncDialog(on_close: EventHandler<()>) -> Element {
+    let mut status = use_signal(|| None::<SyncStatus>);
+    let mut message = use_signal(|| None::<String>);
+    let mut busy = use_signal(|| false);
+    let mut server = use_signal(String::new);
+    let mut username = use_signal(String::new);
+    let mut password = use_signal(String::new);
+    let mut name = use_signal(String::new);
+
+    use_effect(move || {
+        spawn(async move {
+            // 设备名默认沿用本机的名字（首次运行时取自主机名）
+            let device = eval("return window.__synapseDevice ? window.__synapseDevice.name : '';")
+                .await
+                .unwrap_or_default();
+            name.set(device.as_str().unwrap_or_default().to_string());
+            match invoke_sync("sync_status", serde_json::json!({})).await {
+                Ok(current) => status.set(Some(current)),
+                Err(error) => message.set(Some(error)),
+            }
+        });
+    });
+
+    // 登录后本机在服务器上的设备 id 成为本地的设备身份，重新加载后新记录的条目都带上它
+    let on_sign_in = move |evt: FormEvent| {
+        evt.prevent_default();
+        if busy() {
+            return;
+        }
+        busy.set(true);
+        spawn(async move {
+            let args = serde_json::json!({
+                "server": server.read().clone(),
+                "username": username.read().clone(),
+                "password": password.read().clone(),
+                "name": name.read().clone(),
+            });
+            match invoke_sync("sync_login", args).await {
+                Ok(current) => {
+                    let device = serde_json::json!({
+                        "id": current.device_id,
+                        "name": current.device_name,
+                    });
+                    let _ = eval(&format!(
+                        r#"
+                        const device = {device};
+                        device.platform = window.__synapseDevice ? window.__synapseDevice.platform : null;
+                        localStorage.setItem('synapse-device', JSON.stringify(device));
+                        location.reload();
+                    "#
+                    ))
+                    .await;
+                }
+                Err(error) => message.set(Some(error)),
+            }
+            busy.set(false);
+        });
+    };
+
+    let sign_out = move |_| {
+        spawn(async move {
+            let confirmed = eval("return confirm('Stop syncing? Local history is kept.');")
+                .await
+                .ok()
+                .and_then(|value| value.as_bool())
+                .unwrap_or(false);
+            if !confirmed {
+                return;
+            }
+            match invoke_sync("sync_logout", serde_json::json!({})).await {
+                Ok(current) => {
+                    message.set(None);
+                    status.set(Some(current));
+                }
+                Err(error) => message.set(Some(error)),
+            }
+        });
+    };
+
+    // 批准前让用户与另一台设备上显示的指纹核对，核对过的指纹交给 Rust 侧再次校验
+    let approve = move |device: WaitingDevice| {
+        spawn(async move {
+            let prompt = serde_json::to_string(&format!(
+                "Approve {}? Only continue if it shows this fingerprint:\n\n{}",
+                device.name, device.fingerprint
+            ))
+            .unwrap_or_default();
+            let confirmed = eval(&format!("return confirm({prompt});"))
+                .await
+                .ok()
+                .and_then(|value| value.as_bool())
+                .unwrap_or(false);
+            if !confirmed {
+                return;
+            }
+            let args = serde_json::json!({ "deviceId": device.id, "fingerprint": device.fingerprint });
+            match invoke_sync("sync_approve", args).await {
+                Ok(current) => {
+                    message.set(None);
+                    status.set(Some(current));
+                }
+                Err(error) => message.set(Some(error)),
+            }
+        });
+    };
+
+    let current = status.read().clone();
+    rsx! {
+        div {
+            class: "modal-overlay",
+            onclick: move |_| on_close.call(()),
+            div {
+                class: "modal-content",
+                onclick: move |evt| evt.stop_propagation(),
+                div {
+                    class: "modal-header",
+                    h3 { "Sync" }
+                    button {
+                        class: "modal-close",
+                        onclick: move |_| on_close.call(()),
+                        "×"
+                    }
+                }
+                div {
+                    class: "modal-body",
+                    if let Some(msg) = message() {
+                        div { class: "login-error", "{msg}" }
+                    }
+                    if let Some(current) = current {
+                        if let (Some(server_url), Some(user)) = (current.server.clone(), current.username.clone()) {
+                            div {
+                                class: "device-list",
+                                div {
+                                    class: "device-row",
+                                    div {
+                                        div {
+                                            span { class: "device-name", "{user} @ {server_url}" }
+                                        }
+                                        div {
+                                            class: "device-meta",
+                                            if current.has_key {
+                                                "Text and HTML clips are end-to-end encrypted with key version {current.key_version.unwrap_or_default()}"
+                                            } else if current.key_version.is_some() {
+                                                "Waiting for approval: on a device that already syncs, approve this one after checking its fingerprint (synapse keys approve {current.device_id.clone().unwrap_or_default()})"
+                                            } else {
+                                                "No key yet; one is created with the first synced clip"
+                                            }
+                                        }
+                                        if let Some(error) = current.error.clone() {
+                                            div { class: "device-meta", "Last error: {error}" }
+                                        }
                                     }
-                                } catch (e) {
-                                    device = navigator.platform || 'Unknown';
-                                    username = 'Unknown';
-                                }
-                                const getContentSize = (content) => {
-                                    if (typeof content === 'string') {
-                                        if (content.startsWith('data:')) {
-                                            return new Blob([content]).size;
+                                    div {
+                                        class: "history-item-actions-right",
+                                        button {
+                                            class: "action-button action-button-delete",
+                                            onclick: sign_out,
+                                            "Sign out"
                                         }
-                                        return Math.floor(content.length * 3 / 4);
                                     }
-                                    return 0;
-                                };
-                                
-                                // 优先尝试读取图片（使用 base64 API）
-                                try {
-                                    const base64Image = await window.__TAURI__.core.invoke('plugin:clipboard|read_image_base64');
-                                    if (base64Image && base64Image.trim() !== '') {
-                                        const size = getContentSize(base64Image);
-                                        clipboardData = {
-                                            type: 'image',
-                                            content: base64Image,
-                                            mimeType: 'image/png',
-                                            timestamp: timestamp,
-                                            device: device,
-                                            username: username,
-                                            size: size
-                                        };
-                                        // dioxus.send() 会自动序列化对象
-                                        dioxus.send(clipboardData);
-                                        return;
+                                }
+                                div {
+                                    class: "device-row",
+                                    div {
+                                        div {
+                                            span { class: "device-name", "{current.device_name.clone().unwrap_or_default()}" }
+                                            span { class: "device-badge", "This device" }
+                                        }
+                                        div { class: "device-meta device-fingerprint", "{current.fingerprint}" }
                                     }
-                                } catch (imageError) {
                                 }
-                                
-                                // 如果没有图片，尝试读取文本
-                                try {
-                                    const text = await window.__TAURI__.core.invoke('plugin:clipboard|read_text');
-                                    if (text && text.trim() !== '') {
-                                        const size = new Blob([text]).size;
-                                        clipboardData = {
-                                            type: 'text',
-                                            content: text,
-                                            mimeType: 'text/plain',
-                                            timestamp: timestamp,
-                                            device: device,
-                                            username: username,
-                                            size: size
-                                        };
-                                        // dioxus.send() 会自动序列化对象
-                                        dioxus.send(clipboardData);
+                                for device in current.waiting.clone() {
+                                    div {
+                                        key: "{device.id}",
+                                        class: "device-row",
+                                        div {
+                                            div {
+                                                span { class: "device-name", "{device.name}" }
+                                            }
+                                            div { class: "device-meta device-fingerprint", "{device.fingerprint}" }
+                                        }
+                                        div {
+                                            class: "history-item-actions-right",
+                                            button {
+                                                class: "action-button action-button-copy",
+                                                onclick: {
+                                                    let device = device.clone();
+                                                    move |_| approve(device.clone())
+                                                },
+                                                "Approve"
+                                            }
+                                        }
                                     }
-                                } catch (textError) {
-                                    console.error("Failed to read clipboard on focus: " + textError);
                                 }
-                            } catch (e) {
-                                console.error("Failed to read clipboard on focus: " + e);
                             }
-                        });
+                        } else {
+                            form {
+                                class: "sync-form",
+                                onsubmit: on_sign_in,
+                                div {
+                                    class: "device-meta",
+                                    "Sign in to a Synapse server to sync text and HTML clips with your other devices. They are end-to-end encrypted; the server cannot read them."
+                                }
+                                input {
+                                    class: "login-input",
+                                    r#type: "url",
+                                    placeholder: "Server (https://synapse.example.com)",
+                                    value: "{server}",
+                                    oninput: move |evt| server.set(evt.value()),
+                                }
+                                input {
+                                    class: "login-input",
+                                    r#type: "text",
+                                    placeholder: "Username",
+                                    autocomplete: "username",
+                                    value: "{username}",
+                                    oninput: move |evt| username.set(evt.value()),
+                                }
+                                input {
+                                    class: "login-input",
+                                    r#type: "password",
+                                    placeholder: "Password",
+                                    autocomplete: "current-password",
+                                    value: "{password}",
+                                    oninput: move |evt| password.set(evt.value()),
+                                }
+                                input {
+                                    class: "login-input",
+                                    r#type: "text",
+                                    placeholder: "Device name",
+                                    value: "{name}",
+                                    oninput: move |evt| name.set(evt.value()),
+                                }
+                                div { class: "device-meta", "This device's fingerprint: " span { class: "device-fingerprint", "{current.fingerprint}" } }
+                                button {
+                                    class: "action-button action-button-copy",
+                                    r#type: "submit",
+                                    disabled: busy(),
+                                    "Sign in"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 快速粘贴窗口：全局快捷键打开，输入筛选，上下键选择，回车放回剪贴板，Esc 关闭
+// 条目来自 Rust 侧的本地历史，每次打开时重新读取
+#[component]
+fn QuickPicker() -> Element {
+    let mut clips = use_signal(Vec::<ClipboardItem>::new);
+    let mut query = use_signal(String::new);
+    let mut selected = use_signal(|| 0usize);
+
+    use_effect(move || {
+        spawn(async move {
+            let mut opened = eval(
+                r#"
+                const load = async () => {
+                    try {
+                        dioxus.send(await window.__TAURI__.core.invoke('picker_recent'));
                     } catch (e) {
-                        console.error("Failed to set up focus listener: " + e);
+                        console.error("Failed to load recent clips: " + e);
                     }
-                })();
+                    const input = document.querySelector('.picker-filter');
+                    if (input) {
+                        input.focus();
+                    }
+                };
+                await window.__TAURI__.event.listen('picker://open', load);
+                await load();
+            "#,
+            );
+            while let Ok(msg) = opened.recv().await {
+                if let Ok(recent) = serde_json::from_value::<Vec<ClipboardItem>>(msg) {
+                    clips.set(recent);
+                    query.set(String::new());
+                    selected.set(0);
+                }
+            }
+        });
+    });
+
+    let matches = picker_matches(&clips.read(), &query.read());
+    let current = selected().min(matches.len().saturating_sub(1));
+    let candidates = matches.clone();
+    let on_keydown = move |evt: KeyboardEvent| match evt.key() {
+        Key::ArrowDown => {
+            evt.prevent_default();
+            selected.set((current + 1).min(candidates.len().saturating_sub(1)));
+        }
+        Key::ArrowUp => {
+            evt.prevent_default();
+            selected.set(current.saturating_sub(1));
+        }
+        Key::Enter => {
+            if let Some(item) = candidates.get(current) {
+                paste_clip(item.clone());
+            }
+        }
+        Key::Escape => close_picker(false),
+        _ => {}
+    };
+
+    rsx! {
+        div {
+            class: "picker",
+            input {
+                class: "picker-filter",
+                r#type: "text",
+                placeholder: "Filter clips",
+                autofocus: true,
+                value: "{query}",
+                oninput: move |evt| {
+                    query.set(evt.value());
+                    selected.set(0);
+                },
+                onkeydown: on_keydown,
+            }
+            if matches.is_empty() {
+                div {
+                    class: "picker-empty",
+                    "没有匹配的条目"
+                }
+            }
+            ul {
+                class: "picker-list",
+                for (position, item) in matches.into_iter().enumerate() {
+                    li {
+                        key: "{position}",
+                        class: if position == current { "picker-item selected" } else { "picker-item" },
+                        onmouseenter: move |_| selected.set(position),
+                        onclick: {
+                            let item = item.clone();
+                            move |_| paste_clip(item.clone())
+                        },
+                        if item.item_type == "image" {
+                            img {
+                                class: "picker-thumbnail",
+                                src: "{item.thumbnail_src()}",
+                            }
+                        } else if item.item_type == "file" {
+                            span {
+                                class: "picker-text",
+                                {picker_preview(&item.file_names())}
+                            }
+                        } else {
+                            span {
+                                class: "picker-text",
+                                {picker_preview(item.plain_text())}
+                            }
+                        }
+                        if let Some(app) = &item.source_app {
+                            span {
+                                class: "picker-source",
+                                "{app}"
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 按模糊匹配得分筛选条目，得分相同时保持原来（最新在前）的顺序
+fn picker_matches(clips: &[ClipboardItem], query: &str) -> Vec<ClipboardItem> {
+    let mut scored: Vec<(i64, &ClipboardItem)> = clips
+        .iter()
+        .filter_map(|item| {
+            let score = match item.item_type.as_str() {
+                "image" => fuzzy_score(&format!("image {}", item.source_app.as_deref().unwrap_or_default()), query),
+                "file" => fuzzy_score(&item.file_names(), query),
+                _ => fuzzy_score(item.plain_text(), query),
+            };
+            score.map(|score| (score, item))
+        })
+        .collect();
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored.into_iter().map(|(_, item)| item.clone()).collect()
+}
+
+// 模糊匹配：查询中的字符按顺序出现在文本里即命中（忽略大小写和空白），
+// 连续命中的得分更高，第一个命中越靠前越好
+fn fuzzy_score(text: &str, query: &str) -> Option<i64> {
+    let mut pending = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .peekable();
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+    for (position, c) in text.chars().flat_map(char::to_lowercase).enumerate() {
+        let Some(&wanted) = pending.peek() else {
+            break;
+        };
+        if c != wanted {
+            continue;
+        }
+        pending.next();
+        match previous {
+            Some(last) if last + 1 == position => score += 8,
+            Some(_) => score += 1,
+            None => score -= position.min(20) as i64,
+        }
+        previous = Some(position);
+    }
+    pending.peek().is_none().then_some(score)
+}
+
+// 列表中显示的单行预览
+fn picker_preview(content: &str) -> String {
+    let line = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() > 80 {
+        format!("{}…", line.chars().take(80).collect::<String>())
+    } else {
+        line
+    }
+}
+
+// 把条目放回剪贴板并关闭窗口；localStorage 中 synapse-picker-paste 为 true 时随后模拟粘贴
+fn paste_clip(item: ClipboardItem) {
+    let Some(id) = item.id else {
+        return;
+    };
+    spawn(async move {
+        let _ = eval(&format!(
+            r#"
+            try {{
+                await window.__TAURI__.core.invoke('history_copy', {{ id: {} }});
+                await window.__TAURI__.core.invoke('picker_close', {{ paste: localStorage.getItem('synapse-picker-paste') === 'true' }});
+            }} catch (e) {{
+                console.error("Failed to paste clip: " + e);
+            }}
+            "#,
+            id
+        ))
+        .await;
+    });
+}
+
+fn close_picker(paste: bool) {
+    spawn(async move {
+        let _ = eval(&format!(
+            "window.__TAURI__.core.invoke('picker_close', {{ paste: {} }});",
+            paste
+        ))
+        .await;
+    });
+}
+
+#[component]
+fn App() -> Element {
+    let clipboard_history = use_signal(|| Vec::<ClipboardItem>::new());
+    let mut show_devices = use_signal(|| false);
+    let mut show_sync = use_signal(|| false);
+    let mut filter = use_signal(|| HistoryFilter::All);
+    // 搜索词；Web 模式下由后端全文检索，结果是匹配条目的 id
+    let mut query = use_signal(String::new);
+    let mut server_matches = use_signal(|| None::<HashSet<i64>>);
+    let signed_in_user = match try_use_context::<Signal<AuthState>>().map(|auth| auth.read().clone()) {
+        Some(AuthState::SignedIn { username }) => Some(username),
+        _ => None,
+    };
+    let search_on_server = signed_in_user.is_some();
+    // 桌面端从托盘菜单暂停记录，暂停期间剪贴板变化不进入历史（由 Rust 侧判断，这里只显示提示）
+    let mut capture_paused = use_signal(|| false);
+
+    // 桌面端：剪贴板由 Rust 侧记录，这里把 localStorage 中的设备身份、敏感内容规则
+    // （synapse-sensitive-rules，JSON，格式与服务端配置的 [sensitive] 段相同）和保留策略（synapse-retention）交给它
+    use_effect(move || {
+        spawn(async move {
+            let _ = eval(
+                r#"
+                if (window.__TAURI__) {
+                    const device = window.__synapseDevice;
+                    window.__TAURI__.core.invoke('configure_capture', {
+                        device: device ? { id: device.id, name: device.name } : null,
+                        rules: localStorage.getItem('synapse-sensitive-rules'),
+                        retention: localStorage.getItem('synapse-retention'),
+                    }).catch((e) => console.error("Failed to configure capture: " + e));
+                }
+                "#,
+            );
+        });
+    });
+
+    // 桌面端：托盘菜单暂停记录时显示提示
+    use_effect(move || {
+        spawn(async move {
+            let mut paused = eval(
+                r#"
+                if (!window.__TAURI__) {
+                    return;
+                }
+                await window.__TAURI__.event.listen('capture://paused', (event) => dioxus.send(event.payload));
+                // 页面重新加载时恢复当前的暂停状态
+                dioxus.send(await window.__TAURI__.core.invoke('capture_paused').catch(() => false));
+            "#,
+            );
+            while let Ok(msg) = paused.recv::<bool>().await {
+                capture_paused.set(msg);
+            }
+        });
+    });
+
+    // 桌面端：应用 localStorage 中配置的快速粘贴快捷键（未配置时使用默认的 Ctrl+Shift+V）
+    use_effect(move || {
+        spawn(async move {
+            let _ = eval(
+                r#"
+                if (window.__TAURI__) {
+                    window.__TAURI__.core.invoke('set_picker_shortcut', { shortcut: localStorage.getItem('synapse-picker-shortcut') })
+                        .catch((e) => console.error("Failed to register quick picker shortcut: " + e));
+                }
+                "#,
+            );
+        });
+    });
+
+    // Web 模式：订阅后端事件流，cursor=0 会先回放当前完整历史
+    // EventSource 断线重连时会自动带上 Last-Event-ID，从断点继续
+    // Tauri 模式：历史在 Rust 侧，先订阅 history://event（格式相同），再把现有的条目当作新建事件读入
+    use_effect(move || {
+        spawn(async move {
+            let mut events = eval(
+                r#"
+                if (window.__TAURI__) {
+                    await window.__TAURI__.event.listen('history://event', (event) => dioxus.send(event.payload));
+                    const clips = await window.__TAURI__.core.invoke('history_list');
+                    clips.forEach((clip) => dioxus.send({ type: 'created', clip: clip }));
+                    return;
+                }
+                const source = new EventSource('/api/events?cursor=0');
+                ['created', 'updated', 'deleted', 'cleared'].forEach((name) => {
+                    source.addEventListener(name, (e) => dioxus.send(JSON.parse(e.data)));
+                });
             "#,
             );
 
             let mut history = clipboard_history;
-            while let Ok(msg) = handler.recv().await {
-                match serde_json::from_value::<ClipboardItem>(msg) {
-                    Ok(item) => {
-                        let content = item.content.trim();
-                        if !content.is_empty() {
-                            history.write().push(item);
+            while let Ok(msg) = events.recv().await {
+                match serde_json::from_value::<ServerEvent>(msg) {
+                    Ok(ServerEvent::Created { clip }) => {
+                        if !history.read().iter().any(|item| item.id.is_some() && item.id == clip.id) {
+                            history.write().push(*clip);
+                        }
+                    }
+                    Ok(ServerEvent::Updated { clip }) => {
+                        let mut entries = history.write();
+                        if let Some(index) = entries.iter().position(|item| item.id.is_some() && item.id == clip.id) {
+                            // 重复复制会更新时间，这时移到最新的位置
+                            if clip.timestamp > entries[index].timestamp {
+                                entries.remove(index);
+                                entries.push(*clip);
+                            } else {
+                                entries[index] = *clip;
+                            }
                         }
                     }
+                    Ok(ServerEvent::Deleted { id }) => {
+                        history.write().retain(|item| item.id != Some(id));
+                    }
+                    Ok(ServerEvent::Cleared) => {
+                        history.write().clear();
+                    }
                     Err(e) => {
-                        // 如果解析失败，记录警告（使用 console.warn 在浏览器中显示）
-                        let error_msg = format!("Failed to parse clipboard item: {:?}", e);
+                        let error_msg = format!("Failed to parse server event: {:?}", e);
                         let _ = eval(&format!(
                             r#"console.warn("{}");"#,
                             error_msg.replace('"', "\\\"")
@@ -384,19 +1292,85 @@ fn App() -> Element {
             // History List
             main {
                 class: "main-content",
+                if capture_paused() {
+                    div {
+                        class: "capture-paused",
+                        "Capture paused. Resume it from the tray menu."
+                    }
+                }
                 if clipboard_history.read().is_empty() {
                     div {
                         class: "empty-state",
                         "Waiting for clipboard changes..."
                     }
                 } else {
-                    for (rev_idx, item) in clipboard_history.read().iter().enumerate().rev() {
+                    // 搜索框：输入停顿后再请求后端，等待结果时先在本地匹配
+                    input {
+                        class: "history-search",
+                        r#type: "search",
+                        placeholder: "Search history",
+                        value: "{query}",
+                        oninput: move |e| {
+                            let value = e.value();
+                            query.set(value.clone());
+                            server_matches.set(None);
+                            if !search_on_server || value.trim().is_empty() {
+                                return;
+                            }
+                            spawn(async move {
+                                let _ = eval("await new Promise((resolve) => setTimeout(resolve, 250));").await;
+                                if *query.peek() != value {
+                                    return;
+                                }
+                                let ids = eval(&format!(
+                                    r#"
+                                    const response = await fetch('/api/search?limit=500&q=' + encodeURIComponent({}));
+                                    const page = await response.json();
+                                    return page.items.map((clip) => clip.id);
+                                "#,
+                                    serde_json::to_string(&value).unwrap_or_default()
+                                ))
+                                .await;
+                                // 期间又有新的输入时丢弃过时的结果
+                                if let Ok(Ok(ids)) = ids.map(serde_json::from_value::<Vec<i64>>) {
+                                    if *query.peek() == value {
+                                        server_matches.set(Some(ids.into_iter().collect()));
+                                    }
+                                }
+                            });
+                        },
+                    }
+                    // 筛选栏：全部、收藏以及历史中出现的集合
+                    nav {
+                        class: "history-filter",
+                        button {
+                            class: if filter() == HistoryFilter::All { "filter-chip filter-chip-active" } else { "filter-chip" },
+                            onclick: move |_| filter.set(HistoryFilter::All),
+                            "All"
+                        }
+                        button {
+                            class: if filter() == HistoryFilter::Favorites { "filter-chip filter-chip-active" } else { "filter-chip" },
+                            onclick: move |_| filter.set(HistoryFilter::Favorites),
+                            "Favorites"
+                        }
+                        for name in collection_names(&clipboard_history.read()) {
+                            button {
+                                key: "{name}",
+                                class: if filter() == HistoryFilter::Collection(name.clone()) { "filter-chip filter-chip-active" } else { "filter-chip" },
+                                onclick: {
+                                    let name = name.clone();
+                                    move |_| filter.set(HistoryFilter::Collection(name.clone()))
+                                },
+                                "{name}"
+                            }
+                        }
+                    }
+                    // 最新的在前，置顶的条目排在最前面
+                    for (idx, item) in visible_history(&clipboard_history.read(), &filter.read(), &query.read(), server_matches.read().as_ref()) {
                         ClipboardItemView {
-                            key: "{rev_idx}",
-                            item: item.clone(),
-                            rev_index: rev_idx,
-                            total_len: clipboard_history.read().len(),
-                            clipboard_history: clipboard_history_for_delete
+                            key: "{idx}",
+                            item: item,
+                            highlight: query()
                         }
                     }
                 }
@@ -405,16 +1379,155 @@ fn App() -> Element {
             // Footer / Taskbar info
             footer {
                 class: "footer",
-                "Running in background | Tray icon active"
+                if let Some(username) = signed_in_user {
+                    span { "Signed in as {username} | " }
+                    button {
+                        class: "footer-link",
+                        onclick: move |_| show_devices.set(true),
+                        "Devices"
+                    }
+                    span { " | " }
+                    button {
+                        class: "footer-link",
+                        onclick: move |_| {
+                            spawn(async move {
+                                // 退出后重新加载页面，同时关闭事件流
+                                let _ = eval(
+                                    r#"
+                                    await fetch('/api/auth/logout', { method: 'POST' });
+                                    location.reload();
+                                "#,
+                                )
+                                .await;
+                            });
+                        },
+                        "Sign out"
+                    }
+                } else {
+                    span { "Running in background | Tray icon active | " }
+                    button {
+                        class: "footer-link",
+                        onclick: move |_| show_sync.set(true),
+                        "Sync"
+                    }
+                }
+            }
+
+            if show_devices() {
+                DevicesDialog { on_close: move |_| show_devices.set(false) }
+            }
+            if show_sync() {
+                SyncDialog { on_close: move |_| show_sync.set(false) }
             }
         }
     }
 }
 
+// 按筛选条件和搜索词取出要显示的条目及其在历史中的下标，最新的在前，置顶的在最前
+// 后端条目以后端的检索结果为准，本地条目（以及结果返回之前）在本地匹配
+fn visible_history(
+    history: &[ClipboardItem],
+    filter: &HistoryFilter,
+    query: &str,
+    server_matches: Option<&HashSet<i64>>,
+) -> Vec<(usize, ClipboardItem)> {
+    let mut items: Vec<_> = history
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, item)| filter.matches(item))
+        .filter(|(_, item)| match (item.id, server_matches) {
+            _ if query.trim().is_empty() => true,
+            (Some(id), Some(matches)) => matches.contains(&id),
+            _ => matches_query(item, query),
+        })
+        .map(|(idx, item)| (idx, item.clone()))
+        .collect();
+    items.sort_by_key(|(_, item)| !item.pinned);
+    items
+}
+
+// 未加密的文本和 HTML 是否包含所有搜索词（忽略大小写）
+fn matches_query(item: &ClipboardItem, query: &str) -> bool {
+    if item.key_version.is_some() || !matches!(item.item_type.as_str(), "text" | "html") {
+        return false;
+    }
+    let content = item.content.to_lowercase();
+    query
+        .split_whitespace()
+        .all(|term| content.contains(&term.to_lowercase()))
+}
+
+// 把文本按搜索词切成片段，true 表示命中需要高亮（只对 ASCII 忽略大小写，保证下标不变）
+fn highlight_segments(text: &str, query: &str) -> Vec<(String, bool)> {
+    let lower = text.to_ascii_lowercase();
+    let mut ranges: Vec<(usize, usize)> = query
+        .split_whitespace()
+        .flat_map(|term| {
+            let term = term.to_ascii_lowercase();
+            lower
+                .match_indices(&term)
+                .map(|(start, found)| (start, start + found.len()))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    ranges.sort();
+    let mut segments = Vec::new();
+    let mut position = 0;
+    for (start, end) in ranges {
+        if end <= position {
+            continue;
+        }
+        let start = start.max(position);
+        if start > position {
+            segments.push((text[position..start].to_string(), false));
+        }
+        segments.push((text[start..end].to_string(), true));
+        position = end;
+    }
+    if position < text.len() {
+        segments.push((text[position..].to_string(), false));
+    }
+    segments
+}
+
+// 历史中出现过的集合名，按名称排序
+fn collection_names(history: &[ClipboardItem]) -> Vec<String> {
+    let mut names: Vec<String> = history.iter().filter_map(|item| item.collection.clone()).collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+// 修改条目的置顶、收藏或集合：Web 模式通过 API 修改，事件流会同步到所有设备；
+// Tauri 模式交给 Rust 侧的本地历史。集合名为空表示移出集合
+fn update_item(id: Option<i64>, update: serde_json::Value) {
+    let Some(id) = id else {
+        return;
+    };
+    spawn(async move {
+        let _ = eval(&format!(
+            r#"
+            const update = {update};
+            if (window.__TAURI__) {{
+                await window.__TAURI__.core.invoke('history_update', {{ id: {id}, update: update }});
+            }} else {{
+                await fetch('/api/clips/{id}', {{ method: 'PATCH', headers: {{ 'Content-Type': 'application/json' }}, body: JSON.stringify(update) }});
+            }}
+            "#
+        ))
+        .await;
+    });
+}
+
 #[component]
-fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, clipboard_history: Signal<Vec<ClipboardItem>>) -> Element {
-    let original_idx = total_len - 1 - rev_index;
+fn ClipboardItemView(item: ClipboardItem, highlight: String) -> Element {
     let mut show_modal = use_signal(|| false);
+    // 弹窗中 HTML 的显示方式：沙箱预览，以及是否加载远程图片
+    let mut sandboxed = use_signal(|| false);
+    let mut remote_images = use_signal(|| false);
+    // 列表中的图片加载完成后去掉 blurhash 占位背景
+    let mut image_loaded = use_signal(|| false);
     let toast = use_toast();
     // 格式化时间（简单的格式化）
     let time_str = item.timestamp.map(|ts| {
@@ -427,15 +1540,7 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
     }).unwrap_or_else(|| "未知时间".to_string());
     
     // 格式化大小
-    let size_str = item.size.map(|s| {
-        if s < 1024 {
-            format!("{} B", s)
-        } else if s < 1024 * 1024 {
-            format!("{:.2} KB", s as f64 / 1024.0)
-        } else {
-            format!("{:.2} MB", s as f64 / (1024.0 * 1024.0))
-        }
-    }).unwrap_or_else(|| "未知大小".to_string());
+    let size_str = item.size.map(format_size).unwrap_or_else(|| "未知大小".to_string());
     
     // 格式化类型
     let type_str = match item.item_type.as_str() {
@@ -444,205 +1549,139 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
         "file" => "文件",
         _ => "文本"
     };
+    // 多格式条目列出同时保存的其他格式
+    let type_str = std::iter::once(type_str)
+        .chain(item.formats.keys().map(|mime| match mime.as_str() {
+            "text/plain" => "纯文本",
+            "text/rtf" => "RTF",
+            "text/html" => "HTML",
+            _ => mime.as_str(),
+        }))
+        .collect::<Vec<_>>()
+        .join(" + ");
     
     // 复制功能（带成功/失败提示）
-    let copy_content = item.content.clone();
-    let copy_type = item.item_type.clone();
-    let toast_for_copy = toast.clone();
+    // Tauri 模式由 Rust 侧写回剪贴板，这次变化不会再被记录；Web 模式使用浏览器的剪贴板 API
+    let copy_id = item.id;
+    // 浏览器不能把文件放进剪贴板，文件条目复制路径列表
+    let copy_content = match item.item_type.as_str() {
+        "file" => item.files().iter().map(|file| file.path.as_str()).collect::<Vec<_>>().join("\n"),
+        _ => item.content.clone(),
+    };
+    let copy_plain = item.plain_text().to_string();
+    let copy_image_src = if item.item_type == "image" { item.image_src() } else { String::new() };
+    let is_image = item.item_type == "image";
+    let is_html = item.item_type == "html";
     let on_copy = move |_| {
         let content = copy_content.clone();
-        let item_type = copy_type.clone();
-        let toast_clone = toast_for_copy.clone();
+        let plain = copy_plain.clone();
+        let image_src = copy_image_src.clone();
         spawn(async move {
-            // 在复制前停止后端监控（只停止监控，不停止事件监听器）
-            let _ = eval(
+            let result = eval(&format!(
                 r#"
-                (async function() {
-                    try {
-                        // 只停止后端监控，事件监听器保持运行
-                        await window.__TAURI__.core.invoke('plugin:clipboard|stop_monitor');
-                    } catch (e) {
-                        console.error("Failed to stop clipboard monitor: " + e);
-                    }
-                })()
-                "#
-            ).await;
-            
-            if item_type == "image" {
-                let result = eval(&format!(
-                    r#"
-                    (async function() {{
-                        try {{
-                            // 处理 base64 数据：移除 data:image/png;base64, 前缀（如果存在）
-                            let base64Data = {};
-                            if (typeof base64Data === 'string' && base64Data.startsWith('data:')) {{
-                                // 移除 data:image/png;base64, 前缀
-                                base64Data = base64Data.split(',')[1];
-                            }}
-                            await window.__TAURI__.core.invoke('plugin:clipboard|write_image_base64', {{ base64Image: base64Data }});
-                            return JSON.stringify({{ success: true, message: '图片已复制到剪贴板' }});
-                        }} catch (e) {{
-                            return JSON.stringify({{ success: false, message: '复制图片失败: ' + e.message }});
-                        }}
-                    }})()
-                    "#,
-                    serde_json::to_string(&content).unwrap_or_default()
-                )).await;
-                
-                match result {
-                    Ok(result_value) => {
-                        // 默认值：假设成功（因为如果 invoke 没有抛出异常，通常意味着成功）
-                        let mut success_flag = true;
-                        let mut message = "图片已复制到剪贴板".to_string();
-                        
-                        // 先尝试解析为 JSON 字符串（因为 JavaScript 返回的是 JSON.stringify(...)）
-                        if let Some(json_str) = result_value.as_str() {
-                            if let Ok(result_obj) = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(json_str) {
-                                if let Some(success) = result_obj.get("success").and_then(|v| v.as_bool()) {
-                                    success_flag = success;
-                                    if let Some(msg) = result_obj.get("message").and_then(|v| v.as_str()) {
-                                        message = msg.to_string();
-                                    }
-                                }
-                            }
-                        } else {
-                            // 如果不是字符串，尝试直接解析为对象（Dioxus 可能已经自动序列化了）
-                            if let Ok(result_obj) = serde_json::from_value::<serde_json::Map<String, serde_json::Value>>(result_value) {
-                                if let Some(success) = result_obj.get("success").and_then(|v| v.as_bool()) {
-                                    success_flag = success;
-                                    if let Some(msg) = result_obj.get("message").and_then(|v| v.as_str()) {
-                                        message = msg.to_string();
-                                    }
-                                }
-                            }
-                        }
-                        
-                        // 显示 toast
-                        let title = if success_flag { "复制成功" } else { "复制失败" };
-                        let options = dioxus_primitives::toast::ToastOptions::default().description(message);
-                        if success_flag {
-                            toast_clone.success(title.to_string(), options);
-                        } else {
-                            toast_clone.error(title.to_string(), options);
-                        }
-                    }
-                    Err(_) => {
-                        // eval 执行失败，但复制操作可能已经成功，显示成功提示
-                        let options = dioxus_primitives::toast::ToastOptions::default().description("图片已复制到剪贴板".to_string());
-                        toast_clone.success("复制成功".to_string(), options);
-                    }
+                try {{
+                    if (window.__TAURI__) {{
+                        await window.__TAURI__.core.invoke('history_copy', {{ id: {id} }});
+                    }} else if ({is_image}) {{
+                        const blob = await (await fetch({image_src})).blob();
+                        await navigator.clipboard.write([new ClipboardItem({{ [blob.type]: blob }})]);
+                    }} else if ({is_html}) {{
+                        await navigator.clipboard.write([new ClipboardItem({{
+                            'text/html': new Blob([{content}], {{ type: 'text/html' }}),
+                            'text/plain': new Blob([{plain}], {{ type: 'text/plain' }}),
+                        }})]);
+                    }} else {{
+                        await navigator.clipboard.writeText({content});
+                    }}
+                    return null;
+                }} catch (e) {{
+                    return String(e);
+                }}
+                "#,
+                id = copy_id.map_or("null".to_string(), |id| id.to_string()),
+                content = serde_json::to_string(&content).unwrap_or_default(),
+                plain = serde_json::to_string(&plain).unwrap_or_default(),
+                image_src = serde_json::to_string(&image_src).unwrap_or_default(),
+            ))
+            .await;
+            let what = if is_image { "图片" } else { "文本" };
+            match result {
+                Ok(serde_json::Value::String(error)) => {
+                    let options = dioxus_primitives::toast::ToastOptions::default().description(format!("复制{what}失败: {error}"));
+                    toast.error("复制失败".to_string(), options);
                 }
-                
-                // 复制完成后，重新启动后端监控（无论成功还是失败）
-                // 注意：只重新启动监控，事件监听器保持运行，不需要重新设置
-                let _ = eval(
-                    r#"
-                    (async function() {
-                        try {
-                            // 只重新启动后端监控，事件监听器保持运行
-                            await window.__TAURI__.core.invoke('plugin:clipboard|start_monitor');
-                        } catch (e) {
-                            console.error("Failed to restart clipboard monitor: " + e);
-                        }
-                    })()
-                    "#
-                ).await;
-            } else {
-                let text_content = content;
-                let result = eval(&format!(
-                    r#"
-                    (async function() {{
-                        try {{
-                            await window.__TAURI__.core.invoke('plugin:clipboard|write_text', {{ text: {} }});
-                            return JSON.stringify({{ success: true, message: '文本已复制到剪贴板' }});
-                        }} catch (e) {{
-                            return JSON.stringify({{ success: false, message: '复制文本失败: ' + e.message }});
-                        }}
-                    }})()
-                    "#,
-                    serde_json::to_string(&text_content).unwrap_or_default()
-                )).await;
-                
-                match result {
-                    Ok(result_value) => {
-                        // 默认值：假设成功（因为如果 invoke 没有抛出异常，通常意味着成功）
-                        let mut success_flag = true;
-                        let mut message = "文本已复制到剪贴板".to_string();
-                        
-                        // 先尝试解析为 JSON 字符串（因为 JavaScript 返回的是 JSON.stringify(...)）
-                        if let Some(json_str) = result_value.as_str() {
-                            if let Ok(result_obj) = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(json_str) {
-                                if let Some(success) = result_obj.get("success").and_then(|v| v.as_bool()) {
-                                    success_flag = success;
-                                    if let Some(msg) = result_obj.get("message").and_then(|v| v.as_str()) {
-                                        message = msg.to_string();
-                                    }
-                                }
-                            }
-                        } else {
-                            // 如果不是字符串，尝试直接解析为对象（Dioxus 可能已经自动序列化了）
-                            if let Ok(result_obj) = serde_json::from_value::<serde_json::Map<String, serde_json::Value>>(result_value) {
-                                if let Some(success) = result_obj.get("success").and_then(|v| v.as_bool()) {
-                                    success_flag = success;
-                                    if let Some(msg) = result_obj.get("message").and_then(|v| v.as_str()) {
-                                        message = msg.to_string();
-                                    }
-                                }
-                            }
-                        }
-                        
-                        // 显示 toast
-                        let title = if success_flag { "复制成功" } else { "复制失败" };
-                        let options = dioxus_primitives::toast::ToastOptions::default().description(message);
-                        if success_flag {
-                            toast_clone.success(title.to_string(), options);
-                        } else {
-                            toast_clone.error(title.to_string(), options);
-                        }
-                    }
-                    Err(_) => {
-                        // eval 执行失败，但复制操作可能已经成功，显示成功提示
-                        let options = dioxus_primitives::toast::ToastOptions::default().description("文本已复制到剪贴板".to_string());
-                        toast_clone.success("复制成功".to_string(), options);
-                    }
+                _ => {
+                    let options = dioxus_primitives::toast::ToastOptions::default().description(format!("{what}已复制到剪贴板"));
+                    toast.success("复制成功".to_string(), options);
                 }
-                
-                // 复制完成后，重新启动后端监控（无论成功还是失败）
-                // 注意：只重新启动监控，事件监听器保持运行，不需要重新设置
-                let _ = eval(
-                    r#"
-                    (async function() {
-                        try {
-                            // 只重新启动后端监控，事件监听器保持运行
-                            await window.__TAURI__.core.invoke('plugin:clipboard|start_monitor');
-                        } catch (e) {
-                            console.error("Failed to restart clipboard monitor: " + e);
-                        }
-                    })()
-                    "#
-                ).await;
             }
         });
     };
     
     // 内容区域
     let content_area = match item.item_type.as_str() {
+        // 端到端加密的内容只能在持有密钥的设备上解密
+        _ if item.key_version.is_some() => {
+            rsx! {
+                div {
+                    class: "history-item-content history-item-encrypted",
+                    "已端到端加密（密钥版本 {item.key_version.unwrap_or_default()}），请在已批准的桌面端或 CLI 中查看"
+                }
+            }
+        }
         "html" => {
+            let html = sanitize::sanitize_html(&item.content, false);
             rsx! {
                 div {
                     class: "history-item-content history-item-html",
-                    dangerous_inner_html: "{item.content}"
+                    dangerous_inner_html: "{html}"
+                }
+            }
+        }
+        "file" => {
+            let files = item.files();
+            rsx! {
+                ul {
+                    class: "history-item-content history-item-files",
+                    for file in files {
+                        li {
+                            // 经后端传输的文件可以直接下载
+                            if let Some(url) = file.download_url() {
+                                a {
+                                    href: "{url}",
+                                    download: "",
+                                    "{file.name()}"
+                                }
+                            } else {
+                                span { title: "{file.path}", "{file.name()}" }
+                            }
+                            span {
+                                class: "history-item-file-size",
+                                if file.folder { "文件夹" } else { "{format_size(file.size)}" }
+                            }
+                        }
+                    }
                 }
             }
         }
         "image" => {
-            let mime = item.mime_type.clone().unwrap_or_else(|| "image/png".to_string());
+            // 加载完成前以 blurhash 为背景；宽高属性让浏览器提前按比例留出位置
+            let placeholder = match &item.preview {
+                Some(preview) if !image_loaded() => blurhash::data_url(&preview.blurhash)
+                    .map(|url| format!("background: url({url}) center / cover;"))
+                    .unwrap_or_default(),
+                _ => String::new(),
+            };
             rsx! {
                 div {
                     class: "history-item-content history-item-image",
                     img {
-                        src: "data:{mime};base64,{item.content}",
-                        style: "max-width: 100%; height: auto; border-radius: 4px;"
+                        src: "{item.thumbnail_src()}",
+                        width: item.preview.as_ref().map(|preview| preview.thumbnail_width.to_string()),
+                        height: item.preview.as_ref().map(|preview| preview.thumbnail_height.to_string()),
+                        loading: "lazy",
+                        onload: move |_| image_loaded.set(true),
+                        style: "max-width: 100%; height: auto; border-radius: 4px; {placeholder}"
                     }
                 }
             }
@@ -689,7 +1728,14 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
                     style: "position: relative;",
                     pre {
                         style: if is_code { "margin: 0; font-family: 'Monaco', 'Menlo', 'Ubuntu Mono', 'Consolas', 'source-code-pro', monospace; white-space: pre-wrap; word-wrap: break-word;" } else { "margin: 0; white-space: pre-wrap; word-wrap: break-word;" },
-                        "{display_content}"
+                        // 高亮搜索命中的部分
+                        for (text, hit) in highlight_segments(&display_content, &highlight) {
+                            if hit {
+                                mark { class: "search-hit", "{text}" }
+                            } else {
+                                "{text}"
+                            }
+                        }
                     }
                     if is_truncated {
                         div {
@@ -704,7 +1750,9 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
     
     // 判断是否需要显示查看按钮
     let should_show_view = {
-        if item.item_type == "image" {
+        if item.key_version.is_some() {
+            false
+        } else if item.item_type == "image" {
             true
         } else if item.item_type == "text" || item.item_type == "html" {
             const MAX_LINES: usize = 10;
@@ -719,9 +1767,8 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
     // 准备弹窗内容
     let modal_title = if item.item_type == "image" { "查看图片" } else { "查看完整内容" };
     let view_button_text = if item.item_type == "image" { "查看" } else { "查看完整内容" };
-    let image_mime = item.mime_type.clone().unwrap_or_else(|| "image/png".to_string());
     let image_src = if item.item_type == "image" {
-        format!("data:{};base64,{}", image_mime, item.content)
+        item.image_src()
     } else {
         String::new()
     };
@@ -765,7 +1812,7 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
         let extension = match item.item_type.as_str() {
             "image" => {
                 // 从 mime type 获取扩展名
-                match image_mime.as_str() {
+                match item.mime_type.as_deref().unwrap_or("image/png") {
                     "image/png" => "png",
                     "image/jpeg" | "image/jpg" => "jpg",
                     "image/gif" => "gif",
@@ -795,7 +1842,8 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
     };
     
     // 下载功能
-    let download_content = item.content.clone();
+    // 图片下载其地址，文本下载内容本身
+    let download_content = if item.item_type == "image" { item.image_src() } else { item.content.clone() };
     let download_type = item.item_type.clone();
     let download_filename = filename.clone();
     let download_mime = item.mime_type.clone();
@@ -829,17 +1877,8 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
                             filters = [{{ name: 'Images', extensions: ['png', 'jpg', 'jpeg', 'gif', 'webp', 'svg'] }}];
                             defaultPath = {};
                             
-                            // 将 base64 转换为 Uint8Array
-                            // base64 数据可能包含 data:image/png;base64, 前缀，需要移除
-                            let base64Data = {};
-                            if (base64Data.startsWith('data:')) {{
-                                base64Data = base64Data.split(',')[1];
-                            }}
-                            const binaryString = atob(base64Data);
-                            const bytes = new Uint8Array(binaryString.length);
-                            for (let i = 0; i < binaryString.length; i++) {{
-                                bytes[i] = binaryString.charCodeAt(i);
-                            }}
+                            // 图片地址可能是 data URL 或后端的 /api/blobs/{{hash}}，统一用 fetch 取出字节
+                            const bytes = new Uint8Array(await (await fetch({})).arrayBuffer());
                             
                             const filePath = await save({{
                                 filters: filters,
@@ -915,6 +1954,11 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
         });
     };
     
+    let delete_id = item.id;
+    let pinned = item.pinned;
+    let favorite = item.favorite;
+    let current_collection = item.collection.clone().unwrap_or_default();
+
     rsx! {
         div {
             class: "history-item",
@@ -926,6 +1970,21 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
                     span { class: "meta-item", "时间: {time_str}" }
                     span { class: "meta-item", "设备: {item.device.as_ref().unwrap_or(&\"未知\".to_string())}" }
                     span { class: "meta-item", "用户: {item.username.as_ref().unwrap_or(&\"未知\".to_string())}" }
+                    if let Some(app) = &item.source_app {
+                        span { class: "meta-item", "来源: {app}" }
+                    }
+                    if item.pinned {
+                        span { class: "meta-item meta-badge", "置顶" }
+                    }
+                    if item.favorite {
+                        span { class: "meta-item meta-badge", "收藏" }
+                    }
+                    if let Some(collection) = &item.collection {
+                        span { class: "meta-item meta-badge", "集合: {collection}" }
+                    }
+                    if item.copy_count > 1 {
+                        span { class: "meta-item", "复制 {item.copy_count} 次" }
+                    }
                     span { class: "meta-item", "大小: {size_str}" }
                     span { class: "meta-item", "类型: {type_str}" }
                 }
@@ -939,8 +1998,6 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
                 button {
                     class: "action-button action-button-delete",
                     onclick: move |_| {
-                        let idx = original_idx;
-                        let mut history_for_delete = clipboard_history;
                         spawn(async move {
                             // 显示确认对话框
                             let confirmed = eval(r#"
@@ -951,16 +2008,55 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
                             
                             if let Ok(confirmed_value) = confirmed {
                                 if let Ok(true) = serde_json::from_value::<bool>(confirmed_value) {
-                                    history_for_delete.write().remove(idx);
+                                    // Web 模式通过 API 删除，Tauri 模式删除 Rust 侧的本地条目，
+                                    // 都由事件同步移除
+                                    if let Some(id) = delete_id {
+                                        let _ = eval(&format!(
+                                            "window.__TAURI__ ? window.__TAURI__.core.invoke('history_delete', {{ id: {0} }}) : fetch('/api/clips/{0}', {{ method: 'DELETE' }})",
+                                            id
+                                        )).await;
+                                    }
                                 }
                             }
                         });
                     },
                     "删除"
                 }
-                // 右侧：查看、下载、复制按钮组
+                // 右侧：置顶、收藏、集合、查看、下载、复制按钮组
                 div {
                     class: "history-item-actions-right",
+                    button {
+                        class: "action-button action-button-view",
+                        onclick: move |_| {
+                            update_item(delete_id, serde_json::json!({ "pinned": !pinned }));
+                        },
+                        if pinned { "取消置顶" } else { "置顶" }
+                    }
+                    button {
+                        class: "action-button action-button-view",
+                        onclick: move |_| {
+                            update_item(delete_id, serde_json::json!({ "favorite": !favorite }));
+                        },
+                        if favorite { "取消收藏" } else { "收藏" }
+                    }
+                    button {
+                        class: "action-button action-button-view",
+                        onclick: move |_| {
+                            let current = current_collection.clone();
+                            spawn(async move {
+                                // 取消输入时不做修改
+                                let name = eval(&format!(
+                                    "return prompt('集合名称（留空则移出集合）', {});",
+                                    serde_json::to_string(&current).unwrap_or_default()
+                                ))
+                                .await;
+                                if let Ok(serde_json::Value::String(name)) = name {
+                                    update_item(delete_id, serde_json::json!({ "collection": name }));
+                                }
+                            });
+                        },
+                        "集合"
+                    }
                     // 查看按钮：文本类型且被截断时，或图片类型时显示
                     if should_show_view {
                         button {
@@ -971,10 +2067,13 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
                             "{view_button_text}"
                         }
                     }
-                    button {
-                        class: "action-button action-button-download",
-                        onclick: on_download,
-                        "下载"
+                    // 文件条目在列表中逐个下载
+                    if item.item_type != "file" {
+                        button {
+                            class: "action-button action-button-download",
+                            onclick: on_download,
+                            "下载"
+                        }
                     }
                     button {
                         class: "action-button action-button-copy",
@@ -1018,8 +2117,36 @@ fn ClipboardItemView(item: ClipboardItem, rev_index: usize, total_len: usize, cl
                             } else {
                                 if item.item_type == "html" {
                                     div {
-                                        class: "modal-html-content",
-                                        dangerous_inner_html: "{item.content}"
+                                        class: "modal-html-toolbar",
+                                        button {
+                                            class: "action-button",
+                                            onclick: move |_| {
+                                                let current = sandboxed();
+                                                sandboxed.set(!current);
+                                            },
+                                            if sandboxed() { "普通预览" } else { "沙箱预览" }
+                                        }
+                                        if !remote_images() && sanitize::has_remote_images(&item.content) {
+                                            span { class: "modal-html-notice", "已屏蔽远程图片" }
+                                            button {
+                                                class: "action-button",
+                                                onclick: move |_| remote_images.set(true),
+                                                "加载远程图片"
+                                            }
+                                        }
+                                    }
+                                    if sandboxed() {
+                                        // 不允许脚本、没有同源权限的 iframe，内容还受 CSP 限制
+                                        iframe {
+                                            class: "modal-html-sandbox",
+                                            "sandbox": "",
+                                            srcdoc: "{sanitize::sandbox_document(&item.content, remote_images())}"
+                                        }
+                                    } else {
+                                        div {
+                                            class: "modal-html-content",
+                                            dangerous_inner_html: "{sanitize::sanitize_html(&item.content, remote_images())}"
+                                        }
                                     }
                                 } else {
                                     pre {
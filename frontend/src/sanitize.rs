@@ -0,0 +1,155 @@
+// 剪贴板中的 HTML 来自任意网页，不能原样插入页面：桌面端的 webview 拥有文件系统等权限，
+// 一段 <img onerror> 就能在其中执行脚本。这里在 Rust 侧按白名单清理，只保留排版用的标签和属性，
+// 去掉脚本、事件处理器、表单和内嵌框架。远程图片默认屏蔽（会泄露 IP，也可以用来追踪），
+// 只保留内嵌的 data:image 图片，用户确认后才加载 https 图片
+use ammonia::Builder;
+use std::borrow::Cow;
+
+// 沙箱预览的内容安全策略：禁止脚本和一切网络请求，只允许内联样式和图片
+const SANDBOX_CSP: &str = "default-src 'none'; style-src 'unsafe-inline'; img-src data:";
+
+// 清理后可以直接用 dangerous_inner_html 渲染的 HTML
+pub fn sanitize_html(html: &str, remote_images: bool) -> String {
+    builder(remote_images).clean(html).to_string()
+}
+
+// 沙箱预览用的完整文档，放进不允许脚本、没有同源权限的 iframe（srcdoc）。
+// 沙箱中额外保留 style 属性，排版更接近原样；样式里的远程资源由 CSP 拦截
+pub fn sandbox_document(html: &str, remote_images: bool) -> String {
+    let body = builder(remote_images)
+        .add_generic_attributes(&["style"])
+        .clean(html)
+        .to_string();
+    let csp = if remote_images {
+        format!("{SANDBOX_CSP} https:")
+    } else {
+        SANDBOX_CSP.to_string()
+    };
+    format!(
+        r#"<!DOCTYPE html><html><head><meta charset="utf-8"><meta http-equiv="Content-Security-Policy" content="{csp}"><meta name="referrer" content="no-referrer"><style>body{{margin:0;font-family:system-ui,sans-serif;font-size:14px;line-height:1.6;word-wrap:break-word}}img{{max-width:100%;height:auto}}</style></head><body>{body}</body></html>"#
+    )
+}
+
+// 清理时是否屏蔽了远程图片，用于决定是否显示“加载远程图片”
+pub fn has_remote_images(html: &str) -> bool {
+    sanitize_html(html, false) != sanitize_html(html, true)
+}
+
+fn builder(remote_images: bool) -> Builder<'static> {
+    let mut builder = Builder::default();
+    builder
+        .add_url_schemes(&["data"])
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .set_tag_attribute_value("a", "target", "_blank")
+        .set_tag_attribute_value("img", "referrerpolicy", "no-referrer")
+        .attribute_filter(move |element, attribute, value| {
+            let is_image = element == "img" && attribute == "src";
+            if is_image && value.starts_with("data:image/") {
+                return Some(Cow::Borrowed(value));
+            }
+            if is_image {
+                return (remote_images && value.starts_with("https://"))
+                    .then_some(Cow::Borrowed(value));
+            }
+            // data: 只用于内嵌图片，链接等其他地方不允许
+            (!value.trim_start().starts_with("data:")).then_some(Cow::Borrowed(value))
+        });
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXEL: &str = "data:image/png;base64,iVBORw0KGgo=";
+
+    #[test]
+    fn strips_scripts_styles_and_frames() {
+        let html = sanitize_html(
+            r#"<p>hi</p><script>alert(1)</script><style>p{color:red}</style><iframe src="https://example.com"></iframe>"#,
+            true,
+        );
+        assert_eq!(html, "<p>hi</p>");
+    }
+
+    #[test]
+    fn strips_event_handlers() {
+        let html = sanitize_html(
+            &format!(
+                r#"<img src="{PIXEL}" onerror="alert(1)"><b onclick="alert(2)" onmouseover="alert(3)">x</b>"#
+            ),
+            false,
+        );
+        for handler in ["onerror", "onclick", "onmouseover"] {
+            assert!(!html.contains(handler), "{html}");
+        }
+        assert!(!html.contains("alert"), "{html}");
+        assert!(html.contains("<b>x</b>"), "{html}");
+    }
+
+    #[test]
+    fn strips_script_and_data_urls() {
+        for url in [
+            "javascript:alert(1)",
+            " JavaScript:alert(1)",
+            "data:text/html,<script>alert(1)</script>",
+            " data:image/png;base64,AAAA",
+        ] {
+            let html = sanitize_html(&format!(r#"<a href="{url}">link</a>"#), true);
+            assert!(!html.contains("href"), "{url}: {html}");
+        }
+        for url in ["javascript:alert(1)", "data:text/html,<b>x</b>"] {
+            let html = sanitize_html(&format!(r#"<img src="{url}">"#), true);
+            assert!(!html.contains("src"), "{url}: {html}");
+        }
+    }
+
+    #[test]
+    fn blocks_remote_images_until_allowed() {
+        let remote = r#"<img src="https://example.com/a.png">"#;
+        assert!(!sanitize_html(remote, false).contains("src"));
+        assert!(sanitize_html(remote, true).contains(r#"src="https://example.com/a.png""#));
+        assert!(has_remote_images(remote));
+        // 不加密的 http 图片始终屏蔽
+        assert!(!sanitize_html(r#"<img src="http://example.com/a.png">"#, true).contains("src"));
+        // 内嵌图片不算远程图片
+        let inline = format!(r#"<img src="{PIXEL}">"#);
+        assert!(sanitize_html(&inline, false).contains(PIXEL));
+        assert!(!has_remote_images(&inline));
+    }
+
+    #[test]
+    fn keeps_formatting() {
+        let html = sanitize_html(
+            r#"<h1>Title</h1><p><b>bold</b> <i>italic</i> <code>code</code></p><ul><li>one</li></ul><table><tbody><tr><td>cell</td></tr></tbody></table>"#,
+            false,
+        );
+        assert_eq!(
+            html,
+            "<h1>Title</h1><p><b>bold</b> <i>italic</i> <code>code</code></p><ul><li>one</li></ul><table><tbody><tr><td>cell</td></tr></tbody></table>"
+        );
+        let link = sanitize_html(r#"<a href="https://example.com/">link</a>"#, false);
+        assert!(link.contains(r#"href="https://example.com/""#), "{link}");
+        assert!(
+            link.contains(r#"rel="noopener noreferrer nofollow""#),
+            "{link}"
+        );
+        assert!(link.contains(r#"target="_blank""#), "{link}");
+    }
+
+    #[test]
+    fn sandbox_keeps_inline_styles_only() {
+        let html = r#"<p style="color: red">red</p><script>alert(1)</script>"#;
+        assert!(!sanitize_html(html, false).contains("style"));
+        let document = sandbox_document(html, false);
+        assert!(
+            document.contains(r#"<p style="color: red">red</p>"#),
+            "{document}"
+        );
+        assert!(!document.contains("alert"), "{document}");
+        assert!(document.contains(&format!(r#"content="{SANDBOX_CSP}""#)));
+        assert!(
+            sandbox_document(html, true).contains(&format!(r#"content="{SANDBOX_CSP} https:""#))
+        );
+    }
+}
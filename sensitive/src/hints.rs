@@ -0,0 +1,302 @@
+//! What the host tells about the clipboard content besides the content itself.
+//!
+//! Password managers put an extra, usually empty, format next to the secret
+//! they copy so that clipboard managers leave it alone: KeePassXC and KDE
+//! apps use `x-kde-passwordManagerHint`, macOS apps follow the
+//! nspasteboard.org conventions and Windows apps register formats that
+//! clipboard monitors are asked to respect.
+//!
+//! The source application is taken to be the one owning the focused window
+//! when the change is noticed; clipboard APIs do not record who copied.
+
+use crate::SourceApp;
+
+/// Formats that mark the clipboard content as not meant for history.
+pub const CONCEALED_FORMATS: &[&str] = &[
+    "x-kde-passwordManagerHint",
+    "org.nspasteboard.ConcealedType",
+    "org.nspasteboard.TransientType",
+    "org.nspasteboard.AutoGeneratedType",
+    "ExcludeClipboardContentFromMonitorProcessing",
+    "Clipboard Viewer Ignore",
+];
+
+/// The concealment format currently on the host clipboard, if any.
+///
+/// Blocks for at most a fraction of a second while the clipboard owner
+/// answers. Errors talking to the clipboard count as no hint.
+pub fn concealed() -> Option<String> {
+    platform::formats()
+        .into_iter()
+        .find(|format| CONCEALED_FORMATS.contains(&format.as_str()))
+}
+
+/// The application owning the focused window, where the platform exposes it.
+/// Window titles are only available on X11 and Windows.
+pub fn source_app() -> Option<SourceApp> {
+    platform::source_app()
+}
+
+/// X11, and Wayland sessions through XWayland.
+#[cfg(all(
+    unix,
+    not(any(
+        target_os = "macos",
+        target_os = "android",
+        target_os = "ios",
+        target_os = "emscripten"
+    ))
+))]
+mod platform {
+    use crate::SourceApp;
+    use std::{
+        error::Error,
+        thread,
+        time::{Duration, Instant},
+    };
+    use x11rb::{
+        connection::Connection,
+        protocol::{
+            xproto::{Atom, AtomEnum, ConnectionExt as _, CreateWindowAux, Window, WindowClass},
+            Event,
+        },
+        rust_connection::RustConnection,
+        COPY_DEPTH_FROM_PARENT, CURRENT_TIME, NONE,
+    };
+
+    /// How long the clipboard owner gets to answer.
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    pub fn formats() -> Vec<String> {
+        targets().unwrap_or_default()
+    }
+
+    pub fn source_app() -> Option<SourceApp> {
+        active_window().ok().flatten()
+    }
+
+    /// Formats offered by the current clipboard owner, from its `TARGETS`.
+    fn targets() -> Result<Vec<String>, Box<dyn Error>> {
+        let (conn, screen) = RustConnection::connect(None)?;
+        let root = &conn.setup().roots[screen];
+        // The window goes away with the connection
+        let window = conn.generate_id()?;
+        conn.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            root.root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            root.root_visual,
+            &CreateWindowAux::new(),
+        )?;
+        let clipboard = intern(&conn, b"CLIPBOARD")?;
+        let targets = intern(&conn, b"TARGETS")?;
+        let property = intern(&conn, b"SYNAPSE_TARGETS")?;
+        conn.convert_selection(window, clipboard, targets, property, CURRENT_TIME)?;
+        conn.flush()?;
+
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            match conn.poll_for_event()? {
+                Some(Event::SelectionNotify(event)) if event.requestor == window => {
+                    if event.property == NONE {
+                        return Ok(Vec::new());
+                    }
+                    let reply = conn
+                        .get_property(true, window, property, AtomEnum::ATOM, 0, 1024)?
+                        .reply()?;
+                    let atoms: Vec<u32> =
+                        reply.value32().map(Iterator::collect).unwrap_or_default();
+                    let names = atoms
+                        .into_iter()
+                        .map(|atom| conn.get_atom_name(atom))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    return names
+                        .into_iter()
+                        .map(|cookie| {
+                            Ok(String::from_utf8_lossy(&cookie.reply()?.name).into_owned())
+                        })
+                        .collect();
+                }
+                Some(_) => {}
+                None if Instant::now() >= deadline => return Ok(Vec::new()),
+                None => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+    }
+
+    /// The `WM_CLASS` class name and title of `_NET_ACTIVE_WINDOW`.
+    fn active_window() -> Result<Option<SourceApp>, Box<dyn Error>> {
+        let (conn, screen) = RustConnection::connect(None)?;
+        let root = conn.setup().roots[screen].root;
+        let active = intern(&conn, b"_NET_ACTIVE_WINDOW")?;
+        let window: Window = conn
+            .get_property(false, root, active, AtomEnum::WINDOW, 0, 1)?
+            .reply()?
+            .value32()
+            .and_then(|mut value| value.next())
+            .unwrap_or(NONE);
+        if window == NONE {
+            return Ok(None);
+        }
+
+        // WM_CLASS holds the instance and the class name, each NUL terminated
+        let class = property(&conn, window, AtomEnum::WM_CLASS.into())?;
+        let Some(name) = class
+            .rsplit(|&byte| byte == 0)
+            .find(|part| !part.is_empty())
+        else {
+            return Ok(None);
+        };
+        let name = String::from_utf8_lossy(name).into_owned();
+
+        let mut title = property(&conn, window, intern(&conn, b"_NET_WM_NAME")?)?;
+        if title.is_empty() {
+            title = property(&conn, window, AtomEnum::WM_NAME.into())?;
+        }
+        let title = (!title.is_empty()).then(|| String::from_utf8_lossy(&title).into_owned());
+        Ok(Some(SourceApp { name, title }))
+    }
+
+    fn intern(conn: &RustConnection, name: &[u8]) -> Result<Atom, Box<dyn Error>> {
+        Ok(conn.intern_atom(false, name)?.reply()?.atom)
+    }
+
+    fn property(
+        conn: &RustConnection,
+        window: Window,
+        property: Atom,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(conn
+            .get_property(false, window, property, AtomEnum::ANY, 0, 1024)?
+            .reply()?
+            .value)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use crate::SourceApp;
+    use objc2::{class, msg_send, rc::Retained, runtime::AnyObject, ClassType};
+    use objc2_app_kit::NSPasteboard;
+    use objc2_foundation::{NSArray, NSString};
+
+    /// Types on the general pasteboard.
+    pub fn formats() -> Vec<String> {
+        let pasteboard: Option<Retained<NSPasteboard>> =
+            unsafe { msg_send![NSPasteboard::class(), generalPasteboard] };
+        let Some(pasteboard) = pasteboard else {
+            return Vec::new();
+        };
+        let types: Option<Retained<NSArray<NSString>>> = unsafe { msg_send![&pasteboard, types] };
+        types
+            .map(|types| types.iter().map(|t| t.to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    /// The frontmost application. Window titles need accessibility access,
+    /// so they are not read.
+    pub fn source_app() -> Option<SourceApp> {
+        let workspace: Option<Retained<AnyObject>> =
+            unsafe { msg_send![class!(NSWorkspace), sharedWorkspace] };
+        let workspace = workspace?;
+        let app: Option<Retained<AnyObject>> =
+            unsafe { msg_send![&workspace, frontmostApplication] };
+        let app = app?;
+        let name: Option<Retained<NSString>> = unsafe { msg_send![&app, localizedName] };
+        Some(SourceApp {
+            name: name?.to_string(),
+            title: None,
+        })
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use crate::SourceApp;
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt, path::Path};
+    use windows_sys::Win32::{
+        Foundation::CloseHandle,
+        System::Threading::{
+            OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+            PROCESS_QUERY_LIMITED_INFORMATION,
+        },
+        UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId},
+    };
+
+    /// Windows cannot list formats without opening the clipboard, so only the
+    /// concealment formats are looked up.
+    pub fn formats() -> Vec<String> {
+        use clipboard_win::raw::{is_format_avail, register_format};
+
+        super::CONCEALED_FORMATS
+            .iter()
+            .filter(|name| {
+                register_format(**name).is_some_and(|format| is_format_avail(format.get()))
+            })
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    /// The executable name, without extension, and the title of the
+    /// foreground window.
+    pub fn source_app() -> Option<SourceApp> {
+        let mut buffer = [0u16; 1024];
+        unsafe {
+            let window = GetForegroundWindow();
+            if window.is_null() {
+                return None;
+            }
+            let len = GetWindowTextW(window, buffer.as_mut_ptr(), buffer.len() as i32);
+            let title = (len > 0).then(|| {
+                OsString::from_wide(&buffer[..len as usize])
+                    .to_string_lossy()
+                    .into_owned()
+            });
+
+            let mut pid = 0;
+            GetWindowThreadProcessId(window, &mut pid);
+            let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+            if process.is_null() {
+                return None;
+            }
+            let mut size = buffer.len() as u32;
+            let ok = QueryFullProcessImageNameW(
+                process,
+                PROCESS_NAME_WIN32,
+                buffer.as_mut_ptr(),
+                &mut size,
+            );
+            CloseHandle(process);
+            if ok == 0 {
+                return None;
+            }
+            let path = OsString::from_wide(&buffer[..size as usize]);
+            let name = Path::new(&path).file_stem()?.to_string_lossy().into_owned();
+            Some(SourceApp { name, title })
+        }
+    }
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "ios",
+    target_os = "emscripten",
+    not(any(unix, windows))
+))]
+mod platform {
+    use crate::SourceApp;
+
+    pub fn formats() -> Vec<String> {
+        Vec::new()
+    }
+
+    pub fn source_app() -> Option<SourceApp> {
+        None
+    }
+}
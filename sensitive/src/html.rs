@@ -0,0 +1,178 @@
+//! The text of an HTML clip.
+//!
+//! Rich-text copies often come without a `text/plain` version, yet they are
+//! scanned and searched by what a reader sees, not by their markup. This is a
+//! tolerant tag stripper rather than an HTML parser: it drops tags, scripts
+//! and styles, turns block elements into line breaks and decodes character
+//! references.
+
+use std::borrow::Cow;
+
+/// Elements that start a new line of text.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "br", "p", "div", "li", "tr", "h1", "h2", "h3", "h4", "h5", "h6",
+];
+
+/// Extracts the text of `html`, one line per block with whitespace collapsed.
+pub fn to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        push_text(&mut text, &rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim().to_ascii_lowercase();
+        rest = &rest[start + end + 1..];
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+        if matches!(name.as_str(), "script" | "style") && !tag.starts_with('/') {
+            let close = format!("</{name}");
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(at) => &rest[at..],
+                None => "",
+            };
+        } else if BLOCK_ELEMENTS.contains(&name.as_str())
+            && !text.is_empty()
+            && !text.ends_with('\n')
+        {
+            text.push('\n');
+        }
+    }
+    push_text(&mut text, rest);
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Appends the text between two tags. Line breaks in the source are
+/// whitespace like any other; only block elements start a new line.
+fn push_text(text: &mut String, source: &str) {
+    text.extend(
+        decode_entities(source)
+            .chars()
+            .map(|c| if c == '\n' || c == '\r' { ' ' } else { c }),
+    );
+}
+
+/// Decodes the common named references and numeric ones. Anything else,
+/// including references to invalid code points, is kept as written.
+fn decode_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let code = entity.strip_prefix('#')?;
+                let code = match code.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => code.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (entity, character) {
+            (Some(entity), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    Cow::Owned(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_tags_are_dropped() {
+        assert_eq!(
+            to_text(r#"<span class="a">one <b><i>two</i></b> <a href="x?y=1">three</a></span>"#),
+            "one two three"
+        );
+    }
+
+    #[test]
+    fn block_elements_break_lines() {
+        assert_eq!(
+            to_text("<p>first</p><p>second<br>third<BR/>fourth</p><ul><li>a</li><li>b</li></ul>"),
+            "first\nsecond\nthird\nfourth\na\nb"
+        );
+        // Source line breaks and runs of spaces inside a block are whitespace
+        assert_eq!(
+            to_text("<div>  one\n   two  </div>\n\n<div>three</div>"),
+            "one two\nthree"
+        );
+    }
+
+    #[test]
+    fn scripts_and_styles_are_dropped() {
+        assert_eq!(
+            to_text("<style>p { color: red }</style><p>shown</p><SCRIPT>alert('x')</script>"),
+            "shown"
+        );
+        assert_eq!(to_text("before<script>never closed"), "before");
+    }
+
+    #[test]
+    fn unterminated_tags_end_the_text() {
+        assert_eq!(to_text("text <b"), "text");
+    }
+
+    #[test]
+    fn named_and_numeric_references_are_decoded() {
+        assert_eq!(
+            to_text("<p>&lt;b&gt; &amp; &quot;q&quot; &apos;a&apos;&nbsp;x</p>"),
+            "<b> & \"q\" 'a' x"
+        );
+        assert_eq!(to_text("&#65;&#x42;&#X43; &#20013;&#x6587;"), "ABC 中文");
+    }
+
+    #[test]
+    fn invalid_references_are_kept() {
+        assert_eq!(decode_entities("&#xZZ;"), "&#xZZ;");
+        assert_eq!(decode_entities("&#12a;"), "&#12a;");
+        assert_eq!(
+            decode_entities("&#x110000; &#xD800;"),
+            "&#x110000; &#xD800;"
+        );
+        assert_eq!(decode_entities("&unknown; &amp"), "&unknown; &amp");
+        assert_eq!(decode_entities("fish & chips"), "fish & chips");
+        assert_eq!(
+            decode_entities("&averyveryverylongname;"),
+            "&averyveryverylongname;"
+        );
+    }
+
+    #[test]
+    fn plain_text_is_borrowed() {
+        assert!(matches!(decode_entities("no references"), Cow::Borrowed(_)));
+    }
+}
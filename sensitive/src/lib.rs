@@ -0,0 +1,461 @@
+//! Detection of sensitive clipboard content.
+//!
+//! Captured text runs through an ordered list of rules before it reaches the
+//! history. Each rule pairs a detector (API keys, JWTs, private keys, payment
+//! card numbers, high-entropy strings or a custom regex) with an action: skip
+//! the clip, mask the matched text, or keep the clip but let it expire. Text
+//! claimed by an earlier rule is not matched again by a later one, so the
+//! order of `rules` decides which action wins.
+//!
+//! Password managers additionally mark the secrets they copy with a clipboard
+//! format such as `x-kde-passwordManagerHint`; `concealed` picks the action
+//! for those clips. With the `host` feature, [`hints`] reads the markers from
+//! the host clipboard.
+//!
+//! Clips copied from the applications in `exclude_apps`, or while a window
+//! whose title contains one of `exclude_titles` is focused, are dropped before
+//! any of this.
+//!
+//! HTML clips are checked by their text, which [`html::to_text`] extracts.
+
+#[cfg(feature = "host")]
+pub mod hints;
+pub mod html;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{fmt, ops::Range, time::Duration};
+
+const API_KEY: &str = concat!(
+    r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+    r"|\bgh[pousr]_[A-Za-z0-9]{36,}\b",
+    r"|\bgithub_pat_[A-Za-z0-9_]{22,}\b",
+    r"|\bglpat-[A-Za-z0-9_-]{20,}",
+    r"|\bxox[abposr]-[A-Za-z0-9-]{10,}",
+    r"|\b[rs]k_(?:live|test)_[A-Za-z0-9]{16,}\b",
+    r"|\bAIza[0-9A-Za-z_-]{35}",
+    r"|\bsk-[A-Za-z0-9_-]{20,}",
+    // `password = ...` style assignments: only the value is masked
+    r#"|(?i:\b(?:\w*_)?(?:api[_-]?key|secret[_-]?key|client[_-]?secret|access[_-]?token|auth[_-]?token|password|passwd)\b)"#,
+    r#"["']?\s*[:=]\s*["']?(?P<secret>[^\s"']{8,})"#,
+);
+const JWT: &str = r"\beyJ[A-Za-z0-9_-]{8,}\.eyJ[A-Za-z0-9_-]{8,}\.[A-Za-z0-9_-]{8,}";
+const PRIVATE_KEY: &str = r"(?s)-----BEGIN [A-Z0-9 ]*PRIVATE KEY(?: BLOCK)?-----.*?(?:-----END [A-Z0-9 ]*PRIVATE KEY(?: BLOCK)?-----|\z)";
+const CARD_NUMBER: &str = r"\b\d(?:[ -]?\d){12,18}\b";
+
+/// Detection settings, read from the server's `[sensitive]` config section
+/// or the app's saved settings.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    /// Turns the whole pipeline off.
+    pub enabled: bool,
+    /// How long a clip matched by an `expire` rule stays in history.
+    pub expire_after_secs: u64,
+    /// Shortest run of token characters `high_entropy` looks at.
+    pub min_entropy_length: usize,
+    /// Shannon entropy, in bits per character, from which a token counts as random.
+    pub entropy_threshold: f64,
+    /// Applied to clips a password manager marked as concealed.
+    pub concealed: Action,
+    /// Applications whose clips are never recorded, compared case-insensitively
+    /// with the application name.
+    pub exclude_apps: Vec<String>,
+    /// Clips are not recorded while the focused window's title contains one
+    /// of these, e.g. a bank's name in a browser tab or `pass` in a terminal.
+    pub exclude_titles: Vec<String>,
+    /// Checked in order.
+    pub rules: Vec<Rule>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            expire_after_secs: 15 * 60,
+            min_entropy_length: 24,
+            entropy_threshold: 4.0,
+            concealed: Action::Skip,
+            exclude_apps: [
+                "1Password",
+                "Bitwarden",
+                "KeePassXC",
+                "KeePass",
+                "Enpass",
+                "Keychain Access",
+            ]
+            .map(String::from)
+            .to_vec(),
+            exclude_titles: Vec::new(),
+            rules: vec![
+                Rule::new(Detector::PrivateKey, Action::Skip),
+                Rule::new(Detector::Jwt, Action::Mask),
+                Rule::new(Detector::ApiKey, Action::Mask),
+                Rule::new(Detector::CreditCard, Action::Mask),
+                Rule::new(Detector::HighEntropy, Action::Expire),
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    #[serde(flatten)]
+    pub detector: Detector,
+    pub action: Action,
+}
+
+impl Rule {
+    pub fn new(detector: Detector, action: Action) -> Self {
+        Self { detector, action }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "detector", rename_all = "snake_case")]
+pub enum Detector {
+    /// Well-known token formats (AWS, GitHub, GitLab, Slack, Stripe, Google,
+    /// OpenAI-style `sk-` keys) and `password = ...` style assignments.
+    ApiKey,
+    Jwt,
+    /// PEM and PGP private key blocks.
+    PrivateKey,
+    /// Payment card numbers with a known issuer prefix and a valid Luhn checksum.
+    CreditCard,
+    /// Long tokens mixing upper case, lower case and digits with high entropy.
+    HighEntropy,
+    /// A custom regular expression. When it has a group named `secret`, only
+    /// that group is masked.
+    Pattern {
+        name: String,
+        pattern: String,
+    },
+}
+
+impl Detector {
+    pub fn name(&self) -> &str {
+        match self {
+            Detector::ApiKey => "api_key",
+            Detector::Jwt => "jwt",
+            Detector::PrivateKey => "private_key",
+            Detector::CreditCard => "credit_card",
+            Detector::HighEntropy => "high_entropy",
+            Detector::Pattern { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Do not record the clip at all.
+    Skip,
+    /// Record the clip with the matched text masked.
+    Mask,
+    /// Record the clip but remove it after `expire_after_secs`.
+    Expire,
+}
+
+/// The application a clip was copied from.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SourceApp {
+    pub name: String,
+    /// Title of the focused window, where the platform exposes it.
+    pub title: Option<String>,
+}
+
+/// What should happen to a scanned clip.
+#[derive(Debug, Default, PartialEq)]
+pub struct Verdict {
+    /// Names of the detectors that matched, in rule order.
+    pub detectors: Vec<String>,
+    pub skip: bool,
+    /// The content with every masked span replaced, when a `mask` rule matched.
+    pub masked: Option<String>,
+    pub expire_after: Option<Duration>,
+}
+
+impl Verdict {
+    pub fn is_clean(&self) -> bool {
+        self.detectors.is_empty()
+    }
+}
+
+/// A custom pattern that failed to compile.
+#[derive(Debug)]
+pub struct Error {
+    pub rule: String,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid pattern for rule {}: {}",
+            self.rule, self.message
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The compiled form of `Rules`.
+pub struct Scanner {
+    enabled: bool,
+    expire_after: Duration,
+    concealed: Action,
+    /// Lower-cased `exclude_apps` and `exclude_titles`.
+    exclude_apps: Vec<String>,
+    exclude_titles: Vec<String>,
+    rules: Vec<(Rule, Matcher)>,
+}
+
+enum Matcher {
+    Regex(Regex),
+    CreditCard(Regex),
+    HighEntropy { regex: Regex, threshold: f64 },
+}
+
+struct Span {
+    /// Text claimed by the rule.
+    whole: Range<usize>,
+    /// Text replaced when masking.
+    secret: Range<usize>,
+}
+
+impl Scanner {
+    pub fn new(rules: &Rules) -> Result<Self, Error> {
+        let builtin = |pattern: &str| Regex::new(pattern).expect("built-in pattern is valid");
+        let compiled = rules
+            .rules
+            .iter()
+            .map(|rule| {
+                let matcher = match &rule.detector {
+                    Detector::ApiKey => Matcher::Regex(builtin(API_KEY)),
+                    Detector::Jwt => Matcher::Regex(builtin(JWT)),
+                    Detector::PrivateKey => Matcher::Regex(builtin(PRIVATE_KEY)),
+                    Detector::CreditCard => Matcher::CreditCard(builtin(CARD_NUMBER)),
+                    Detector::HighEntropy => Matcher::HighEntropy {
+                        regex: builtin(&format!(
+                            "[A-Za-z0-9+/_-]{{{},}}={{0,2}}",
+                            rules.min_entropy_length.max(1)
+                        )),
+                        threshold: rules.entropy_threshold,
+                    },
+                    Detector::Pattern { name, pattern } => {
+                        Matcher::Regex(Regex::new(pattern).map_err(|e| Error {
+                            rule: name.clone(),
+                            message: e.to_string(),
+                        })?)
+                    }
+                };
+                Ok((rule.clone(), matcher))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self {
+            enabled: rules.enabled,
+            expire_after: Duration::from_secs(rules.expire_after_secs),
+            concealed: rules.concealed,
+            exclude_apps: lowercase(&rules.exclude_apps),
+            exclude_titles: lowercase(&rules.exclude_titles),
+            rules: compiled,
+        })
+    }
+
+    /// Whether clips copied from `source` must be dropped.
+    pub fn excludes(&self, source: &SourceApp) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let name = source.name.to_lowercase();
+        let title = source.title.as_deref().unwrap_or_default().to_lowercase();
+        self.exclude_apps.contains(&name)
+            || self
+                .exclude_titles
+                .iter()
+                .any(|fragment| title.contains(fragment.as_str()))
+    }
+
+    /// The verdict for a clip carrying a concealment hint. `text` is `None`
+    /// for content that cannot be masked, which is then skipped instead.
+    pub fn conceal(&self, text: Option<&str>) -> Verdict {
+        let mut verdict = Verdict::default();
+        if !self.enabled {
+            return verdict;
+        }
+        verdict.detectors.push("concealed".to_string());
+        match (self.concealed, text) {
+            (Action::Expire, _) => verdict.expire_after = Some(self.expire_after),
+            // Even a prefix of a password is too much to keep
+            (Action::Mask, Some(_)) => verdict.masked = Some("••••••••".to_string()),
+            _ => verdict.skip = true,
+        }
+        verdict
+    }
+
+    pub fn scan(&self, text: &str) -> Verdict {
+        let mut verdict = Verdict::default();
+        if !self.enabled {
+            return verdict;
+        }
+        let mut claimed: Vec<Range<usize>> = Vec::new();
+        let mut masks: Vec<(Range<usize>, String)> = Vec::new();
+        for (rule, matcher) in &self.rules {
+            let spans: Vec<Span> = matcher
+                .find(text)
+                .into_iter()
+                .filter(|span| {
+                    !claimed
+                        .iter()
+                        .any(|c| c.start < span.whole.end && span.whole.start < c.end)
+                })
+                .collect();
+            if spans.is_empty() {
+                continue;
+            }
+            verdict.detectors.push(rule.detector.name().to_string());
+            match rule.action {
+                Action::Skip => verdict.skip = true,
+                Action::Expire => verdict.expire_after = Some(self.expire_after),
+                Action::Mask => masks.extend(
+                    spans
+                        .iter()
+                        .map(|span| (span.secret.clone(), matcher.mask(text, span))),
+                ),
+            }
+            claimed.extend(spans.into_iter().map(|span| span.whole));
+        }
+
+        if !masks.is_empty() {
+            masks.sort_by_key(|(range, _)| range.start);
+            let mut masked = String::with_capacity(text.len());
+            let mut end = 0;
+            for (range, replacement) in masks {
+                masked.push_str(&text[end..range.start]);
+                masked.push_str(&replacement);
+                end = range.end;
+            }
+            masked.push_str(&text[end..]);
+            verdict.masked = Some(masked);
+        }
+        verdict
+    }
+}
+
+fn lowercase(values: &[String]) -> Vec<String> {
+    values
+        .iter()
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_lowercase())
+        .collect()
+}
+
+impl Matcher {
+    fn find(&self, text: &str) -> Vec<Span> {
+        match self {
+            Matcher::Regex(regex) => regex
+                .captures_iter(text)
+                .filter_map(|caps| {
+                    let whole = caps.get(0)?.range();
+                    let secret = caps.name("secret").map_or(whole.clone(), |m| m.range());
+                    Some(Span { whole, secret })
+                })
+                .collect(),
+            Matcher::CreditCard(regex) => regex
+                .find_iter(text)
+                .filter(|m| is_card_number(m.as_str()))
+                .map(|m| Span {
+                    whole: m.range(),
+                    secret: m.range(),
+                })
+                .collect(),
+            Matcher::HighEntropy { regex, threshold } => regex
+                .find_iter(text)
+                .filter(|m| looks_random(m.as_str(), *threshold))
+                .map(|m| Span {
+                    whole: m.range(),
+                    secret: m.range(),
+                })
+                .collect(),
+        }
+    }
+
+    fn mask(&self, text: &str, span: &Span) -> String {
+        let secret = &text[span.secret.clone()];
+        // A `secret` group is the value of an assignment such as
+        // `password = ...`, which may well be a password
+        let assignment = span.secret != span.whole;
+        match self {
+            // Keep the last four digits, as receipts do
+            Matcher::CreditCard(_) => {
+                let digits: Vec<char> = secret.chars().filter(char::is_ascii_digit).collect();
+                let last: String = digits[digits.len().saturating_sub(4)..].iter().collect();
+                format!("•••• {last}")
+            }
+            // Keep a short prefix of long tokens so they stay recognisable;
+            // like a concealed clip, a password keeps nothing
+            _ if !assignment && secret.chars().count() >= 16 && !secret.starts_with("-----") => {
+                let prefix: String = secret.chars().take(4).collect();
+                format!("{prefix}••••••••")
+            }
+            _ => "••••••••".to_string(),
+        }
+    }
+}
+
+fn is_card_number(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) || digits.iter().all(|&d| d == digits[0]) {
+        return false;
+    }
+    let prefix = |len: usize| digits[..len].iter().fold(0, |n, d| n * 10 + d);
+    let known_issuer = digits[0] == 4
+        || (51..=55).contains(&prefix(2))
+        || (2221..=2720).contains(&prefix(4))
+        || matches!(prefix(2), 34 | 36 | 37 | 38 | 62 | 65)
+        || (300..=305).contains(&prefix(3))
+        || (644..=649).contains(&prefix(3))
+        || prefix(4) == 6011
+        || (3528..=3589).contains(&prefix(4));
+    known_issuer && luhn(&digits)
+}
+
+fn luhn(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match i % 2 {
+            0 => d,
+            _ if d > 4 => d * 2 - 9,
+            _ => d * 2,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Random tokens mix character classes; paths, words and hex digests do not
+/// (hex never exceeds four bits per character).
+fn looks_random(token: &str, threshold: f64) -> bool {
+    token.chars().any(|c| c.is_ascii_uppercase())
+        && token.chars().any(|c| c.is_ascii_lowercase())
+        && token.chars().any(|c| c.is_ascii_digit())
+        && entropy(token) >= threshold
+}
+
+fn entropy(token: &str) -> f64 {
+    let mut counts = [0usize; 128];
+    for byte in token.bytes() {
+        counts[usize::from(byte & 0x7f)] += 1;
+    }
+    let len = token.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
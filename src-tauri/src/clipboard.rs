@@ -0,0 +1,192 @@
+use crate::history::{self, Captured, Clip, History};
+use clipboard_rs::{Clipboard as _, ClipboardContent, ClipboardContext, ContentFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use synapse_sensitive::hints;
+use tauri::{AppHandle, Listener, Manager};
+use tauri_plugin_clipboard::Clipboard;
+use url::Url;
+
+/// 文件条目（item_type 为 file）的内容是 `LocalFile` 的 JSON 数组，记录的是本机路径，
+/// 文件本身不复制；写回剪贴板时文件需要还在原处
+#[derive(Serialize, Deserialize)]
+pub struct LocalFile {
+    pub path: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub folder: bool,
+}
+
+/// 启动剪贴板监控。每次变化都在阻塞线程池中读取内容和元数据，然后交给历史记录；
+/// 读取 X11 的 TARGETS 需要等待剪贴板所有者应答，所以不能放在事件回调中
+pub fn watch(app: &AppHandle) {
+    if let Err(e) = app.state::<Clipboard>().start_monitor(app.clone()) {
+        log::error!("Failed to start clipboard monitor: {e}");
+    }
+    let handle = app.clone();
+    app.listen("plugin:clipboard://clipboard-monitor/update", move |_| {
+        let app = handle.clone();
+        tauri::async_runtime::spawn_blocking(move || {
+            if let Some(captured) = read(&app) {
+                history::record(&app, captured);
+            }
+        });
+    });
+}
+
+/// 读取剪贴板：优先读取复制的文件，其次是图片，最后是文本及其 HTML、RTF 格式。
+/// 来自排除列表中应用（密码管理器、网银页面等）的内容直接丢弃，不读取内容
+fn read(app: &AppHandle) -> Option<Captured> {
+    let history = app.state::<History>();
+    if history.paused() {
+        return None;
+    }
+    let source = hints::source_app();
+    if let Some(source) = source.as_ref().filter(|source| history.excludes(source)) {
+        log::info!("Skipped clip from excluded application {}", source.name);
+        return None;
+    }
+    // 密码管理器复制的内容带有隐藏标记，交给敏感内容规则处理
+    let concealed = hints::concealed();
+    if let Some(hint) = &concealed {
+        log::info!("Clipboard entry marked concealed by {hint}");
+    }
+
+    let clipboard = app.state::<Clipboard>();
+    let (item_type, content, formats, size) = match read_files() {
+        Some(files) => {
+            let size = files.len();
+            ("file", files, BTreeMap::new(), size)
+        }
+        None => match clipboard.read_image_base64() {
+            Ok(image) if !image.trim().is_empty() => {
+                let size = image.len() * 3 / 4;
+                ("image", image, BTreeMap::new(), size)
+            }
+            _ => {
+                let text = clipboard
+                    .read_text()
+                    .ok()
+                    .filter(|text| !text.trim().is_empty());
+                let (item_type, content, formats) = read_rich(text)?;
+                let size = content.len() + formats.values().map(String::len).sum::<usize>();
+                (item_type, content, formats, size)
+            }
+        },
+    };
+    Some(Captured {
+        item_type,
+        content,
+        formats,
+        size,
+        source_app: source.map(|source| source.name),
+        concealed: concealed.is_some(),
+    })
+}
+
+/// 读取文件管理器中复制的文件和文件夹。X11 上是 file:// URI，其他平台是路径；
+/// 已经不存在的路径跳过
+fn read_files() -> Option<String> {
+    let context = ClipboardContext::new().ok()?;
+    if !context.has(ContentFormat::Files) {
+        return None;
+    }
+    let files: Vec<LocalFile> = context
+        .get_files()
+        .ok()?
+        .iter()
+        .filter_map(|entry| {
+            let path = match entry.strip_prefix("file://") {
+                Some(_) => Url::parse(entry).ok()?.to_file_path().ok()?,
+                None => PathBuf::from(entry),
+            };
+            let meta = fs::metadata(&path).ok()?;
+            Some(LocalFile {
+                path: path.to_string_lossy().into_owned(),
+                size: if meta.is_dir() { 0 } else { meta.len() },
+                folder: meta.is_dir(),
+            })
+        })
+        .collect();
+    if files.is_empty() {
+        return None;
+    }
+    serde_json::to_string(&files).ok()
+}
+
+/// 同一次复制的文本格式合成一条记录：有 HTML 时以 HTML 为主，纯文本和 RTF 作为其他格式；
+/// 否则以纯文本为主，RTF 作为其他格式。只有 RTF 的内容无法显示，不记录
+fn read_rich(text: Option<String>) -> Option<(&'static str, String, BTreeMap<String, String>)> {
+    let context = ClipboardContext::new()
+        .map_err(|e| log::warn!("Failed to open clipboard for rich text: {e}"))
+        .ok();
+    let html = context
+        .as_ref()
+        .filter(|context| context.has(ContentFormat::Html))
+        .and_then(|context| context.get_html().ok())
+        .filter(|html| !html.trim().is_empty());
+    let rtf = context
+        .as_ref()
+        .filter(|context| context.has(ContentFormat::Rtf))
+        .and_then(|context| context.get_rich_text().ok())
+        .filter(|rtf| !rtf.trim().is_empty());
+
+    let mut formats = BTreeMap::new();
+    if let Some(rtf) = rtf {
+        formats.insert("text/rtf".to_string(), rtf);
+    }
+    match (html, text) {
+        (Some(html), text) => {
+            if let Some(text) = text {
+                formats.insert("text/plain".to_string(), text);
+            }
+            Some(("html", html, formats))
+        }
+        (None, Some(text)) => Some(("text", text, formats)),
+        (None, None) => None,
+    }
+}
+
+/// 把一条多格式的记录按原来的所有格式一起写回剪贴板，粘贴的应用自己选择需要的格式
+pub fn write_formats(clip: &Clip) -> Result<(), String> {
+    ClipboardContext::new()
+        .and_then(|context| context.set(contents(clip)))
+        .map_err(|e| e.to_string())
+}
+
+/// `write_formats` 写入的各个格式。
+/// 只复制了 HTML 时也带上从中提取的纯文本，粘贴到纯文本编辑器里不会是标签
+pub fn contents(clip: &Clip) -> Vec<ClipboardContent> {
+    let mut contents = vec![ClipboardContent::Text(clip.plain_text().into_owned())];
+    if clip.item_type == "html" {
+        contents.push(ClipboardContent::Html(clip.content.clone()));
+    }
+    if let Some(rtf) = clip.formats.get("text/rtf") {
+        contents.push(ClipboardContent::Rtf(rtf.clone()));
+    }
+    contents
+}
+
+/// 把文件条目中的路径写回剪贴板，在文件管理器中粘贴即可复制这些文件
+pub fn write_files(clip: &Clip) -> Result<(), String> {
+    let files: Vec<LocalFile> = serde_json::from_str(&clip.content).map_err(|e| e.to_string())?;
+    let files: Vec<String> = files
+        .into_iter()
+        .filter(|file| fs::metadata(&file.path).is_ok())
+        .map(|file| {
+            if cfg!(target_os = "linux") {
+                Url::from_file_path(&file.path).map_or(file.path, String::from)
+            } else {
+                file.path
+            }
+        })
+        .collect();
+    if files.is_empty() {
+        return Err("The copied files no longer exist".to_string());
+    }
+    ClipboardContext::new()
+        .and_then(|context| context.set_files(files))
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,259 @@
+//! 端到端加密的密钥，只在 Rust 侧使用：界面拿不到私钥和用户密钥，只能看到指纹。
+//! 设备私钥保存在应用数据目录的 device-key 中，首次运行时生成；用户密钥由服务器按设备包装保存，
+//! 同步时解开。与 CLI 相同，密钥只分享给用户核对过指纹并批准的设备，
+//! 服务器自己注册的设备或替换过的公钥都拿不到密钥
+
+use crate::sync::{Client, Grant, Missing};
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+use synapse_crypto::{DeviceKey, UserKey};
+
+/// 读取设备私钥，首次运行时生成；文件只有当前用户可读
+pub fn load_device_key(dir: &Path) -> Result<DeviceKey, Box<dyn std::error::Error>> {
+    let path = dir.join("device-key");
+    if let Ok(secret) = fs::read_to_string(&path) {
+        return Ok(DeviceKey::from_base64(secret.trim())?);
+    }
+    let device = DeviceKey::generate();
+    fs::create_dir_all(dir)?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&path)?
+        .write_all(device.secret_base64().as_bytes())?;
+    log::info!("Generated device key at {:?}", path);
+    Ok(device)
+}
+
+/// 公钥的指纹，显示给用户与另一台设备核对
+pub fn fingerprint(device: &DeviceKey) -> String {
+    synapse_crypto::fingerprint(&device.public_base64()).expect("own public key is valid")
+}
+
+/// 与 CLI 相同，比较时忽略空白和大小写
+pub fn same_fingerprint(a: &str, b: &str) -> bool {
+    let normalize = |fingerprint: &str| {
+        fingerprint
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_uppercase()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// 本机能读取的用户密钥
+pub struct Keyring {
+    keys: BTreeMap<i64, UserKey>,
+    current: Option<i64>,
+    device_id: String,
+    device: DeviceKey,
+    /// 用户批准过的设备 id 及批准时核对的公钥
+    approved: BTreeMap<String, String>,
+    /// 缺少密钥、还没有被批准的设备
+    waiting: Vec<Missing>,
+}
+
+impl Keyring {
+    /// 取回服务器为本机包装的密钥，把缺少的版本分享给已批准的设备；
+    /// 有设备被撤销时轮换出新的版本。没有批准的设备只列在 `waiting` 中
+    pub fn sync(
+        client: &Client,
+        device_id: &str,
+        device: DeviceKey,
+        approved: BTreeMap<String, String>,
+    ) -> Result<Self, String> {
+        let status = client.key_status()?;
+        let mut keys = BTreeMap::new();
+        for wrapped in &status.keys {
+            let key = device
+                .unwrap(&wrapped.wrapped_key)
+                .map_err(|e| format!("key version {}: {e}", wrapped.version))?;
+            keys.insert(wrapped.version, key);
+        }
+        let mut keyring = Self {
+            keys,
+            current: status.current_version,
+            device_id: device_id.to_string(),
+            device,
+            approved,
+            waiting: Vec::new(),
+        };
+
+        let mut grants = Vec::new();
+        for missing in status.missing {
+            if !keyring.is_approved(&missing.device_id, &missing.public_key) {
+                keyring.waiting.push(missing);
+                continue;
+            }
+            grants.extend(keyring.grants(
+                &missing.device_id,
+                &missing.public_key,
+                &missing.versions,
+            )?);
+        }
+        if !grants.is_empty() {
+            client.add_grants(&grants)?;
+            log::info!("Shared {} key version(s) with other devices", grants.len());
+        }
+
+        if status.rotation_needed {
+            let version = keyring.rotate(client)?;
+            log::info!("A device was revoked; rotated to key version {version}");
+        }
+        Ok(keyring)
+    }
+
+    fn is_approved(&self, device_id: &str, public_key: &str) -> bool {
+        self.approved.get(device_id).map(String::as_str) == Some(public_key)
+    }
+
+    /// 本机持有的 `versions` 中的密钥，为另一台设备各包装一份
+    fn grants(
+        &self,
+        device_id: &str,
+        public_key: &str,
+        versions: &[i64],
+    ) -> Result<Vec<Grant>, String> {
+        let mut grants = Vec::new();
+        for version in versions {
+            if let Some(key) = self.keys.get(version) {
+                grants.push(Grant {
+                    version: *version,
+                    device_id: device_id.to_string(),
+                    wrapped_key: key.wrap_for(public_key).map_err(|e| e.to_string())?,
+                });
+            }
+        }
+        Ok(grants)
+    }
+
+    /// 缺少密钥、等待批准的设备
+    pub fn waiting(&self) -> &[Missing] {
+        &self.waiting
+    }
+
+    pub fn current_version(&self) -> Option<i64> {
+        self.current
+    }
+
+    /// 本机是否持有当前版本的密钥，没有时只能等待其他设备批准
+    pub fn has_current(&self) -> bool {
+        self.current
+            .is_some_and(|version| self.keys.contains_key(&version))
+    }
+
+    /// 用户核对指纹后批准另一台设备：分享它缺少的密钥，之后的新版本也包装给它。
+    /// 返回分享的版本数
+    pub fn approve(
+        &mut self,
+        client: &Client,
+        device_id: &str,
+        public_key: &str,
+    ) -> Result<usize, String> {
+        let versions: Vec<i64> = self
+            .waiting
+            .iter()
+            .filter(|missing| missing.device_id == device_id && missing.public_key == public_key)
+            .flat_map(|missing| missing.versions.iter().copied())
+            .collect();
+        let grants = self.grants(device_id, public_key, &versions)?;
+        if !grants.is_empty() {
+            client.add_grants(&grants)?;
+        }
+        self.waiting
+            .retain(|missing| missing.device_id != device_id);
+        self.approved
+            .insert(device_id.to_string(), public_key.to_string());
+        Ok(grants.len())
+    }
+
+    /// 生成下一个密钥版本，包装给本机和所有已批准、未撤销的设备
+    pub fn rotate(&mut self, client: &Client) -> Result<i64, String> {
+        let version = self.current.unwrap_or(0) + 1;
+        let key = UserKey::generate();
+        let wrap = |public_key: &str| key.wrap_for(public_key).map_err(|e| e.to_string());
+        let mut grants = vec![Grant {
+            version,
+            device_id: self.device_id.clone(),
+            wrapped_key: wrap(&self.device.public_base64())?,
+        }];
+        for device in client.devices()? {
+            let Some(public_key) = &device.public_key else {
+                continue;
+            };
+            if device.revoked_at.is_none() && self.is_approved(&device.id, public_key) {
+                grants.push(Grant {
+                    version,
+                    wrapped_key: wrap(public_key)?,
+                    device_id: device.id,
+                });
+            }
+        }
+        client.create_key_version(version, &grants)?;
+        self.keys.insert(version, key);
+        self.current = Some(version);
+        Ok(version)
+    }
+
+    /// 用当前版本加密，用户还没有密钥时先创建第一个版本。返回密文和版本
+    pub fn encrypt(&mut self, client: &Client, content: &str) -> Result<(String, i64), String> {
+        let version = match self.current {
+            Some(version) => version,
+            None => self.rotate(client)?,
+        };
+        let key = self.keys.get(&version).ok_or_else(|| {
+            format!("This device has not been given key version {version} yet; approve it on another device first")
+        })?;
+        Ok((key.encrypt(content.as_bytes()), version))
+    }
+
+    pub fn decrypt(&self, content: &str, version: i64) -> Result<String, String> {
+        let key = self
+            .keys
+            .get(&version)
+            .ok_or_else(|| format!("missing key version {version}"))?;
+        let plaintext = key.decrypt(content).map_err(|e| e.to_string())?;
+        String::from_utf8(plaintext).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_key_is_kept_across_runs() {
+        let dir = std::env::temp_dir().join(format!("synapse-device-key-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let first = load_device_key(&dir).unwrap();
+        let second = load_device_key(&dir).unwrap();
+        assert_eq!(first.public_base64(), second.public_base64());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.join("device-key"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fingerprints_ignore_spacing_and_case() {
+        let device = DeviceKey::generate();
+        let actual = fingerprint(&device);
+        assert!(same_fingerprint(&actual, &actual));
+        assert!(same_fingerprint(
+            &actual.replace(' ', "").to_lowercase(),
+            &actual
+        ));
+        assert!(!same_fingerprint(
+            &fingerprint(&DeviceKey::generate()),
+            &actual
+        ));
+    }
+}
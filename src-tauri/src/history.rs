@@ -0,0 +1,1156 @@
+//! 本地剪贴板历史，由 Rust 侧持有：窗口隐藏或重新加载都不影响记录，
+//! 并保存在应用数据目录的 history.db（SQLite）中，重启后置顶、收藏和过期时间都还在。
+//! clipboard 模块读到剪贴板的新内容后交给 `record`，这里做敏感内容检测、去重、
+//! 保留策略和自动过期，并通过 `history://event` 把变更推送给界面。
+//! 事件与服务端 `/api/events` 的格式相同，界面用同一套代码处理两种来源
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::{Condvar, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use synapse_sensitive::{html, Rules, Scanner, SourceApp};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_clipboard::Clipboard;
+
+pub const EVENT: &str = "history://event";
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+const MAX_COLLECTION_LEN: usize = 64;
+
+/// 一条历史记录，序列化后与前端的 ClipboardItem 相同
+#[derive(Clone, Serialize)]
+pub struct Clip {
+    pub id: i64,
+    #[serde(rename = "type")]
+    pub item_type: String,
+    pub content: String,
+    pub mime_type: String,
+    pub timestamp: i64,
+    pub device: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    pub username: String,
+    pub size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_app: Option<String>,
+    pub pinned: bool,
+    pub favorite: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    pub copy_count: u32,
+    /// 同一次复制中 `content` 以外的格式，键为 MIME 类型（text/plain、text/rtf），
+    /// 写回剪贴板时一起恢复
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub formats: BTreeMap<String, String>,
+    /// 与服务端相同的内容哈希，用于合并重复复制
+    #[serde(skip)]
+    hash: Option<String>,
+}
+
+impl Clip {
+    /// 纯文本表示，用于托盘菜单等只能显示纯文本的地方
+    pub fn plain_text(&self) -> Cow<'_, str> {
+        plain_text(&self.item_type, &self.content, &self.formats)
+    }
+}
+
+/// 剪贴板上读到的新内容
+pub struct Captured {
+    /// text、html、image 或 file
+    pub item_type: &'static str,
+    pub content: String,
+    /// 同一次复制的其他格式，见 `Clip::formats`
+    pub formats: BTreeMap<String, String>,
+    pub size: usize,
+    pub source_app: Option<String>,
+    /// 剪贴板上是否有密码管理器的隐藏标记
+    pub concealed: bool,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum HistoryEvent {
+    Created { clip: Clip },
+    Updated { clip: Clip },
+    Deleted { id: i64 },
+    Cleared,
+}
+
+/// 保留策略，字段与服务端 /api/retention 相同。
+/// 默认最多保留 1000 条，避免图片的 base64 无限占用内存
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    max_items: Option<usize>,
+    max_age_days: Option<u32>,
+    max_bytes: Option<u64>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_items: Some(1000),
+            max_age_days: None,
+            max_bytes: None,
+        }
+    }
+}
+
+impl Retention {
+    /// 从最新的条目往前累计，删除超出任一限制的普通条目（置顶、收藏和集合中的条目除外），
+    /// 返回删除的 id
+    fn apply(&self, clips: &mut Vec<Clip>, now: i64) -> Vec<i64> {
+        let cutoff = self
+            .max_age_days
+            .map(|days| now - days as i64 * MILLIS_PER_DAY);
+        let mut items = 0;
+        let mut bytes = 0;
+        let mut removed = Vec::new();
+        for clip in clips.iter().rev() {
+            if clip.pinned || clip.favorite || clip.collection.is_some() {
+                continue;
+            }
+            items += 1;
+            bytes += clip.content.len() as u64;
+            bytes += clip
+                .formats
+                .values()
+                .map(|text| text.len() as u64)
+                .sum::<u64>();
+            let keep = self.max_items.map_or(true, |max| items <= max)
+                && self.max_bytes.map_or(true, |max| bytes <= max)
+                && cutoff.map_or(true, |cutoff| clip.timestamp >= cutoff);
+            if !keep {
+                removed.push(clip.id);
+            }
+        }
+        clips.retain(|clip| !removed.contains(&clip.id));
+        removed
+    }
+}
+
+/// 界面 localStorage 中保存的本机设备身份
+#[derive(Deserialize)]
+pub struct Device {
+    id: String,
+    name: String,
+}
+
+/// 修改置顶、收藏或集合，字段与服务端 `PATCH /api/clips/{id}` 相同；集合名为空表示移出集合
+#[derive(Deserialize)]
+pub struct ClipUpdate {
+    pinned: Option<bool>,
+    favorite: Option<bool>,
+    collection: Option<String>,
+}
+
+pub struct History {
+    inner: Mutex<Inner>,
+    /// 唤醒过期计时器（见 `start_expiry`）：有条目设置了过期时间
+    expiry: Condvar,
+}
+
+struct Inner {
+    /// 最旧的在前
+    clips: Vec<Clip>,
+    next_id: i64,
+    device: Option<Device>,
+    scanner: Scanner,
+    retention: Retention,
+    paused: bool,
+    /// 刚从历史写回剪贴板的内容，随后的那次剪贴板变化不再记录
+    copied: Option<String>,
+    db: Db,
+}
+
+impl History {
+    /// 打开应用数据目录中的 history.db，读出上次保存的历史
+    pub fn open(app: &AppHandle) -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = app.path().app_data_dir()?;
+        fs::create_dir_all(&dir)?;
+        let db = Db::open(&dir.join("history.db"))?;
+        Ok(Self {
+            inner: Mutex::new(Inner::new(db)?),
+            expiry: Condvar::new(),
+        })
+    }
+
+    /// 最近的条目，置顶的在前，其余最新的在前
+    pub fn recent(&self, limit: usize) -> Vec<Clip> {
+        let inner = self.inner.lock().unwrap();
+        let mut clips: Vec<&Clip> = inner.clips.iter().rev().collect();
+        clips.sort_by_key(|clip| !clip.pinned);
+        clips.into_iter().take(limit).cloned().collect()
+    }
+
+    pub fn paused(&self) -> bool {
+        self.inner.lock().unwrap().paused
+    }
+
+    /// 来自这个应用的内容是否按排除列表丢弃
+    pub fn excludes(&self, source: &SourceApp) -> bool {
+        self.inner.lock().unwrap().scanner.excludes(source)
+    }
+}
+
+/// 记录剪贴板上的新内容；相同的内容合并到已有的条目，移到最新并累加复制次数
+pub fn record(app: &AppHandle, captured: Captured) {
+    let history = app.state::<History>();
+    let username = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "Unknown".to_string());
+    let (events, expiring) = history.inner.lock().unwrap().record(
+        captured,
+        tauri_plugin_os::hostname(),
+        username,
+        now_millis(),
+    );
+    // 登录了服务器时，本机复制的文本和 HTML 加密后上传
+    for event in &events {
+        if let HistoryEvent::Created { clip } | HistoryEvent::Updated { clip } = event {
+            crate::sync::push(app, clip);
+        }
+    }
+    publish(app, events);
+    if expiring {
+        history.expiry.notify_one();
+    }
+}
+
+impl Inner {
+    /// 读出数据库中保存的历史
+    fn new(db: Db) -> rusqlite::Result<Self> {
+        let clips = db.load()?;
+        let next_id = clips.iter().map(|clip| clip.id).max().unwrap_or(0) + 1;
+        Ok(Self {
+            clips,
+            next_id,
+            device: None,
+            scanner: Scanner::new(&Rules::default()).expect("default rules compile"),
+            retention: Retention::default(),
+            paused: false,
+            copied: None,
+            db,
+        })
+    }
+
+    /// `record` 的主体：返回要推送的事件，以及新条目是否设置了过期时间。
+    /// 界面还没有告知设备身份时以 `hostname` 作为设备名
+    fn record(
+        &mut self,
+        captured: Captured,
+        hostname: String,
+        username: String,
+        now: i64,
+    ) -> (Vec<HistoryEvent>, bool) {
+        if self.paused {
+            return (Vec::new(), false);
+        }
+
+        let Captured {
+            mut item_type,
+            mut content,
+            mut formats,
+            mut size,
+            source_app,
+            concealed,
+        } = captured;
+        let mut expires_at = None;
+        let is_text = matches!(item_type, "text" | "html");
+        // 富文本按纯文本检测；遮盖后只保留遮盖过的纯文本，其他格式中仍有原文
+        let text = plain_text(item_type, &content, &formats);
+        let verdict = if concealed {
+            Some(self.scanner.conceal(is_text.then_some(&*text)))
+        } else if is_text {
+            Some(self.scanner.scan(&text))
+        } else {
+            None
+        };
+        if let Some(verdict) = verdict {
+            if verdict.skip {
+                log::info!("Skipped clip matching {}", verdict.detectors.join(", "));
+                return (Vec::new(), false);
+            }
+            if let Some(masked) = verdict.masked {
+                size = masked.len();
+                content = masked;
+                item_type = "text";
+                formats.clear();
+            }
+            if let Some(after) = verdict.expire_after {
+                expires_at = Some(now + after.as_millis() as i64);
+            }
+        }
+
+        let hash = content_hash(item_type, &content);
+        if hash.is_some() && self.copied.take() == hash {
+            return (Vec::new(), false);
+        }
+        let (device, device_id) = match &self.device {
+            Some(device) => (device.name.clone(), Some(device.id.clone())),
+            None => (hostname, None),
+        };
+
+        let clip = Clip {
+            id: 0,
+            item_type: item_type.to_string(),
+            content,
+            mime_type: mime_type(item_type).to_string(),
+            timestamp: now,
+            device,
+            device_id,
+            username,
+            size,
+            expires_at,
+            source_app,
+            pinned: false,
+            favorite: false,
+            collection: None,
+            copy_count: 1,
+            formats,
+            hash,
+        };
+        let events = self.add(clip, now);
+        (events, expires_at.is_some())
+    }
+
+    /// `import` 的主体。已经有相同的内容、且不早于这次复制时不再合并：
+    /// 服务器修改置顶等属性时也会推送同一条目，事件流重新回放时也是这样
+    fn import(&mut self, incoming: Incoming, now: i64) -> Vec<HistoryEvent> {
+        if incoming.expires_at.is_some_and(|at| at <= now) {
+            return Vec::new();
+        }
+        let hash = content_hash(&incoming.item_type, &incoming.content);
+        if self.clips.iter().any(|old| {
+            old.item_type == incoming.item_type
+                && old.hash == hash
+                && old.timestamp >= incoming.timestamp
+        }) {
+            return Vec::new();
+        }
+        let clip = Clip {
+            id: 0,
+            mime_type: mime_type(&incoming.item_type).to_string(),
+            size: incoming.content.len(),
+            item_type: incoming.item_type,
+            content: incoming.content,
+            timestamp: incoming.timestamp,
+            device: incoming.device,
+            device_id: incoming.device_id,
+            username: incoming.username,
+            expires_at: incoming.expires_at,
+            source_app: incoming.source_app,
+            pinned: false,
+            favorite: false,
+            collection: None,
+            copy_count: 1,
+            formats: BTreeMap::new(),
+            hash,
+        };
+        self.add(clip, now)
+    }
+
+    /// 加入一条新复制的内容：相同的内容合并到已有的条目，换成这次复制的时间和来源，
+    /// 累加复制次数并保留置顶、收藏和集合；否则作为新条目。之后执行保留策略
+    fn add(&mut self, mut clip: Clip, now: i64) -> Vec<HistoryEvent> {
+        let mut events = Vec::new();
+        let existing = clip.hash.as_ref().and_then(|hash| {
+            self.clips
+                .iter()
+                .position(|old| old.item_type == clip.item_type && old.hash.as_ref() == Some(hash))
+        });
+        match existing {
+            Some(index) => {
+                let old = self.clips.remove(index);
+                clip.id = old.id;
+                clip.pinned = old.pinned;
+                clip.favorite = old.favorite;
+                clip.collection = old.collection;
+                clip.copy_count = old.copy_count + 1;
+                self.insert(clip.clone());
+                events.push(HistoryEvent::Updated { clip });
+            }
+            None => {
+                clip.id = self.next_id;
+                self.next_id += 1;
+                self.insert(clip.clone());
+                events.push(HistoryEvent::Created { clip });
+            }
+        }
+        let removed = self.retention.apply(&mut self.clips, now);
+        self.db.delete(&removed);
+        events.extend(removed.into_iter().map(|id| HistoryEvent::Deleted { id }));
+        events
+    }
+
+    /// 按复制时间放到历史中的位置（同步来的条目不一定是最新的），并写入数据库
+    fn insert(&mut self, clip: Clip) {
+        self.db.save(&clip);
+        let at = self
+            .clips
+            .partition_point(|old| old.timestamp <= clip.timestamp);
+        self.clips.insert(at, clip);
+    }
+}
+
+/// 从服务器同步来的条目，已由 sync 模块解密
+pub struct Incoming {
+    pub item_type: String,
+    pub content: String,
+    pub timestamp: i64,
+    pub device: String,
+    pub device_id: Option<String>,
+    pub username: String,
+    pub expires_at: Option<i64>,
+    pub source_app: Option<String>,
+}
+
+/// 把其他设备的条目并入本地历史；不会再上传回服务器
+pub fn import(app: &AppHandle, incoming: Incoming) {
+    let history = app.state::<History>();
+    let expiring = incoming.expires_at.is_some();
+    let events = history.inner.lock().unwrap().import(incoming, now_millis());
+    if expiring && !events.is_empty() {
+        history.expiry.notify_one();
+    }
+    publish(app, events);
+}
+
+fn mime_type(item_type: &str) -> &'static str {
+    match item_type {
+        "image" => "image/png",
+        "html" => "text/html",
+        "file" => "text/uri-list",
+        _ => "text/plain",
+    }
+}
+
+/// 唯一的过期计时器：等到最早的 `expires_at`，删除到期的条目；
+/// 有新的过期时间时被唤醒重新计算。启动时已经过期的条目立即删除
+pub fn start_expiry(app: AppHandle) {
+    thread::spawn(move || {
+        let history = app.state::<History>();
+        let mut inner = history.inner.lock().unwrap();
+        loop {
+            let now = now_millis();
+            let expired: Vec<i64> = inner
+                .clips
+                .iter()
+                .filter(|clip| clip.expires_at.is_some_and(|at| at <= now))
+                .map(|clip| clip.id)
+                .collect();
+            if !expired.is_empty() {
+                inner.clips.retain(|clip| !expired.contains(&clip.id));
+                inner.db.delete(&expired);
+                // 推送时会刷新托盘菜单，它也要读取历史
+                drop(inner);
+                publish(
+                    &app,
+                    expired
+                        .into_iter()
+                        .map(|id| HistoryEvent::Deleted { id })
+                        .collect(),
+                );
+                inner = history.inner.lock().unwrap();
+                continue;
+            }
+            let next = inner.clips.iter().filter_map(|clip| clip.expires_at).min();
+            inner = match next {
+                Some(at) => {
+                    let wait = Duration::from_millis((at - now) as u64);
+                    history.expiry.wait_timeout(inner, wait).unwrap().0
+                }
+                None => history.expiry.wait(inner).unwrap(),
+            };
+        }
+    });
+}
+
+/// 把条目写回剪贴板，这次变化不会再被记录
+pub fn copy(app: &AppHandle, id: i64) -> Result<(), String> {
+    let history = app.state::<History>();
+    let clip = {
+        let mut inner = history.inner.lock().unwrap();
+        let clip = inner
+            .clips
+            .iter()
+            .find(|clip| clip.id == id)
+            .cloned()
+            .ok_or_else(|| format!("Clip {id} not found"))?;
+        inner.copied = clip.hash.clone();
+        clip
+    };
+    let clipboard = app.state::<Clipboard>();
+    let result = if !clip.formats.is_empty() || clip.item_type == "html" {
+        crate::clipboard::write_formats(&clip)
+    } else if clip.item_type == "file" {
+        crate::clipboard::write_files(&clip)
+    } else if clip.item_type == "image" {
+        let data = match clip.content.split_once(";base64,") {
+            Some((_, data)) => data.to_string(),
+            None => clip.content,
+        };
+        clipboard.write_image_base64(data)
+    } else {
+        clipboard.write_text(clip.content)
+    };
+    if result.is_err() {
+        history.inner.lock().unwrap().copied = None;
+    }
+    result
+}
+
+/// 清空历史
+pub fn clear(app: &AppHandle) {
+    {
+        let history = app.state::<History>();
+        let mut inner = history.inner.lock().unwrap();
+        inner.clips.clear();
+        inner.db.clear();
+    }
+    publish(app, vec![HistoryEvent::Cleared]);
+}
+
+/// 暂停或恢复记录，并通知界面
+pub fn set_paused(app: &AppHandle, paused: bool) {
+    app.state::<History>().inner.lock().unwrap().paused = paused;
+    let _ = app.emit("capture://paused", paused);
+}
+
+/// 推送变更；托盘菜单中的最近条目随之更新
+fn publish(app: &AppHandle, events: Vec<HistoryEvent>) {
+    if events.is_empty() {
+        return;
+    }
+    for event in events {
+        let _ = app.emit(EVENT, event);
+    }
+    #[cfg(desktop)]
+    crate::tray::refresh(app);
+}
+
+/// 文本条目的纯文本：复制时一起带来的 text/plain，只有 HTML 时从 HTML 中提取
+pub fn plain_text<'a>(
+    item_type: &str,
+    content: &'a str,
+    formats: &'a BTreeMap<String, String>,
+) -> Cow<'a, str> {
+    match formats.get("text/plain") {
+        Some(text) => Cow::Borrowed(text),
+        None if item_type == "html" => Cow::Owned(html::to_text(content)),
+        None => Cow::Borrowed(content),
+    }
+}
+
+/// 与服务端相同的内容哈希：文本取 SHA-256，图片取 base64 解码后字节的 SHA-256
+fn content_hash(item_type: &str, content: &str) -> Option<String> {
+    let digest = match item_type {
+        "image" => {
+            let data = match content.split_once(";base64,") {
+                Some((_, data)) => data,
+                None => content,
+            };
+            Sha256::digest(STANDARD.decode(data.trim()).ok()?)
+        }
+        _ => Sha256::digest(content.as_bytes()),
+    };
+    Some(hex::encode(digest))
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// 表结构的各个版本，与服务端的 store 一样只能在末尾追加，
+/// 已执行到的版本记在 `PRAGMA user_version` 中
+const MIGRATIONS: &[&str] = &[
+    // v1: 条目表。加入版本号之前的数据库已经有这张表，且 user_version 为 0
+    "CREATE TABLE IF NOT EXISTS clips (
+        id          INTEGER PRIMARY KEY,
+        item_type   TEXT    NOT NULL,
+        content     TEXT    NOT NULL,
+        mime_type   TEXT    NOT NULL,
+        timestamp   INTEGER NOT NULL,
+        device      TEXT    NOT NULL,
+        device_id   TEXT,
+        username    TEXT    NOT NULL,
+        size        INTEGER NOT NULL,
+        expires_at  INTEGER,
+        source_app  TEXT,
+        pinned      INTEGER NOT NULL,
+        favorite    INTEGER NOT NULL,
+        collection  TEXT,
+        copy_count  INTEGER NOT NULL,
+        formats     TEXT    NOT NULL,
+        hash        TEXT
+    );",
+];
+
+/// 依次执行尚未执行的迁移，每个版本一个事务
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+        log::info!("Applied history schema migration v{}", index + 1);
+    }
+    Ok(())
+}
+
+/// 历史在磁盘上的副本；内存中的 `Inner::clips` 与它保持一致。
+/// 写入失败只记录日志，内存中的历史照常使用
+struct Db(Connection);
+
+impl Db {
+    fn open(path: &Path) -> rusqlite::Result<Self> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+        Ok(Self(conn))
+    }
+
+    /// 只在内存中的空历史，供测试使用
+    #[cfg(test)]
+    fn open_in_memory() -> rusqlite::Result<Self> {
+        let mut conn = Connection::open_in_memory()?;
+        migrate(&mut conn)?;
+        Ok(Self(conn))
+    }
+
+    /// 全部条目，与内存中的顺序相同：最旧的在前
+    fn load(&self) -> rusqlite::Result<Vec<Clip>> {
+        let mut stmt = self.0.prepare(
+            "SELECT id, item_type, content, mime_type, timestamp, device, device_id, username,
+                    size, expires_at, source_app, pinned, favorite, collection, copy_count,
+                    formats, hash
+             FROM clips ORDER BY timestamp, id",
+        )?;
+        let clips = stmt.query_map([], |row| {
+            let formats: String = row.get(15)?;
+            Ok(Clip {
+                id: row.get(0)?,
+                item_type: row.get(1)?,
+                content: row.get(2)?,
+                mime_type: row.get(3)?,
+                timestamp: row.get(4)?,
+                device: row.get(5)?,
+                device_id: row.get(6)?,
+                username: row.get(7)?,
+                size: row.get::<_, i64>(8)? as usize,
+                expires_at: row.get(9)?,
+                source_app: row.get(10)?,
+                pinned: row.get(11)?,
+                favorite: row.get(12)?,
+                collection: row.get(13)?,
+                copy_count: row.get(14)?,
+                formats: serde_json::from_str(&formats).unwrap_or_default(),
+                hash: row.get(16)?,
+            })
+        })?;
+        clips.collect()
+    }
+
+    /// 新增或整条覆盖
+    fn save(&self, clip: &Clip) {
+        let formats = serde_json::to_string(&clip.formats).unwrap_or_default();
+        let result = self.0.execute(
+            "INSERT OR REPLACE INTO clips (id, item_type, content, mime_type, timestamp, device,
+                 device_id, username, size, expires_at, source_app, pinned, favorite, collection,
+                 copy_count, formats, hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+            params![
+                clip.id,
+                clip.item_type,
+                clip.content,
+                clip.mime_type,
+                clip.timestamp,
+                clip.device,
+                clip.device_id,
+                clip.username,
+                clip.size as i64,
+                clip.expires_at,
+                clip.source_app,
+                clip.pinned,
+                clip.favorite,
+                clip.collection,
+                clip.copy_count,
+                formats,
+                clip.hash,
+            ],
+        );
+        if let Err(e) = result {
+            log::warn!("Failed to save clip {}: {e}", clip.id);
+        }
+    }
+
+    fn delete(&self, ids: &[i64]) {
+        for id in ids {
+            if let Err(e) = self.0.execute("DELETE FROM clips WHERE id = ?1", [id]) {
+                log::warn!("Failed to delete clip {id}: {e}");
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Err(e) = self.0.execute("DELETE FROM clips", []) {
+            log::warn!("Failed to clear history: {e}");
+        }
+    }
+}
+
+/// 完整的历史，最旧的在前
+#[tauri::command]
+pub fn history_list(history: State<History>) -> Vec<Clip> {
+    history.inner.lock().unwrap().clips.clone()
+}
+
+#[tauri::command]
+pub fn history_copy(app: AppHandle, id: i64) -> Result<(), String> {
+    copy(&app, id)
+}
+
+#[tauri::command]
+pub fn history_delete(app: AppHandle, id: i64) {
+    let removed = {
+        let history = app.state::<History>();
+        let mut inner = history.inner.lock().unwrap();
+        let before = inner.clips.len();
+        inner.clips.retain(|clip| clip.id != id);
+        inner.db.delete(&[id]);
+        inner.clips.len() != before
+    };
+    if removed {
+        publish(&app, vec![HistoryEvent::Deleted { id }]);
+    }
+}
+
+#[tauri::command]
+pub fn history_update(app: AppHandle, id: i64, update: ClipUpdate) -> Result<(), String> {
+    let collection = update.collection.map(|name| name.trim().to_string());
+    if collection
+        .as_ref()
+        .is_some_and(|name| name.chars().count() > MAX_COLLECTION_LEN)
+    {
+        return Err(format!(
+            "Collection names are at most {MAX_COLLECTION_LEN} characters"
+        ));
+    }
+    let clip = {
+        let history = app.state::<History>();
+        let mut inner = history.inner.lock().unwrap();
+        let clip = inner
+            .clips
+            .iter_mut()
+            .find(|clip| clip.id == id)
+            .ok_or_else(|| format!("Clip {id} not found"))?;
+        if let Some(pinned) = update.pinned {
+            clip.pinned = pinned;
+        }
+        if let Some(favorite) = update.favorite {
+            clip.favorite = favorite;
+        }
+        if let Some(collection) = collection {
+            clip.collection = (!collection.is_empty()).then_some(collection);
+        }
+        let clip = clip.clone();
+        inner.db.save(&clip);
+        clip
+    };
+    publish(&app, vec![HistoryEvent::Updated { clip }]);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn history_clear(app: AppHandle) {
+    clear(&app);
+}
+
+/// 界面启动时传入 localStorage 中的设备身份、敏感内容规则（synapse-sensitive-rules）
+/// 和保留策略（synapse-retention），规则和策略是 JSON，为空或无效时使用默认值
+#[tauri::command]
+pub fn configure_capture(
+    app: AppHandle,
+    device: Option<Device>,
+    rules: Option<String>,
+    retention: Option<String>,
+) {
+    let rules = rules
+        .and_then(|json| match serde_json::from_str::<Rules>(&json) {
+            Ok(rules) => Some(rules),
+            Err(e) => {
+                log::warn!("Invalid sensitive rules: {e}");
+                None
+            }
+        })
+        .unwrap_or_default();
+    let scanner = Scanner::new(&rules).unwrap_or_else(|e| {
+        log::warn!("{e}");
+        Scanner::new(&Rules::default()).expect("default rules compile")
+    });
+    let retention = retention
+        .and_then(|json| serde_json::from_str::<Retention>(&json).ok())
+        .unwrap_or_default();
+
+    let events = {
+        let history = app.state::<History>();
+        let mut guard = history.inner.lock().unwrap();
+        let inner = &mut *guard;
+        inner.device = device;
+        inner.scanner = scanner;
+        inner.retention = retention;
+        let removed = inner.retention.apply(&mut inner.clips, now_millis());
+        inner.db.delete(&removed);
+        removed
+            .into_iter()
+            .map(|id| HistoryEvent::Deleted { id })
+            .collect()
+    };
+    publish(&app, events);
+}
+
+/// 界面重新加载时读取当前是否暂停
+#[tauri::command]
+pub fn capture_paused(history: State<History>) -> bool {
+    history.paused()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(id: i64, content: &str) -> Clip {
+        Clip {
+            id,
+            item_type: "text".to_string(),
+            content: content.to_string(),
+            mime_type: "text/plain".to_string(),
+            timestamp: id,
+            device: "laptop".to_string(),
+            device_id: None,
+            username: "alice".to_string(),
+            size: content.len(),
+            expires_at: None,
+            source_app: None,
+            pinned: false,
+            favorite: false,
+            collection: None,
+            copy_count: 1,
+            formats: BTreeMap::new(),
+            hash: None,
+        }
+    }
+
+    fn ids(clips: &[Clip]) -> Vec<i64> {
+        clips.iter().map(|clip| clip.id).collect()
+    }
+
+    fn captured(item_type: &'static str, content: &str) -> Captured {
+        Captured {
+            item_type,
+            content: content.to_string(),
+            formats: BTreeMap::new(),
+            size: content.len(),
+            source_app: None,
+            concealed: false,
+        }
+    }
+
+    fn history() -> Inner {
+        Inner::new(Db::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn record(inner: &mut Inner, captured: Captured, now: i64) -> Vec<HistoryEvent> {
+        inner
+            .record(captured, "laptop".to_string(), "alice".to_string(), now)
+            .0
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_items() {
+        let retention = Retention {
+            max_items: Some(2),
+            max_age_days: None,
+            max_bytes: None,
+        };
+        let mut clips = vec![clip(1, "a"), clip(2, "b"), clip(3, "c")];
+        assert_eq!(retention.apply(&mut clips, 0), [1]);
+        assert_eq!(ids(&clips), [2, 3]);
+    }
+
+    #[test]
+    fn retention_counts_bytes_of_every_format() {
+        let retention = Retention {
+            max_items: None,
+            max_age_days: None,
+            max_bytes: Some(10),
+        };
+        let mut newest = clip(3, "1234");
+        newest
+            .formats
+            .insert("text/plain".to_string(), "1234".to_string());
+        let mut clips = vec![clip(1, "12"), clip(2, "12"), newest];
+        // 最新的一条连同它的 text/plain 共 8 字节，再往前只放得下一条
+        assert_eq!(retention.apply(&mut clips, 0), [1]);
+        assert_eq!(ids(&clips), [2, 3]);
+    }
+
+    #[test]
+    fn retention_spares_pinned_favorite_and_collected_items() {
+        let retention = Retention {
+            max_items: Some(1),
+            max_age_days: None,
+            max_bytes: Some(1),
+        };
+        let mut pinned = clip(1, "pinned");
+        pinned.pinned = true;
+        let mut favorite = clip(2, "favorite");
+        favorite.favorite = true;
+        let mut collected = clip(3, "collected");
+        collected.collection = Some("work".to_string());
+        let mut clips = vec![pinned, favorite, collected, clip(4, "a"), clip(5, "b")];
+        assert_eq!(retention.apply(&mut clips, 0), [4]);
+        assert_eq!(ids(&clips), [1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn retention_drops_items_past_their_age() {
+        let retention = Retention {
+            max_items: None,
+            max_age_days: Some(1),
+            max_bytes: None,
+        };
+        let mut clips = vec![clip(1, "old"), clip(MILLIS_PER_DAY + 1, "new")];
+        assert_eq!(retention.apply(&mut clips, 2 * MILLIS_PER_DAY), [1]);
+        assert_eq!(ids(&clips), [MILLIS_PER_DAY + 1]);
+    }
+
+    #[test]
+    fn repeated_copies_are_merged() {
+        let mut inner = history();
+        record(&mut inner, captured("text", "hello"), 1);
+        record(&mut inner, captured("text", "world"), 2);
+        let events = record(&mut inner, captured("text", "hello"), 3);
+
+        assert!(matches!(&events[..], [HistoryEvent::Updated { clip }] if clip.id == 1));
+        let clips: Vec<_> = inner
+            .clips
+            .iter()
+            .map(|clip| {
+                (
+                    clip.id,
+                    clip.content.as_str(),
+                    clip.timestamp,
+                    clip.copy_count,
+                )
+            })
+            .collect();
+        assert_eq!(clips, [(2, "world", 2, 1), (1, "hello", 3, 2)]);
+        // 合并后的顺序和复制次数也写进了数据库
+        let saved = inner.db.load().unwrap();
+        assert_eq!(ids(&saved), [2, 1]);
+        assert_eq!(saved[1].copy_count, 2);
+    }
+
+    fn incoming(content: &str, timestamp: i64) -> Incoming {
+        Incoming {
+            item_type: "text".to_string(),
+            content: content.to_string(),
+            timestamp,
+            device: "laptop".to_string(),
+            device_id: Some("remote".to_string()),
+            username: "alice".to_string(),
+            expires_at: None,
+            source_app: None,
+        }
+    }
+
+    #[test]
+    fn imported_clips_are_placed_by_their_copy_time() {
+        let mut inner = history();
+        record(&mut inner, captured("text", "first"), 10);
+        record(&mut inner, captured("text", "third"), 30);
+        let events = inner.import(incoming("second", 20), 40);
+
+        assert!(matches!(&events[..], [HistoryEvent::Created { clip }] if clip.id == 3));
+        assert_eq!(ids(&inner.clips), [1, 3, 2]);
+        assert_eq!(inner.clips[1].device_id.as_deref(), Some("remote"));
+        assert_eq!(ids(&inner.db.load().unwrap()), [1, 3, 2]);
+        // 已经过期的条目不再导入
+        let mut expired = incoming("gone", 35);
+        expired.expires_at = Some(40);
+        assert!(inner.import(expired, 40).is_empty());
+    }
+
+    #[test]
+    fn imported_clips_merge_only_newer_copies() {
+        let mut inner = history();
+        record(&mut inner, captured("text", "hello"), 10);
+        record(&mut inner, captured("text", "world"), 20);
+
+        // 事件流回放或服务器改了置顶时，同一次复制会再次收到
+        assert!(inner.import(incoming("hello", 10), 30).is_empty());
+        assert!(inner.import(incoming("hello", 5), 30).is_empty());
+        assert_eq!(inner.clips[0].copy_count, 1);
+
+        let events = inner.import(incoming("hello", 25), 30);
+        assert!(matches!(&events[..], [HistoryEvent::Updated { clip }] if clip.id == 1));
+        assert_eq!(ids(&inner.clips), [2, 1]);
+        assert_eq!(inner.clips[1].copy_count, 2);
+        assert_eq!(inner.clips[1].device, "laptop");
+    }
+
+    #[test]
+    fn equal_content_of_different_types_is_not_merged() {
+        let mut inner = history();
+        record(&mut inner, captured("text", "<b>hi</b>"), 1);
+        record(&mut inner, captured("html", "<b>hi</b>"), 2);
+        assert_eq!(ids(&inner.clips), [1, 2]);
+    }
+
+    #[test]
+    fn copying_back_is_not_recorded_again() {
+        let mut inner = history();
+        record(&mut inner, captured("text", "hello"), 1);
+        inner.copied = inner.clips[0].hash.clone();
+        assert!(record(&mut inner, captured("text", "hello"), 2).is_empty());
+        assert_eq!(inner.clips[0].copy_count, 1);
+        // 只跳过紧接着的那一次
+        record(&mut inner, captured("text", "hello"), 3);
+        assert_eq!(inner.clips[0].copy_count, 2);
+    }
+
+    #[test]
+    fn record_applies_retention() {
+        let mut inner = history();
+        inner.retention.max_items = Some(1);
+        record(&mut inner, captured("text", "old"), 1);
+        let events = record(&mut inner, captured("text", "new"), 2);
+        assert!(matches!(
+            &events[..],
+            [
+                HistoryEvent::Created { .. },
+                HistoryEvent::Deleted { id: 1 }
+            ]
+        ));
+        assert_eq!(ids(&inner.db.load().unwrap()), [2]);
+    }
+
+    #[test]
+    fn secrets_in_html_are_masked_as_text() {
+        let mut inner = history();
+        let mut html = captured(
+            "html",
+            r#"<p class="note">key: <b>sk-abcdefghijklmnopqrstuvwx</b></p>"#,
+        );
+        html.formats.insert(
+            "text/rtf".to_string(),
+            r"{\rtf1 sk-abcdefghijklmnopqrstuvwx}".to_string(),
+        );
+        record(&mut inner, html, 1);
+
+        // 遮盖的是提取出的文字；原文还在标签和其他格式中，所以只留下纯文本
+        let clip = &inner.clips[0];
+        assert_eq!(clip.item_type, "text");
+        assert_eq!(clip.mime_type, "text/plain");
+        assert!(clip.content.starts_with("key: sk-a"), "{}", clip.content);
+        assert!(!clip.content.contains("sk-abcdefghijklmnopqrstuvwx"));
+        assert!(!clip.content.contains('<'));
+        assert!(clip.formats.is_empty());
+        assert_eq!(clip.size, clip.content.len());
+    }
+
+    #[test]
+    fn markup_is_not_masked() {
+        let mut inner = history();
+        let markup = r#"<span data-key="sk-abcdefghijklmnopqrstuvwx">hello</span>"#;
+        record(&mut inner, captured("html", markup), 1);
+
+        // 只有属性中像密钥，看得见的文字没有，条目原样保留
+        let clip = &inner.clips[0];
+        assert_eq!(clip.item_type, "html");
+        assert_eq!(clip.content, markup);
+        assert_eq!(clip.plain_text(), "hello");
+    }
+
+    #[test]
+    fn concealed_html_is_masked_as_text() {
+        let mut inner = history();
+        let rules = Rules {
+            concealed: synapse_sensitive::Action::Mask,
+            ..Rules::default()
+        };
+        inner.scanner = Scanner::new(&rules).unwrap();
+        let mut html = captured("html", "<b>hunter2</b>");
+        html.concealed = true;
+        record(&mut inner, html, 1);
+
+        let clip = &inner.clips[0];
+        assert_eq!(clip.item_type, "text");
+        assert_eq!(clip.content, "••••••••");
+    }
+
+    #[test]
+    fn html_only_clips_are_written_back_as_html() {
+        use clipboard_rs::ClipboardContent;
+
+        let mut html = clip(1, "<p>one &amp; <b>two</b></p>");
+        html.item_type = "html".to_string();
+        match &crate::clipboard::contents(&html)[..] {
+            [ClipboardContent::Text(text), ClipboardContent::Html(markup)] => {
+                assert_eq!(text, "one & two");
+                assert_eq!(markup, &html.content);
+            }
+            _ => panic!("expected text and HTML"),
+        }
+
+        // 复制时带来的纯文本和 RTF 也一起写回
+        html.formats
+            .insert("text/plain".to_string(), "plain".to_string());
+        html.formats
+            .insert("text/rtf".to_string(), r"{\rtf1 two}".to_string());
+        match &crate::clipboard::contents(&html)[..] {
+            [ClipboardContent::Text(text), ClipboardContent::Html(_), ClipboardContent::Rtf(rtf)] =>
+            {
+                assert_eq!(text, "plain");
+                assert_eq!(rtf, r"{\rtf1 two}");
+            }
+            _ => panic!("expected text, HTML and RTF"),
+        }
+    }
+
+    #[test]
+    fn unversioned_databases_are_migrated() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute(
+            "INSERT INTO clips (id, item_type, content, mime_type, timestamp, device, username,
+                 size, pinned, favorite, copy_count, formats)
+             VALUES (7, 'text', 'kept', 'text/plain', 1, 'laptop', 'alice', 4, 0, 0, 1, '{}')",
+            [],
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+        let version: usize = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        let inner = Inner::new(Db(conn)).unwrap();
+        assert_eq!(ids(&inner.clips), [7]);
+        assert_eq!(inner.next_id, 8);
+    }
+}
@@ -1,8 +1,13 @@
-use tauri::{
-    menu::{Menu, MenuEvent, MenuItem},
-    tray::{TrayIcon, TrayIconBuilder, TrayIconEvent},
-    Listener, Manager, WindowEvent,
-};
+mod clipboard;
+mod crypto;
+mod history;
+#[cfg(desktop)]
+mod picker;
+mod sync;
+#[cfg(desktop)]
+mod tray;
+
+use tauri::{Manager, WindowEvent};
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -13,6 +18,25 @@ pub fn run() {
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
+        .invoke_handler(tauri::generate_handler![
+            history::history_list,
+            history::history_copy,
+            history::history_delete,
+            history::history_update,
+            history::history_clear,
+            history::configure_capture,
+            history::capture_paused,
+            sync::sync_status,
+            sync::sync_login,
+            sync::sync_approve,
+            sync::sync_logout,
+            #[cfg(desktop)]
+            picker::picker_recent,
+            #[cfg(desktop)]
+            picker::picker_close,
+            #[cfg(desktop)]
+            picker::set_picker_shortcut,
+        ])
         .setup(|app| {
             // 0. Enable DevTools for debugging (in debug mode, auto-open; in release, use Cmd+Shift+M / Ctrl+Shift+M)
             if let Some(window) = app.get_webview_window("main") {
@@ -22,63 +46,25 @@ pub fn run() {
                     let _ = window.open_devtools();
                 }
             }
-            
-            // 1. Create Tray Menu
-            let quit_i = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>).expect("failed to create quit item");
-            let show_i = MenuItem::with_id(app, "show", "Show", true, None::<&str>).expect("failed to create show item");
-            let menu = Menu::with_items(app, &[&show_i, &quit_i]).expect("failed to create menu");
 
-            // 2. Build Tray Icon
-            // macOS uses monochrome (white) template images for menu bar icons
-            // Other platforms (Windows/Linux) use colored icons
-            #[cfg(target_os = "macos")]
-            let tray_icon_bytes = include_bytes!("../icons/tray-icon-macos.png");
-            #[cfg(not(target_os = "macos"))]
-            let tray_icon_bytes = include_bytes!("../icons/tray-icon.png");
+            // 本地剪贴板历史，快速粘贴窗口和托盘菜单也从这里读取
+            app.manage(history::History::open(app.handle())?);
+            history::start_expiry(app.handle().clone());
+            // 登录服务器后与其他设备同步；设备密钥只在这里使用，界面拿不到
+            app.manage(sync::Remote::open(app.handle())?);
+            sync::start(app.handle().clone());
             
-            let tray_icon = tauri::image::Image::from_bytes(tray_icon_bytes)
-                .expect("failed to load tray icon");
+            // 1. 全局快捷键打开快速粘贴窗口
+            #[cfg(desktop)]
+            picker::init(app)?;
 
-            let _tray = TrayIconBuilder::new()
-                .icon(tray_icon)
-                .menu(&menu)
-                .on_menu_event(|app: &tauri::AppHandle, event: MenuEvent| match event.id.as_ref() {
-                    "quit" => {
-                        app.exit(0);
-                    }
-                    "show" => {
-                        if let Some(window) = app.get_webview_window("main") {
-                            let _ = window.show();
-                            let _ = window.set_focus();
-                            #[cfg(target_os = "macos")]
-                            let _ = app.set_activation_policy(tauri::ActivationPolicy::Regular);
-                        }
-                    }
-                    _ => {}
-                })
-                .on_tray_icon_event(|tray: &TrayIcon, event: TrayIconEvent| {
-                    if let TrayIconEvent::Click {
-                        button: tauri::tray::MouseButton::Left,
-                        ..
-                    } = event
-                    {
-                        let app = tray.app_handle();
-                        if let Some(window) = app.get_webview_window("main") {
-                            let _ = window.show();
-                            let _ = window.set_focus();
-                            #[cfg(target_os = "macos")]
-                            let _ = app.set_activation_policy(tauri::ActivationPolicy::Regular);
-                        }
-                    }
-                })
-                .build(app).expect("failed to build tray");
+            // 2. 托盘菜单：最近的条目、暂停记录、清空历史
+            #[cfg(desktop)]
+            tray::init(app)?;
 
             // 3. Setup Clipboard Monitor
-            // Note: Monitoring is currently best started from the frontend due to plugin API constraints in Rust for V2.
-            // However, we register the listener in Rust to demonstrate backend reaction.
-            app.listen("plugin:clipboard://text-changed", move |event: tauri::Event| {
-                log::info!("Clipboard text changed: {:?}", event.payload());
-            });
+            // Capture runs here rather than in the webview, so it keeps going while the window is hidden or reloading.
+            clipboard::watch(app.handle());
 
             Ok(())
         })
@@ -90,6 +76,13 @@ pub fn run() {
                 #[cfg(target_os = "macos")]
                 let _ = window.app_handle().set_activation_policy(tauri::ActivationPolicy::Accessory);
             }
+            // 快速粘贴窗口失去焦点时收起
+            #[cfg(desktop)]
+            if let WindowEvent::Focused(false) = event {
+                if window.label() == picker::LABEL {
+                    let _ = window.hide();
+                }
+            }
         })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
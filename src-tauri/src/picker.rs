@@ -0,0 +1,136 @@
+//! 快速粘贴窗口：全局快捷键在鼠标附近弹出一个小窗口，列出最近的剪贴板条目，
+//! 回车把选中的条目放回剪贴板（`history_copy`），并可以模拟一次粘贴
+
+use crate::history::{Clip, History};
+use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+use std::{error::Error, sync::Mutex, thread, time::Duration};
+use tauri::{
+    App, AppHandle, Emitter, Manager, PhysicalPosition, State, WebviewUrl, WebviewWindowBuilder,
+};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+
+pub const LABEL: &str = "picker";
+/// 未配置 localStorage 中的 synapse-picker-shortcut 时使用的快捷键
+const DEFAULT_SHORTCUT: &str = "CommandOrControl+Shift+V";
+const WIDTH: f64 = 420.0;
+const HEIGHT: f64 = 360.0;
+/// 弹窗最多列出的条目数
+const RECENT_LIMIT: usize = 50;
+/// 收起弹窗后等焦点回到之前的应用再模拟粘贴
+const PASTE_DELAY: Duration = Duration::from_millis(150);
+
+#[derive(Default)]
+pub struct Picker {
+    shortcut: Mutex<Option<Shortcut>>,
+}
+
+/// 注册全局快捷键插件和默认快捷键，并预先创建隐藏的弹窗，按下快捷键时可以立即显示
+pub fn init(app: &App) -> Result<(), Box<dyn Error>> {
+    app.handle().plugin(
+        tauri_plugin_global_shortcut::Builder::new()
+            .with_handler(|app, _shortcut, event| {
+                if event.state() == ShortcutState::Pressed {
+                    toggle(app);
+                }
+            })
+            .build(),
+    )?;
+    app.manage(Picker::default());
+    register(app.handle(), DEFAULT_SHORTCUT)?;
+
+    WebviewWindowBuilder::new(app, LABEL, WebviewUrl::App("index.html#picker".into()))
+        .title("Synapse")
+        .inner_size(WIDTH, HEIGHT)
+        .resizable(false)
+        .decorations(false)
+        .always_on_top(true)
+        .skip_taskbar(true)
+        .visible(false)
+        .build()?;
+    Ok(())
+}
+
+/// 换成新的快捷键，旧的随之失效
+fn register(app: &AppHandle, shortcut: &str) -> Result<(), Box<dyn Error>> {
+    let shortcut: Shortcut = shortcut.parse()?;
+    let picker = app.state::<Picker>();
+    let mut current = picker.shortcut.lock().unwrap();
+    if let Some(old) = current.take() {
+        app.global_shortcut().unregister(old)?;
+    }
+    app.global_shortcut().register(shortcut)?;
+    *current = Some(shortcut);
+    Ok(())
+}
+
+/// 显示或收起弹窗。显示时放在鼠标附近，并保证整个窗口留在鼠标所在的屏幕内
+fn toggle(app: &AppHandle) {
+    let Some(window) = app.get_webview_window(LABEL) else {
+        return;
+    };
+    if window.is_visible().unwrap_or(false) {
+        let _ = window.hide();
+        return;
+    }
+    if let Ok(cursor) = app.cursor_position() {
+        let mut x = cursor.x as i32;
+        let mut y = cursor.y as i32;
+        if let Ok(Some(monitor)) = window.monitor_from_point(cursor.x, cursor.y) {
+            let scale = monitor.scale_factor();
+            let origin = monitor.position();
+            let size = monitor.size();
+            let right = origin.x + size.width as i32 - (WIDTH * scale) as i32;
+            let bottom = origin.y + size.height as i32 - (HEIGHT * scale) as i32;
+            x = x.min(right).max(origin.x);
+            y = y.min(bottom).max(origin.y);
+        }
+        let _ = window.set_position(PhysicalPosition::new(x, y));
+    }
+    let _ = window.show();
+    let _ = window.set_focus();
+    // 让弹窗重新读取最近的条目并清空筛选
+    let _ = app.emit_to(LABEL, "picker://open", ());
+}
+
+/// 模拟 Ctrl+V（macOS 上为 Cmd+V）
+fn paste() -> Result<(), Box<dyn Error>> {
+    #[cfg(target_os = "macos")]
+    let modifier = Key::Meta;
+    #[cfg(not(target_os = "macos"))]
+    let modifier = Key::Control;
+    let mut enigo = Enigo::new(&Settings::default())?;
+    enigo.key(modifier, Direction::Press)?;
+    enigo.key(Key::Unicode('v'), Direction::Click)?;
+    enigo.key(modifier, Direction::Release)?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn picker_recent(history: State<History>) -> Vec<Clip> {
+    history.recent(RECENT_LIMIT)
+}
+
+/// 收起弹窗；`paste` 为 true 时随后在之前的应用中模拟一次粘贴
+#[tauri::command]
+pub fn picker_close(app: AppHandle, paste: bool) {
+    if let Some(window) = app.get_webview_window(LABEL) {
+        let _ = window.hide();
+    }
+    if paste {
+        thread::spawn(|| {
+            thread::sleep(PASTE_DELAY);
+            if let Err(e) = self::paste() {
+                log::warn!("Failed to simulate paste: {e}");
+            }
+        });
+    }
+}
+
+/// 使用 localStorage 中 synapse-picker-shortcut 配置的快捷键（如 `Ctrl+Alt+V`），为空时恢复默认
+#[tauri::command]
+pub fn set_picker_shortcut(app: AppHandle, shortcut: Option<String>) -> Result<(), String> {
+    let shortcut = shortcut
+        .filter(|shortcut| !shortcut.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_SHORTCUT.to_string());
+    register(&app, shortcut.trim()).map_err(|e| format!("Invalid shortcut {shortcut}: {e}"))
+}
@@ -0,0 +1,759 @@
+//! 与 Synapse 服务器同步（可选）。登录后本机作为一个设备注册到服务器，带上设备公钥：
+//! 本地记录的文本和 HTML 条目加密后上传，其他设备的条目从 `/api/events` 取回，
+//! 解密后并入本地历史。加解密和密钥分享在 crypto 模块中完成，界面只能看到指纹。
+//! 网络请求都在后台线程或阻塞线程池中执行，服务器不可用时本地历史照常工作
+
+use crate::crypto::{self, Keyring};
+use crate::history::{self, Clip, Incoming};
+use reqwest::blocking::{Client as HttpClient, Response};
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::{mpsc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+use synapse_crypto::DeviceKey;
+use tauri::{AppHandle, Manager};
+
+/// 连接失败后等待这么久再重试
+const RETRY: Duration = Duration::from_secs(30);
+
+/// 登录后保存在应用数据目录的 sync.json，其中有 token，只有当前用户可读
+#[derive(Clone, Serialize, Deserialize)]
+struct Link {
+    server: String,
+    username: String,
+    device_id: String,
+    device_name: String,
+    /// 绑定到本机设备的 API token
+    token: String,
+    /// 用户批准过的设备 id 及批准时核对的公钥，只有这些设备能拿到密钥
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    approved: BTreeMap<String, String>,
+    /// 已经处理过的最后一个服务器事件，重新连接时从这里继续
+    #[serde(default)]
+    cursor: i64,
+}
+
+pub struct Remote {
+    path: PathBuf,
+    device: DeviceKey,
+    state: Mutex<SyncState>,
+    /// 登录或退出时唤醒跟随事件流的线程
+    changed: Condvar,
+    /// 交给上传线程的本地新条目
+    outbox: mpsc::Sender<Clip>,
+    inbox: Mutex<Option<mpsc::Receiver<Clip>>>,
+}
+
+struct SyncState {
+    link: Option<Link>,
+    keyring: Option<Keyring>,
+    /// 每次登录或退出加一，旧的事件流据此结束
+    generation: u64,
+    error: Option<String>,
+}
+
+/// 界面显示的同步状态
+#[derive(Serialize)]
+pub struct SyncStatus {
+    /// 本机设备公钥的指纹，在另一台设备上批准本机时核对
+    fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key_version: Option<i64>,
+    /// 本机是否持有当前的密钥；用户已有密钥而本机没有时需要在另一台设备上批准本机
+    has_key: bool,
+    waiting: Vec<WaitingDevice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// 缺少密钥、等待用户批准的设备
+#[derive(Serialize)]
+pub struct WaitingDevice {
+    id: String,
+    name: String,
+    fingerprint: String,
+}
+
+impl Remote {
+    /// 读取设备私钥和上次的登录信息
+    pub fn open(app: &AppHandle) -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = app.path().app_data_dir()?;
+        let device = crypto::load_device_key(&dir)?;
+        let path = dir.join("sync.json");
+        let link = match fs::read_to_string(&path) {
+            Ok(text) => Some(serde_json::from_str(&text)?),
+            Err(_) => None,
+        };
+        let (outbox, inbox) = mpsc::channel();
+        Ok(Self {
+            path,
+            device,
+            state: Mutex::new(SyncState {
+                link,
+                keyring: None,
+                generation: 0,
+                error: None,
+            }),
+            changed: Condvar::new(),
+            outbox,
+            inbox: Mutex::new(Some(inbox)),
+        })
+    }
+
+    fn save(&self, link: Option<&Link>) -> Result<(), String> {
+        let Some(link) = link else {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+                _ => Ok(()),
+            };
+        };
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let json = serde_json::to_string_pretty(link).map_err(|e| e.to_string())?;
+        options
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(json.as_bytes()))
+            .map_err(|e| format!("Failed to save {:?}: {e}", self.path))
+    }
+
+    /// 换成新的登录信息（退出时为 None），旧的事件流随之结束
+    fn relink(&self, link: Option<Link>) -> Result<(), String> {
+        self.save(link.as_ref())?;
+        let mut state = self.state.lock().unwrap();
+        state.link = link;
+        state.keyring = None;
+        state.generation += 1;
+        state.error = None;
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// 取回密钥并分享给已批准的设备，保存在状态中供加解密使用
+    fn sync_keys(&self, client: &Client, link: &Link) -> Result<(), String> {
+        let device =
+            DeviceKey::from_base64(&self.device.secret_base64()).map_err(|e| e.to_string())?;
+        let keyring = Keyring::sync(client, &link.device_id, device, link.approved.clone())?;
+        let mut state = self.state.lock().unwrap();
+        if state.link.as_ref().map(|current| &current.device_id) == Some(&link.device_id) {
+            state.keyring = Some(keyring);
+        }
+        Ok(())
+    }
+
+    fn linked(&self) -> Option<(Link, u64)> {
+        let state = self.state.lock().unwrap();
+        state.link.clone().map(|link| (link, state.generation))
+    }
+
+    fn set_error(&self, error: Option<String>) {
+        if let Some(error) = &error {
+            log::warn!("Sync: {error}");
+        }
+        self.state.lock().unwrap().error = error;
+    }
+
+    /// 当前状态；`devices` 是服务器上的设备列表，用来显示等待批准的设备名
+    fn status(&self, devices: &[RemoteDevice]) -> SyncStatus {
+        let state = self.state.lock().unwrap();
+        let link = state.link.as_ref();
+        let keyring = state.keyring.as_ref();
+        let waiting = keyring
+            .map(|keyring| keyring.waiting())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|missing| {
+                Some(WaitingDevice {
+                    id: missing.device_id.clone(),
+                    name: devices
+                        .iter()
+                        .find(|device| device.id == missing.device_id)
+                        .map_or_else(|| missing.device_id.clone(), |device| device.name.clone()),
+                    fingerprint: synapse_crypto::fingerprint(&missing.public_key).ok()?,
+                })
+            })
+            .collect();
+        SyncStatus {
+            fingerprint: crypto::fingerprint(&self.device),
+            server: link.map(|link| link.server.clone()),
+            username: link.map(|link| link.username.clone()),
+            device_id: link.map(|link| link.device_id.clone()),
+            device_name: link.map(|link| link.device_name.clone()),
+            key_version: keyring.and_then(Keyring::current_version),
+            has_key: keyring.is_some_and(Keyring::has_current),
+            waiting,
+            error: state.error.clone(),
+        }
+    }
+}
+
+/// 启动后台线程：一个跟随服务器的事件流，一个上传本地的新条目
+pub fn start(app: AppHandle) {
+    let inbox = app.state::<Remote>().inbox.lock().unwrap().take();
+    let Some(inbox) = inbox else {
+        return;
+    };
+    let handle = app.clone();
+    thread::spawn(move || upload(&handle, inbox));
+    thread::spawn(move || {
+        let sync = app.state::<Remote>();
+        loop {
+            let Some((link, generation)) = sync.linked() else {
+                let state = sync.state.lock().unwrap();
+                drop(sync.changed.wait_while(state, |state| state.link.is_none()));
+                continue;
+            };
+            if let Err(e) = follow(&app, &link, generation) {
+                sync.set_error(Some(e));
+                let state = sync.state.lock().unwrap();
+                drop(
+                    sync.changed
+                        .wait_timeout_while(state, RETRY, |state| state.generation == generation),
+                );
+            }
+        }
+    });
+}
+
+/// 本地记录了新条目（或重复复制了已有的条目）；登录后文本和 HTML 条目会加密上传
+pub fn push(app: &AppHandle, clip: &Clip) {
+    if !matches!(clip.item_type.as_str(), "text" | "html") {
+        return;
+    }
+    let sync = app.state::<Remote>();
+    if sync.state.lock().unwrap().link.is_none() {
+        return;
+    }
+    let _ = sync.outbox.send(clip.clone());
+}
+
+fn upload(app: &AppHandle, inbox: mpsc::Receiver<Clip>) {
+    let sync = app.state::<Remote>();
+    for clip in inbox {
+        let Some((link, _)) = sync.linked() else {
+            continue;
+        };
+        match upload_clip(&sync, &link, &clip) {
+            Ok(()) => sync.set_error(None),
+            Err(e) => sync.set_error(Some(format!("Failed to upload clip {}: {e}", clip.id))),
+        }
+    }
+}
+
+fn upload_clip(sync: &Remote, link: &Link, clip: &Clip) -> Result<(), String> {
+    let client = Client::new(&link.server, Some(&link.token))?;
+    // 其他设备刚轮换过密钥时服务器返回 409，取回新密钥后重试一次
+    for attempt in 0..2 {
+        if attempt > 0 || sync.state.lock().unwrap().keyring.is_none() {
+            sync.sync_keys(&client, link)?;
+        }
+        let (content, key_version) = {
+            let mut state = sync.state.lock().unwrap();
+            let keyring = state.keyring.as_mut().ok_or("signed out")?;
+            keyring.encrypt(&client, &clip.content)?
+        };
+        let item = Upload {
+            item_type: &clip.item_type,
+            content,
+            mime_type: &clip.mime_type,
+            timestamp: clip.timestamp,
+            size: clip.content.len(),
+            key_version,
+            expires_at: clip.expires_at,
+            source_app: clip.source_app.as_deref(),
+        };
+        if client.create_clip(&item)? {
+            return Ok(());
+        }
+    }
+    Err("the key version keeps changing".to_string())
+}
+
+/// 取回密钥，然后跟随事件流把其他设备的条目并入本地历史，直到出错或重新登录
+fn follow(app: &AppHandle, link: &Link, generation: u64) -> Result<(), String> {
+    let sync = app.state::<Remote>();
+    let client = Client::new(&link.server, Some(&link.token))?;
+    sync.sync_keys(&client, link)?;
+    sync.set_error(None);
+
+    let response = client.events(link.cursor)?;
+    let mut data = String::new();
+    for line in BufReader::new(response).lines() {
+        if sync.state.lock().unwrap().generation != generation {
+            return Ok(());
+        }
+        let line = line.map_err(|e| format!("Event stream interrupted: {e}"))?;
+        if let Some(chunk) = line.strip_prefix("data:") {
+            data.push_str(chunk.trim_start());
+            continue;
+        }
+        if !line.is_empty() || data.is_empty() {
+            continue;
+        }
+        let event: ServerEvent = serde_json::from_str(&std::mem::take(&mut data))
+            .map_err(|e| format!("Failed to parse event: {e}"))?;
+        if let EventKind::Created { clip } | EventKind::Updated { clip } = event.kind {
+            if clip.device_id.as_deref() != Some(&link.device_id) {
+                if let Some(incoming) = decrypt(&sync, &client, link, clip) {
+                    history::import(app, incoming);
+                }
+            }
+        }
+        let mut state = sync.state.lock().unwrap();
+        if state.generation != generation {
+            return Ok(());
+        }
+        if let Some(current) = state.link.as_mut() {
+            current.cursor = event.seq;
+            let current = current.clone();
+            drop(state);
+            if let Err(e) = sync.save(Some(&current)) {
+                log::warn!("{e}");
+            }
+        }
+    }
+    Err("Server closed the event stream".to_string())
+}
+
+/// 服务器上的条目转为本地条目；只同步文本和 HTML。
+/// 缺少密钥时先重新取回（可能刚被另一台设备批准），仍然解不开的条目跳过
+fn decrypt(sync: &Remote, client: &Client, link: &Link, clip: RemoteClip) -> Option<Incoming> {
+    if !matches!(clip.item_type.as_str(), "text" | "html") || clip.blob.is_some() {
+        return None;
+    }
+    let content = match clip.key_version {
+        None => clip.content,
+        Some(version) => {
+            let decrypt = || {
+                let state = sync.state.lock().unwrap();
+                let keyring = state.keyring.as_ref().ok_or("signed out")?;
+                keyring.decrypt(&clip.content, version)
+            };
+            let result = decrypt().or_else(|_| {
+                sync.sync_keys(client, link)?;
+                decrypt()
+            });
+            match result {
+                Ok(content) => content,
+                Err(e) => {
+                    sync.set_error(Some(format!("Cannot decrypt clip {}: {e}", clip.id)));
+                    return None;
+                }
+            }
+        }
+    };
+    Some(Incoming {
+        item_type: clip.item_type,
+        content,
+        timestamp: clip.timestamp,
+        device: clip.device.unwrap_or_default(),
+        device_id: clip.device_id,
+        username: clip.username.unwrap_or_else(|| link.username.clone()),
+        expires_at: clip.expires_at,
+        source_app: clip.source_app,
+    })
+}
+
+/// 当前的同步状态。已登录时先重新取回密钥，刚批准的设备和刚分享来的密钥都能看到
+#[tauri::command]
+pub async fn sync_status(app: AppHandle) -> Result<SyncStatus, String> {
+    blocking(app, |sync| {
+        let Some((link, _)) = sync.linked() else {
+            return Ok(sync.status(&[]));
+        };
+        let refreshed = Client::new(&link.server, Some(&link.token)).and_then(|client| {
+            sync.sync_keys(&client, &link)?;
+            client.devices()
+        });
+        match refreshed {
+            Ok(devices) => Ok(sync.status(&devices)),
+            Err(e) => {
+                sync.set_error(Some(e));
+                Ok(sync.status(&[]))
+            }
+        }
+    })
+    .await
+}
+
+/// 用密码登录服务器，把本机注册为带公钥的设备。用户已有密钥时，
+/// 要在另一台设备上核对本机的指纹并批准之后才能读写加密的条目
+#[tauri::command]
+pub async fn sync_login(
+    app: AppHandle,
+    server: String,
+    username: String,
+    password: String,
+    name: String,
+) -> Result<SyncStatus, String> {
+    blocking(app, move |sync| {
+        let server = server.trim().trim_end_matches('/').to_string();
+        let client = Client::new(&server, None)?;
+        let registered = client.register_device(
+            username.trim(),
+            &password,
+            name.trim(),
+            &sync.device.public_base64(),
+        )?;
+        // 重新登录时保留之前批准过的设备
+        let approved = sync
+            .linked()
+            .map(|(link, _)| link.approved)
+            .unwrap_or_default();
+        let link = Link {
+            server,
+            username: username.trim().to_string(),
+            device_id: registered.id,
+            device_name: registered.name,
+            token: registered.token,
+            approved,
+            cursor: 0,
+        };
+        log::info!(
+            "Signed in to {} as {} on device {}",
+            link.server,
+            link.username,
+            link.device_id
+        );
+        sync.relink(Some(link.clone()))?;
+        let client = Client::new(&link.server, Some(&link.token))?;
+        sync.sync_keys(&client, &link)?;
+        Ok(sync.status(&client.devices()?))
+    })
+    .await
+}
+
+/// 用户核对过指纹后批准另一台设备。`fingerprint` 是界面上显示、用户确认过的指纹，
+/// 服务器上的公钥在此期间被替换时不会批准
+#[tauri::command]
+pub async fn sync_approve(
+    app: AppHandle,
+    device_id: String,
+    fingerprint: String,
+) -> Result<SyncStatus, String> {
+    blocking(app, move |sync| {
+        let (mut link, _) = sync.linked().ok_or("Not signed in")?;
+        let client = Client::new(&link.server, Some(&link.token))?;
+        let devices = client.devices()?;
+        let device = devices
+            .iter()
+            .find(|device| device.id == device_id && device.revoked_at.is_none())
+            .ok_or_else(|| format!("No active device {device_id}"))?;
+        let public_key = device
+            .public_key
+            .as_deref()
+            .ok_or_else(|| format!("Device {} has no encryption key", device.name))?;
+        let actual = synapse_crypto::fingerprint(public_key).map_err(|e| e.to_string())?;
+        if !crypto::same_fingerprint(&fingerprint, &actual) {
+            return Err(format!(
+                "The fingerprint of {} changed to {actual}; it was not approved",
+                device.name
+            ));
+        }
+        let shared = {
+            let mut state = sync.state.lock().unwrap();
+            let keyring = state.keyring.as_mut().ok_or("Keys are not loaded yet")?;
+            keyring.approve(&client, &device.id, public_key)?
+        };
+        link.approved
+            .insert(device.id.clone(), public_key.to_string());
+        sync.save(Some(&link))?;
+        if let Some(current) = sync.state.lock().unwrap().link.as_mut() {
+            current.approved = link.approved.clone();
+        }
+        log::info!(
+            "Approved device {}; shared {shared} key version(s)",
+            device.name
+        );
+        Ok(sync.status(&devices))
+    })
+    .await
+}
+
+/// 退出登录：在服务器上撤销本机设备并删除保存的 token。本地历史和设备私钥保留
+#[tauri::command]
+pub async fn sync_logout(app: AppHandle) -> Result<SyncStatus, String> {
+    blocking(app, |sync| {
+        if let Some((link, _)) = sync.linked() {
+            let revoked = Client::new(&link.server, Some(&link.token))
+                .and_then(|client| client.revoke_device(&link.device_id));
+            if let Err(e) = revoked {
+                log::warn!("Failed to revoke device {}: {e}", link.device_id);
+            }
+        }
+        sync.relink(None)?;
+        Ok(sync.status(&[]))
+    })
+    .await
+}
+
+/// 在阻塞线程池中执行，网络请求不占用界面线程
+async fn blocking<T: Send + 'static>(
+    app: AppHandle,
+    f: impl FnOnce(&Remote) -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    tauri::async_runtime::spawn_blocking(move || f(&app.state::<Remote>()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// 上传的条目，字段与服务端的 ClipboardItem 相同；设备由 token 决定
+#[derive(Serialize)]
+struct Upload<'a> {
+    #[serde(rename = "type")]
+    item_type: &'a str,
+    content: String,
+    mime_type: &'a str,
+    timestamp: i64,
+    size: usize,
+    key_version: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_app: Option<&'a str>,
+}
+
+/// 服务器上的条目
+#[derive(Deserialize)]
+struct RemoteClip {
+    id: i64,
+    #[serde(rename = "type")]
+    item_type: String,
+    content: String,
+    timestamp: i64,
+    device: Option<String>,
+    device_id: Option<String>,
+    username: Option<String>,
+    key_version: Option<i64>,
+    expires_at: Option<i64>,
+    source_app: Option<String>,
+    blob: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ServerEvent {
+    seq: i64,
+    #[serde(flatten)]
+    kind: EventKind,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum EventKind {
+    Created {
+        clip: RemoteClip,
+    },
+    Updated {
+        clip: RemoteClip,
+    },
+    /// 删除和清空只作用于服务器上的历史，本地历史自己决定保留什么
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+pub struct RemoteDevice {
+    pub id: String,
+    pub name: String,
+    pub public_key: Option<String>,
+    pub revoked_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct RegisteredDevice {
+    id: String,
+    name: String,
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct WrappedKey {
+    pub version: i64,
+    pub wrapped_key: String,
+}
+
+/// 缺少某些密钥版本的设备，与后端 `GET /api/keys` 的 missing 相同
+#[derive(Deserialize)]
+pub struct Missing {
+    pub device_id: String,
+    pub public_key: String,
+    pub versions: Vec<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct KeyStatus {
+    pub current_version: Option<i64>,
+    pub rotation_needed: bool,
+    pub keys: Vec<WrappedKey>,
+    pub missing: Vec<Missing>,
+}
+
+/// 与后端 `POST /api/keys` 的 grants 相同
+#[derive(Serialize)]
+pub struct Grant {
+    pub version: i64,
+    pub device_id: String,
+    pub wrapped_key: String,
+}
+
+/// 服务器 REST API 的阻塞客户端，与 CLI 的 ApiClient 相同
+pub struct Client {
+    http: HttpClient,
+    base_url: String,
+}
+
+impl Client {
+    fn new(base_url: &str, token: Option<&str>) -> Result<Self, String> {
+        let mut headers = header::HeaderMap::new();
+        if let Some(token) = token {
+            let mut value = header::HeaderValue::from_str(&format!("Bearer {token}"))
+                .map_err(|e| e.to_string())?;
+            value.set_sensitive(true);
+            headers.insert(header::AUTHORIZATION, value);
+        }
+        let http = HttpClient::builder()
+            .default_headers(headers)
+            .connect_timeout(Duration::from_secs(10))
+            // 事件流一直保持打开
+            .timeout(None)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// 用密码登录，把本机注册为新设备并取得绑定到它的 token；会话只用于这一次注册
+    fn register_device(
+        &self,
+        username: &str,
+        password: &str,
+        name: &str,
+        public_key: &str,
+    ) -> Result<RegisteredDevice, String> {
+        let credentials = serde_json::json!({ "username": username, "password": password });
+        let response = check(
+            self.http
+                .post(self.url("/api/auth/login"))
+                .json(&credentials)
+                .send(),
+        )?;
+        let session = response
+            .headers()
+            .get(header::SET_COOKIE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .ok_or("Server did not return a session")?
+            .to_string();
+        let device = serde_json::json!({
+            "name": name,
+            "platform": std::env::consts::OS,
+            "public_key": public_key,
+            "issue_token": true,
+        });
+        let response = self
+            .http
+            .post(self.url("/api/devices"))
+            .header(header::COOKIE, &session)
+            .json(&device)
+            .send();
+        let registered = check(response)?.json().map_err(|e| e.to_string())?;
+        let _ = self
+            .http
+            .post(self.url("/api/auth/logout"))
+            .header(header::COOKIE, session)
+            .send();
+        Ok(registered)
+    }
+
+    pub fn devices(&self) -> Result<Vec<RemoteDevice>, String> {
+        check(self.http.get(self.url("/api/devices")).send())?
+            .json()
+            .map_err(|e| e.to_string())
+    }
+
+    fn revoke_device(&self, id: &str) -> Result<(), String> {
+        check(
+            self.http
+                .delete(self.url(&format!("/api/devices/{id}")))
+                .send(),
+        )?;
+        Ok(())
+    }
+
+    pub fn key_status(&self) -> Result<KeyStatus, String> {
+        check(self.http.get(self.url("/api/keys")).send())?
+            .json()
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn create_key_version(&self, version: i64, grants: &[Grant]) -> Result<(), String> {
+        let body = serde_json::json!({ "version": version, "grants": grants });
+        check(self.http.post(self.url("/api/keys")).json(&body).send())?;
+        Ok(())
+    }
+
+    pub fn add_grants(&self, grants: &[Grant]) -> Result<(), String> {
+        let body = serde_json::json!({ "grants": grants });
+        check(
+            self.http
+                .post(self.url("/api/keys/grants"))
+                .json(&body)
+                .send(),
+        )?;
+        Ok(())
+    }
+
+    /// 上传一个条目；密钥版本已经过时（409）时返回 false
+    fn create_clip(&self, item: &Upload) -> Result<bool, String> {
+        let response = self.http.post(self.url("/api/clips")).json(item).send();
+        match response {
+            Ok(response) if response.status() == StatusCode::CONFLICT => Ok(false),
+            response => check(response).map(|_| true),
+        }
+    }
+
+    /// 事件流，从 `cursor` 之后的事件开始；为 0 时先回放服务器上的全部历史
+    fn events(&self, cursor: i64) -> Result<Response, String> {
+        check(
+            self.http
+                .get(self.url(&format!("/api/events?cursor={cursor}")))
+                .send(),
+        )
+    }
+}
+
+fn check(response: reqwest::Result<Response>) -> Result<Response, String> {
+    let response = response.map_err(|e| format!("Failed to reach the Synapse server: {e}"))?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = response
+        .json::<serde_json::Value>()
+        .ok()
+        .and_then(|body| body.get("error")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| status.to_string());
+    Err(format!("Server returned {}: {message}", status.as_u16()))
+}
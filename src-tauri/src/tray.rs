@@ -0,0 +1,220 @@
+//! 托盘菜单：列出最近的条目（点击复制回剪贴板）、暂停记录（可定时恢复）和清空历史
+
+use crate::clipboard::LocalFile;
+use crate::history::{self, Clip, History};
+use std::{path::Path, sync::Mutex, thread, time::Duration};
+use tauri::{
+    image::Image,
+    menu::{Menu, MenuEvent, MenuId, MenuItem, PredefinedMenuItem},
+    tray::{MouseButton, TrayIcon, TrayIconBuilder, TrayIconEvent},
+    App, AppHandle, Manager, Wry,
+};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+
+const ID: &str = "main";
+/// 菜单中列出的最近条目数
+const RECENT_ITEMS: usize = 10;
+/// 菜单项中文本的最大长度
+const LABEL_CHARS: usize = 40;
+const TIMED_PAUSE: Duration = Duration::from_secs(15 * 60);
+
+// macOS uses monochrome (white) template images for menu bar icons
+// Other platforms (Windows/Linux) use colored icons
+#[cfg(target_os = "macos")]
+const ICON: &[u8] = include_bytes!("../icons/tray-icon-macos.png");
+#[cfg(not(target_os = "macos"))]
+const ICON: &[u8] = include_bytes!("../icons/tray-icon.png");
+
+/// 每次暂停或恢复时加一，让过时的定时恢复失效
+#[derive(Default)]
+pub struct PauseGeneration(Mutex<u64>);
+
+pub fn init(app: &App) -> tauri::Result<()> {
+    app.manage(PauseGeneration::default());
+    TrayIconBuilder::with_id(ID)
+        .icon(icon(false)?)
+        .tooltip("Synapse")
+        .menu(&menu(app.handle())?)
+        .on_menu_event(on_menu_event)
+        .on_tray_icon_event(|tray: &TrayIcon, event: TrayIconEvent| {
+            if let TrayIconEvent::Click {
+                button: MouseButton::Left,
+                ..
+            } = event
+            {
+                show_main(tray.app_handle());
+            }
+        })
+        .build(app)?;
+    Ok(())
+}
+
+/// 最近的条目或暂停状态变化后重建菜单
+pub fn refresh(app: &AppHandle) {
+    let Some(tray) = app.tray_by_id(ID) else {
+        return;
+    };
+    match menu(app) {
+        Ok(menu) => {
+            let _ = tray.set_menu(Some(menu));
+        }
+        Err(e) => log::warn!("Failed to rebuild tray menu: {e}"),
+    }
+}
+
+fn menu(app: &AppHandle) -> tauri::Result<Menu<Wry>> {
+    let menu = Menu::new(app)?;
+    let recent = app.state::<History>().recent(RECENT_ITEMS);
+    if recent.is_empty() {
+        menu.append(&item(app, "empty", "No clips yet", false)?)?;
+    }
+    for clip in &recent {
+        menu.append(&item(app, format!("clip:{}", clip.id), label(clip), true)?)?;
+    }
+    menu.append(&PredefinedMenuItem::separator(app)?)?;
+    if app.state::<History>().paused() {
+        menu.append(&item(app, "resume", "Resume capture", true)?)?;
+    } else {
+        menu.append(&item(app, "pause", "Pause capture", true)?)?;
+        menu.append(&item(app, "pause-timed", "Pause for 15 minutes", true)?)?;
+    }
+    menu.append(&item(app, "clear", "Clear history", true)?)?;
+    menu.append(&PredefinedMenuItem::separator(app)?)?;
+    menu.append(&item(app, "show", "Show", true)?)?;
+    menu.append(&item(app, "quit", "Quit", true)?)?;
+    Ok(menu)
+}
+
+fn item(
+    app: &AppHandle,
+    id: impl Into<MenuId>,
+    text: impl AsRef<str>,
+    enabled: bool,
+) -> tauri::Result<MenuItem<Wry>> {
+    MenuItem::with_id(app, id, text, enabled, None::<&str>)
+}
+
+fn on_menu_event(app: &AppHandle, event: MenuEvent) {
+    match event.id.as_ref() {
+        "quit" => app.exit(0),
+        "show" => show_main(app),
+        "pause" => set_paused(app, true, None),
+        "pause-timed" => set_paused(app, true, Some(TIMED_PAUSE)),
+        "resume" => set_paused(app, false, None),
+        "clear" => {
+            let app = app.clone();
+            app.dialog()
+                .message("Delete all clipboard history on this device?")
+                .title("Clear history")
+                .kind(MessageDialogKind::Warning)
+                .buttons(MessageDialogButtons::OkCancel)
+                .show(move |confirmed| {
+                    if confirmed {
+                        history::clear(&app);
+                    }
+                });
+        }
+        id => {
+            let id = id
+                .strip_prefix("clip:")
+                .and_then(|id| id.parse::<i64>().ok());
+            if let Some(id) = id {
+                if let Err(e) = history::copy(app, id) {
+                    log::warn!("Failed to copy clip from tray: {e}");
+                }
+            }
+        }
+    }
+}
+
+fn show_main(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.set_focus();
+        #[cfg(target_os = "macos")]
+        let _ = app.set_activation_policy(tauri::ActivationPolicy::Regular);
+    }
+}
+
+/// 暂停或恢复记录；`resume_after` 指定时间后自动恢复
+fn set_paused(app: &AppHandle, paused: bool, resume_after: Option<Duration>) {
+    let generation = {
+        let pause = app.state::<PauseGeneration>();
+        let mut generation = pause.0.lock().unwrap();
+        *generation += 1;
+        *generation
+    };
+    history::set_paused(app, paused);
+    if let Some(tray) = app.tray_by_id(ID) {
+        if let Ok(icon) = icon(paused) {
+            let _ = tray.set_icon(Some(icon));
+        }
+        let _ = tray.set_tooltip(Some(if paused {
+            "Synapse (paused)"
+        } else {
+            "Synapse"
+        }));
+    }
+    refresh(app);
+
+    if let Some(delay) = resume_after {
+        let app = app.clone();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            let current = *app.state::<PauseGeneration>().0.lock().unwrap();
+            if current == generation {
+                set_paused(&app, false, None);
+            }
+        });
+    }
+}
+
+/// 托盘图标；暂停时降低不透明度，看起来是灰掉的
+fn icon(paused: bool) -> tauri::Result<Image<'static>> {
+    let icon = Image::from_bytes(ICON)?;
+    if !paused {
+        return Ok(icon.to_owned());
+    }
+    let mut rgba = icon.rgba().to_vec();
+    for pixel in rgba.chunks_exact_mut(4) {
+        pixel[3] = (pixel[3] as u16 * 2 / 5) as u8;
+    }
+    Ok(Image::new_owned(rgba, icon.width(), icon.height()))
+}
+
+/// 菜单项文字：文本取第一行并截断，图片显示来源应用，文件显示文件名
+fn label(clip: &Clip) -> String {
+    let text = if clip.item_type == "image" {
+        match &clip.source_app {
+            Some(app) => format!("Image from {app}"),
+            None => "Image".to_string(),
+        }
+    } else {
+        let line = if clip.item_type == "file" {
+            let files: Vec<LocalFile> = serde_json::from_str(&clip.content).unwrap_or_default();
+            let names = files
+                .iter()
+                .map(|file| {
+                    Path::new(&file.path)
+                        .file_name()
+                        .map_or(file.path.clone(), |name| {
+                            name.to_string_lossy().into_owned()
+                        })
+                })
+                .collect::<Vec<_>>();
+            format!("{} files: {}", names.len(), names.join(", "))
+        } else {
+            clip.plain_text()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        if line.chars().count() > LABEL_CHARS {
+            format!("{}…", line.chars().take(LABEL_CHARS).collect::<String>())
+        } else {
+            line
+        }
+    };
+    // Windows 上 & 表示助记键
+    text.replace('&', "&&")
+}